@@ -0,0 +1,43 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tewta::prelude::*;
+
+const ITEMS: u32 = 100_000;
+
+/// An array of posts or followers, with its hashes cached as they would be after the account was published.
+fn array() -> SegmentedArray<u32, 32> {
+    let array = SegmentedArray::from((0..ITEMS).collect::<Vec<_>>());
+    array.hash();
+    array
+}
+
+fn benchmarks(c: &mut Criterion) {
+    c.bench_function("from_vec_and_hash_100k", |b| b.iter(|| {
+        SegmentedArray::<u32, 32>::from((0..ITEMS).collect::<Vec<_>>()).hash()
+    }));
+
+    let array = array();
+    c.bench_function("push_and_rehash_100k", |b| b.iter_batched_ref(|| array.clone(), |array| {
+        array.push(ITEMS).unwrap();
+        array.hash()
+    }, BatchSize::SmallInput));
+    c.bench_function("insert_near_end_and_rehash_100k", |b| b.iter_batched_ref(|| array.clone(), |array| {
+        array.insert(ITEMS as usize - 10, ITEMS).unwrap();
+        array.hash()
+    }, BatchSize::SmallInput));
+    c.bench_function("insert_at_start_and_rehash_100k", |b| b.iter_batched_ref(|| array.clone(), |array| {
+        array.insert(0, ITEMS).unwrap();
+        array.hash()
+    }, BatchSize::SmallInput));
+
+    let root = *array.hash();
+    c.bench_function("prove_and_verify_100k", |b| b.iter(|| {
+        let proof = array.prove(54_321).unwrap();
+        assert!(proof.verify(&root, &54_321u32));
+    }));
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
@@ -3,17 +3,20 @@
 
 use crate::prelude::*;
 
-#[derive(Debug, Hashable)]
+#[derive(Debug, Clone, PartialEq, Protocol, Hashable)]
 pub struct UserMention {
     pub username: String,
     pub peer_id: PeerID,
-    // TODO [#61]: Add timestamp in cache
     /// Cached internet address for that peer_id. Might have changed
     pub cached_addr: Option<String>,
     /// Cached addresses for providers of that peer_id. Might have changed
     pub providers_addrs: Vec<(PeerID, String)>,
+    /// When the cached addresses were looked up, in seconds since the Unix epoch.
+    /// Mentions of followed accounts are [refreshed](crate::node::Node::refresh_mentions) once older than [`NodeConfig::mention_max_age`](crate::node::NodeConfig::mention_max_age).
+    pub cached_at: u64,
 }
 
+#[derive(Debug, Clone, PartialEq, Protocol)]
 pub enum PropValue {
     // Simple types
     Bool(bool),
@@ -40,6 +43,46 @@ pub enum PropValue {
     Map(BTreeMap<String, PropValue>),
 }
 
+impl Hashable for PropValue {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        fn update_bools(values: &[bool], hasher: &mut impl Digest) {
+            values.len().update_hasher(hasher);
+            for value in values {
+                (*value as u8).update_hasher(hasher);
+            }
+        }
+        fn update_floats(values: &[f64], hasher: &mut impl Digest) {
+            values.len().update_hasher(hasher);
+            for value in values {
+                value.to_bits().update_hasher(hasher);
+            }
+        }
+
+        // Variants are distinguished by their index
+        match self {
+            PropValue::Bool(value) => { 0u8.update_hasher(hasher); (*value as u8).update_hasher(hasher) },
+            PropValue::Uint(value) => { 1u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::Int(value) => { 2u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::Float(value) => { 3u8.update_hasher(hasher); value.to_bits().update_hasher(hasher) },
+            PropValue::Date(value) => { 4u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::String(value) => { 5u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::User(value) => { 6u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::Blob(value) => { 7u8.update_hasher(hasher); value.update_hasher(hasher) },
+            PropValue::BoolArray(values) => { 8u8.update_hasher(hasher); update_bools(values, hasher) },
+            PropValue::UintArray(values) => { 9u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::IntArray(values) => { 10u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::FloatArray(values) => { 11u8.update_hasher(hasher); update_floats(values, hasher) },
+            PropValue::DateArray(values) => { 12u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::StringArray(values) => { 13u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::UserArray(values) => { 14u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::BlobArray(values) => { 15u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::Array(values) => { 16u8.update_hasher(hasher); values.update_hasher(hasher) },
+            PropValue::Map(values) => { 17u8.update_hasher(hasher); values.update_hasher(hasher) },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Protocol)]
 pub struct AccountData {
     pub username: String,
     pub followers: SegmentedArray<UserMention, 32>,
@@ -47,7 +90,7 @@ pub struct AccountData {
     pub following: SegmentedArray<UserMention, 32>,
     pub following_count: u32,
     
-    /// The immutable peerID of an eventual backup account.
+    /// The immutable peerID of an eventual backup account, which can sign a [`Migration`] to a new primary key.
     /// When updating account data, peers should check that this field matches exactly.
     /// [Learn more](https://github.com/Mubelotix/tewta/wiki/account-recovery)
     pub backup_peer_id: PeerID,
@@ -56,9 +99,171 @@ pub struct AccountData {
     /// Keys should be prefixed by the implementation name.
     /// [Learn more](https://github.com/Mubelotix/tewta/wiki/custom-account-properties)
     pub props: BTreeMap<String, PropValue>,
+
+    /// Posts of the account, oldest first.
+    /// Only new posts are appended, so old ones can be [pruned](SegmentedArray::retain_last) without changing the hash.
+    pub posts: SegmentedArray<SignedData<Post>, 32>,
+
+    /// Likes, replies and reshares of the account, oldest first.
+    pub interactions: SegmentedArray<SignedData<Interaction>, 32>,
 }
 
 /// An incomplete representation of an account.
+///
+/// Segments of the lists can be [unknown](Segment::Unknown), which makes snapshots of large accounts cheap to transfer.
+/// The hash of a snapshot is that of the complete account, so any snapshot can be checked against the [`AccountSnapshotDescriptor`].
+#[derive(Debug, Clone, PartialEq, Protocol)]
 pub struct AccountDataSnapshot {
+    pub username: String,
+    pub followers: SegmentedArray<UserMention, 32>,
+    pub follower_count: u32,
+    pub following: SegmentedArray<UserMention, 32>,
+    pub following_count: u32,
+    pub backup_peer_id: PeerID,
+    pub props: BTreeMap<String, PropValue>,
+    pub posts: SegmentedArray<SignedData<Post>, 32>,
+    pub interactions: SegmentedArray<SignedData<Interaction>, 32>,
+}
+
+impl AccountData {
+    /// A complete snapshot of the account.
+    pub fn snapshot(&self) -> AccountDataSnapshot {
+        AccountDataSnapshot {
+            username: self.username.clone(),
+            followers: self.followers.clone(),
+            follower_count: self.follower_count,
+            following: self.following.clone(),
+            following_count: self.following_count,
+            backup_peer_id: self.backup_peer_id.clone(),
+            props: self.props.clone(),
+            posts: self.posts.clone(),
+            interactions: self.interactions.clone(),
+        }
+    }
+}
+
+// Items are given back like SegmentedArray::push does
+#[allow(clippy::result_large_err)]
+impl AccountData {
+    /// Appends a post to the post log.
+    /// Posts should be signed by the owner of the account.
+    /// Gives the post back if the end of the log was pruned.
+    pub fn add_post(&mut self, post: SignedData<Post>) -> Result<(), SignedData<Post>> {
+        self.posts.push(post)
+    }
+
+    /// Appends an interaction.
+    /// Interactions should be signed by the owner of the account.
+    /// Gives the interaction back if the end of the list was pruned.
+    pub fn add_interaction(&mut self, interaction: SignedData<Interaction>) -> Result<(), SignedData<Interaction>> {
+        self.interactions.push(interaction)
+    }
+}
+
+impl AccountDataSnapshot {
+    /// Returns true if the followers and following lists are complete.
+    /// Posts and interactions are not considered, as old ones can be pruned from the account itself.
+    pub fn is_complete(&self) -> bool {
+        self.followers.is_complete() && self.following.is_complete()
+    }
+}
+
+/// Fails if the snapshot is not [complete](AccountDataSnapshot::is_complete), giving it back.
+impl TryFrom<AccountDataSnapshot> for AccountData {
+    type Error = AccountDataSnapshot;
+
+    fn try_from(snapshot: AccountDataSnapshot) -> Result<Self, Self::Error> {
+        if !snapshot.is_complete() {
+            return Err(snapshot);
+        }
+        Ok(AccountData {
+            username: snapshot.username,
+            followers: snapshot.followers,
+            follower_count: snapshot.follower_count,
+            following: snapshot.following,
+            following_count: snapshot.following_count,
+            backup_peer_id: snapshot.backup_peer_id,
+            props: snapshot.props,
+            posts: snapshot.posts,
+            interactions: snapshot.interactions,
+        })
+    }
+}
+
+/// Lists contribute their root hash, so that unknown segments don't change the hash.
+macro_rules! impl_account_hashable {
+    ($ty:ty) => {
+        impl Hashable for $ty {
+            fn update_hasher(&self, hasher: &mut impl Digest) {
+                self.username.update_hasher(hasher);
+                hasher.update(self.followers.hash().as_slice());
+                self.follower_count.update_hasher(hasher);
+                hasher.update(self.following.hash().as_slice());
+                self.following_count.update_hasher(hasher);
+                self.backup_peer_id.update_hasher(hasher);
+                self.props.update_hasher(hasher);
+                hasher.update(self.posts.hash().as_slice());
+                hasher.update(self.interactions.hash().as_slice());
+            }
+        }
+    };
+}
+impl_account_hashable!(AccountData);
+impl_account_hashable!(AccountDataSnapshot);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id(i: u8) -> PeerID {
+        format!("{:064x}", i).parse().unwrap()
+    }
+
+    #[test]
+    fn snapshot_hash() {
+        let followers: Vec<UserMention> = (0..100).map(|i| UserMention {
+            username: format!("user{i}"),
+            peer_id: peer_id(i),
+            cached_addr: None,
+            providers_addrs: Vec::new(),
+            cached_at: 0,
+        }).collect();
+        let mut props = BTreeMap::new();
+        props.insert(String::from("tewta:bio"), PropValue::String(String::from("Hello")));
+        props.insert(String::from("tewta:ratios"), PropValue::Array(vec![PropValue::Float(0.5), PropValue::BoolArray(vec![true])]));
+        let account = AccountData {
+            username: String::from("alice"),
+            followers: SegmentedArray::from(followers),
+            follower_count: 100,
+            following: SegmentedArray::from(Vec::new()),
+            following_count: 0,
+            backup_peer_id: peer_id(200),
+            props,
+            posts: SegmentedArray::from(Vec::new()),
+            interactions: SegmentedArray::from(Vec::new()),
+        };
+
+        let bytes = account.raw_bytes(&PROTOCOL_SETTINGS).unwrap();
+        assert_eq!(AccountData::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).unwrap(), account);
+
+        // Partial snapshots have the hash of the account
+        let desc = AccountSnapshotDescriptor::new(1, &account.snapshot());
+        assert_eq!(desc.hash, *Hash::hash(&account));
+        let mut snapshot = account.snapshot();
+        snapshot.followers.remove_segment(1);
+        assert!(!snapshot.is_complete());
+        assert!(desc.matches(&snapshot));
+        let bytes = snapshot.raw_bytes(&PROTOCOL_SETTINGS).unwrap();
+        let snapshot = AccountDataSnapshot::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).unwrap();
+        assert!(desc.matches(&snapshot));
+        let mut snapshot = AccountData::try_from(snapshot).unwrap_err();
 
+        // Any change is detected
+        snapshot.follower_count = 99;
+        assert!(!desc.matches(&snapshot));
+        let mut snapshot = account.snapshot();
+        snapshot.props.insert(String::from("tewta:bio"), PropValue::String(String::from("Hi")));
+        assert!(!desc.matches(&snapshot));
+        assert_eq!(AccountData::try_from(account.snapshot()).unwrap(), account);
+    }
 }
@@ -0,0 +1,27 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// Tells an account that someone started or stopped following it, so that it can update its [followers](AccountData::followers).
+/// Signed by the follower.
+#[derive(Debug, Clone, PartialEq, Protocol)]
+pub struct FollowRecord {
+    /// The follower, as it should appear in the followers list
+    pub follower: UserMention,
+    pub followed: PeerID,
+    /// `false` when unfollowing
+    pub follows: bool,
+    /// Timestamp of the version of the follower account that includes the change.
+    /// Records that are not newer than the last one we got from the same follower are replays.
+    pub timestamp: u64,
+}
+
+impl Hashable for FollowRecord {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.follower.update_hasher(hasher);
+        self.followed.update_hasher(hasher);
+        (self.follows as u8).update_hasher(hasher);
+        self.timestamp.update_hasher(hasher);
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Protocol, serde::Serialize)]
+pub enum InteractionKind {
+    Like,
+    /// The reply is a post of the actor, with [`Post::reply_to`] set to the target
+    Reply { hash: [u8; 32] },
+    Reshare,
+}
+
+/// Something an account did with a post, signed by the actor and stored in its [account](AccountData::interactions).
+/// Actors also announce themselves in the DHT under the [key of the post](PostRef::interactions_key), so that interactions can be found from the post.
+#[derive(Debug, Clone, PartialEq, Protocol, serde::Serialize)]
+pub struct Interaction {
+    pub kind: InteractionKind,
+    pub target: PostRef,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl Hashable for InteractionKind {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        match self {
+            InteractionKind::Like => 0u8.update_hasher(hasher),
+            InteractionKind::Reply { hash } => {
+                1u8.update_hasher(hasher);
+                hasher.update(hash);
+            }
+            InteractionKind::Reshare => 2u8.update_hasher(hasher),
+        }
+    }
+}
+
+impl Hashable for Interaction {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.kind.update_hasher(hasher);
+        self.target.update_hasher(hasher);
+        self.timestamp.update_hasher(hasher);
+    }
+}
+
+impl Interaction {
+    /// An interaction with a post, dated now.
+    pub fn new(kind: InteractionKind, target: PostRef) -> Interaction {
+        Interaction {
+            kind,
+            target,
+            timestamp: unix_timestamp(),
+        }
+    }
+}
+
+impl PostRef {
+    /// The DHT key under which accounts that interacted with the post announce themselves.
+    pub fn interactions_key(&self) -> KeyID {
+        let mut hasher = Sha256::new();
+        hasher.update(b"tewta-interactions");
+        hasher.update(self.author.bytes());
+        hasher.update(self.hash);
+        KeyID::from(<[u8; 32]>::from(hasher.finalize()))
+    }
+}
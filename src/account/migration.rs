@@ -0,0 +1,33 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// Moves an account to a new primary key, for instance after the previous one was compromised.
+/// Only valid when signed by the [backup account](AccountData::backup_peer_id) of the account being moved.
+#[derive(Debug, Clone, PartialEq, Protocol, serde::Serialize)]
+pub struct Migration {
+    pub from: PeerID,
+    pub to: PeerID,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl Hashable for Migration {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.from.update_hasher(hasher);
+        self.to.update_hasher(hasher);
+        self.timestamp.update_hasher(hasher);
+    }
+}
+
+impl Migration {
+    /// A migration, dated now.
+    pub fn new(from: PeerID, to: PeerID) -> Migration {
+        Migration {
+            from,
+            to,
+            timestamp: unix_timestamp(),
+        }
+    }
+}
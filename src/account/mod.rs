@@ -5,3 +5,11 @@ pub mod snapshot;
 pub use snapshot::*;
 pub mod account;
 pub use account::*;
+pub mod post;
+pub use post::*;
+pub mod interaction;
+pub use interaction::*;
+pub mod migration;
+pub use migration::*;
+pub mod follow;
+pub use follow::*;
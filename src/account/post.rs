@@ -0,0 +1,65 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// Points to a post of another account.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Protocol, serde::Serialize)]
+pub struct PostRef {
+    pub author: PeerID,
+    /// Hash of the [`Post`], without its signature
+    pub hash: [u8; 32],
+}
+
+/// Content published by an account, signed by its author and stored in its [post log](AccountData::posts).
+#[derive(Debug, Clone, PartialEq, Protocol, serde::Serialize)]
+pub struct Post {
+    pub text: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Hashes of media blobs, to be downloaded separately
+    pub media: Vec<[u8; 32]>,
+    pub reply_to: Option<PostRef>,
+    pub quote: Option<PostRef>,
+}
+
+impl Hashable for PostRef {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.author.update_hasher(hasher);
+        hasher.update(self.hash);
+    }
+}
+
+impl Hashable for Post {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.text.update_hasher(hasher);
+        self.timestamp.update_hasher(hasher);
+        self.media.len().update_hasher(hasher);
+        for hash in &self.media {
+            hasher.update(hash);
+        }
+        self.reply_to.update_hasher(hasher);
+        self.quote.update_hasher(hasher);
+    }
+}
+
+impl Post {
+    /// A text post, dated now.
+    pub fn new(text: impl Into<String>) -> Post {
+        Post {
+            text: text.into(),
+            timestamp: unix_timestamp(),
+            media: Vec::new(),
+            reply_to: None,
+            quote: None,
+        }
+    }
+
+    /// The reference other posts use to reply to or quote this one.
+    pub fn reference(&self, author: PeerID) -> PostRef {
+        PostRef {
+            author,
+            hash: *Hash::hash(self),
+        }
+    }
+}
@@ -3,8 +3,31 @@
 
 use crate::prelude::*;
 
-#[derive(Debug, Clone, Protocol)]
+/// Describes the latest version of an account, and is signed by its owner.
+#[derive(Debug, Clone, PartialEq, Protocol, serde::Serialize)]
 pub struct AccountSnapshotDescriptor {
+    /// Newer descriptors replace older ones
     pub timestamp: u64,
-    pub hash: Vec<u8>,
+    /// Root hash of the [`AccountDataSnapshot`], which is the same for all snapshots of a given account version
+    pub hash: [u8; 32],
+    /// Copied from the account so that peers storing descriptors can check it never changes
+    pub backup_peer_id: PeerID,
+    /// Size of the complete snapshot in bytes, so that peers can decide whether to download it
+    pub size: u64,
+}
+
+impl AccountSnapshotDescriptor {
+    pub fn new(timestamp: u64, snapshot: &AccountDataSnapshot) -> AccountSnapshotDescriptor {
+        AccountSnapshotDescriptor {
+            timestamp,
+            hash: *Hash::hash(snapshot),
+            backup_peer_id: snapshot.backup_peer_id.clone(),
+            size: snapshot.raw_bytes(&PROTOCOL_SETTINGS).map(|bytes| bytes.len() as u64).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Checks that a snapshot is a view of the account version described.
+    pub fn matches(&self, snapshot: &AccountDataSnapshot) -> bool {
+        *Hash::hash(snapshot) == self.hash && snapshot.backup_peer_id == self.backup_peer_id
+    }
 }
@@ -0,0 +1,32 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! Controls a running node through its [local API](tewta::rpc).
+
+use structopt::StructOpt;
+use tewta::{commands::Command, rpc::{call, RpcEndpoint}};
+
+#[derive(StructOpt)]
+#[structopt(name = "tewtactl")]
+struct Args {
+    /// Unix socket path or loopback TCP address of the node
+    #[structopt(short, long)]
+    endpoint: Option<RpcEndpoint>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    // The doc of `Command` would be used otherwise
+    let about = "Controls a running node through its local API";
+    let args = Args::from_clap(&Args::clap().about(about).long_about(about).get_matches());
+    let endpoint = args.endpoint.unwrap_or_default();
+    match call(&endpoint, &args.command).await {
+        Ok(output) => println!("{}", serde_json::to_string_pretty(&output).expect("values are always serializable")),
+        Err(e) => {
+            eprintln!("{endpoint}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+/// Returns the number of seconds since the Unix epoch, which nodes use to date everything they sign.
+///
+/// This is the system clock, except while testing, where the simulated clock is used so that simulations can be replayed.
+pub fn unix_timestamp() -> u64 {
+    #[cfg(not(feature = "test"))]
+    return std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    #[cfg(feature = "test")]
+    return testing::unix_timestamp();
+}
+
+/// While testing, time is read from the paused Tokio clock.
+/// Each simulation runs on its own thread, so they all start at the same date.
+#[cfg(feature = "test")]
+pub mod testing {
+    use tokio::time::Instant;
+
+    /// Date of the first timestamp read in a simulation.
+    pub const SIMULATION_EPOCH: u64 = 1_600_000_000;
+
+    thread_local!(
+        static SIMULATION_START: Instant = Instant::now();
+    );
+
+    pub fn unix_timestamp() -> u64 {
+        let elapsed = SIMULATION_START.with(|start| start.elapsed());
+        SIMULATION_EPOCH + elapsed.as_secs()
+    }
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn follows_simulation_clock() {
+        let start = unix_timestamp();
+        assert_eq!(start, testing::SIMULATION_EPOCH);
+        tokio::time::sleep(std::time::Duration::from_secs(90)).await;
+        assert_eq!(unix_timestamp(), start + 90);
+    }
+}
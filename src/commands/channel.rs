@@ -2,18 +2,50 @@
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
 use super::*;
+use tokio::sync::oneshot;
+
+type CommandRequest = (Command, oneshot::Sender<CommandOutput>);
 
 pub struct CommandReceiver {
-    receiver: async_channel::Receiver<Command>,
+    receiver: async_channel::Receiver<CommandRequest>,
+}
+
+/// Sends commands to a node and gets their output back.
+#[derive(Clone)]
+pub struct CommandSender {
+    sender: async_channel::Sender<CommandRequest>,
+}
+
+/// Used by the node to reply to a command once it is complete.
+pub struct CommandReplier {
+    sender: oneshot::Sender<CommandOutput>,
 }
 
 impl CommandReceiver {
-    pub fn new() -> (CommandReceiver, async_channel::Sender<Command>) {
+    pub fn new() -> (CommandReceiver, CommandSender) {
         let (sender, receiver) = async_channel::unbounded();
-        (CommandReceiver { receiver }, sender)
+        (CommandReceiver { receiver }, CommandSender { sender })
     }
 
-    pub async fn wait_command(&self) -> Command {
-        self.receiver.recv().await.unwrap()
+    pub async fn wait_command(&self) -> (Command, CommandReplier) {
+        let (command, sender) = self.receiver.recv().await.unwrap();
+        (command, CommandReplier { sender })
+    }
+}
+
+impl CommandSender {
+    /// Sends a command and waits for it to complete.
+    /// Returns `None` if the node stopped before replying.
+    pub async fn run(&self, command: Command) -> Option<CommandOutput> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender.send((command, sender)).await.ok()?;
+        receiver.await.ok()
+    }
+}
+
+impl CommandReplier {
+    pub fn reply(self, output: CommandOutput) {
+        // The sender might not be waiting anymore, which is fine
+        let _ = self.sender.send(output);
     }
 }
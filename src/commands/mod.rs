@@ -7,6 +7,8 @@ mod errors;
 pub use errors::*;
 mod parsing;
 pub use parsing::*;
+mod output;
+pub use output::*;
 
 use structopt::*;
 
@@ -15,7 +17,8 @@ use structopt::*;
 /// When testing, a thousand nodes are running.
 /// Prefix the command by the IDs of the node you want to send that command to.
 /// For instance, `2-5,7 ping` will send the command `ping` to nodes 2, 3, 4, 5 and 7.
-#[derive(StructOpt, Debug, Clone)]
+#[derive(StructOpt, Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "kebab-case")]
 pub enum Command {
     Conns,
     Buckets,
@@ -30,25 +33,60 @@ pub enum Command {
         key: crate::peers::KeyID,
     },
     Id,
-    Add {
-        #[structopt(short)]
-        interactive: bool,
-        #[structopt(short)]
-        patch: bool,
-        #[structopt(long)]
-        files: Vec<String>,
+    /// Connects to a node at an address
+    Connect {
+        addr: String,
     },
-    Fetch {
-        #[structopt(long)]
-        dry_run: bool,
+    Disconnect {
+        peer_id: crate::peers::PeerID,
+    },
+    /// Stores a value signed by us under a key, in our own DHT store
+    Store {
+        key: crate::peers::KeyID,
+    },
+    /// Looks for the address of a peer
+    FindPeer {
+        peer_id: crate::peers::PeerID,
+    },
+    /// Lists the content of our DHT store
+    DhtDump,
+    Peers {
+        /// Show addresses, pings and buckets
+        #[structopt(short, long)]
+        verbose: bool,
+    },
+    /// Looks for peers to fill a bucket
+    Discover {
+        bucket_level: usize,
+        /// 0, 1 or 2 for buckets A, B and C
+        bucket_id: usize,
+    },
+    /// Shows our metrics in the Prometheus text format
+    Metrics,
+    /// Shows posts of the accounts we follow and the posts they interacted with, newest first
+    Timeline {
+        /// Continue after this entry, as given at the end of the previous page
         #[structopt(long)]
-        all: bool,
-        repository: Option<String>,
+        cursor: Option<crate::node::TimelineCursor>,
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Publishes our account under a username, creating it with ourselves as the backup key if needed
+    Publish {
+        username: String,
+    },
+    /// Appends a post to our account
+    Post {
+        text: String,
+    },
+    /// Adds an account to our following list and lets it know
+    Follow {
+        peer_id: crate::peers::PeerID,
     },
-    Commit {
-        #[structopt(short)]
-        message: Option<String>,
-        #[structopt(short)]
-        all: bool,
+    /// Downloads the latest version of an account
+    FetchAccount {
+        peer_id: crate::peers::PeerID,
     },
+    /// Disconnects from all peers and stops the node, which then refuses connections and no longer runs its periodic tasks
+    Quit,
 }
@@ -0,0 +1,151 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::{peers::*, account::*, node::{DhtValue, MetricsSnapshot, TimelinePage, TimelineReason}};
+use std::time::Duration;
+
+/// The result of a [`Command`](super::Command) executed by a node.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "kebab-case")]
+pub enum CommandOutput {
+    /// The command completed and has nothing to report.
+    Done,
+    Conns {
+        count: usize,
+    },
+    /// Non-empty buckets, as `(bucket_level, bucket_id, peers)`
+    Buckets(Vec<(usize, usize, Vec<PeerID>)>),
+    /// Round-trip time of the ping, or `None` if it timed out.
+    Ping(Option<Duration>),
+    Id(PeerID),
+    /// Values found by a DHT lookup, or `None` if the lookup failed.
+    Find(Option<Vec<DhtValue>>),
+    Connected(PeerID),
+    /// Address of the peer, or `None` if it could not be found.
+    FindPeer(Option<String>),
+    DhtDump(Vec<(KeyID, Vec<DhtValue>)>),
+    Peers(Vec<PeerInfo>),
+    Metrics(Box<MetricsSnapshot>),
+    Timeline(TimelinePage),
+    /// Descriptor of the version of our account that was just published
+    Published(AccountSnapshotDescriptor),
+    Posted(PostRef),
+    Account(Box<AccountInfo>),
+    /// The command could not be completed.
+    Error(String),
+}
+
+/// What we know about a peer we are connected to.
+/// Details are only filled when requested.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PeerInfo {
+    pub peer_id: PeerID,
+    pub addr: Option<String>,
+    pub ping: Option<Duration>,
+    /// `(bucket_level, bucket_id)`
+    pub bucket: Option<(usize, usize)>,
+}
+
+/// An account downloaded from the network.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AccountInfo {
+    pub descriptor: AccountSnapshotDescriptor,
+    pub username: String,
+    pub follower_count: u32,
+    pub following_count: u32,
+    pub backup_peer_id: PeerID,
+    /// Posts whose signature is valid, oldest first
+    pub posts: Vec<Post>,
+}
+
+impl std::fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommandOutput::Done => write!(f, "Done"),
+            CommandOutput::Conns { count } => write!(f, "{count} connections"),
+            CommandOutput::Buckets(buckets) if buckets.is_empty() => write!(f, "No buckets"),
+            CommandOutput::Buckets(buckets) => {
+                write!(f, "Buckets:")?;
+                for (bucket_level, bucket_id, peers) in buckets {
+                    write!(f, "\n{}-{} ({}): ", bucket_level, ['A', 'B', 'C'][*bucket_id], peers.len())?;
+                    for peer in peers {
+                        write!(f, "{}, ", peer)?;
+                    }
+                }
+                Ok(())
+            }
+            CommandOutput::Ping(Some(d)) => write!(f, "Ping is {} ms", d.as_millis()),
+            CommandOutput::Ping(None) => write!(f, "Timed out"),
+            CommandOutput::Id(peer_id) => write!(f, "{peer_id}"),
+            CommandOutput::Find(Some(values)) => write!(f, "Found {} values", values.len()),
+            CommandOutput::Find(None) => write!(f, "Not found"),
+            CommandOutput::Connected(peer_id) => write!(f, "Connected to {peer_id}"),
+            CommandOutput::FindPeer(Some(addr)) => write!(f, "Found at {addr}"),
+            CommandOutput::FindPeer(None) => write!(f, "Not found"),
+            CommandOutput::DhtDump(entries) if entries.is_empty() => write!(f, "DHT store is empty"),
+            CommandOutput::DhtDump(entries) => {
+                write!(f, "DHT store:")?;
+                for (key, values) in entries {
+                    write!(f, "\n{key}: {} values", values.len())?;
+                    for value in values {
+                        if let Some(addr) = &value.cached_addr {
+                            write!(f, ", {addr}")?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            CommandOutput::Peers(peers) if peers.is_empty() => write!(f, "No peers"),
+            CommandOutput::Peers(peers) => {
+                write!(f, "{} peers:", peers.len())?;
+                for peer in peers {
+                    write!(f, "\n{}", peer.peer_id)?;
+                    if let Some(addr) = &peer.addr {
+                        write!(f, " {addr}")?;
+                    }
+                    if let Some((bucket_level, bucket_id)) = peer.bucket {
+                        write!(f, " {}-{}", bucket_level, ['A', 'B', 'C'][bucket_id])?;
+                    }
+                    if let Some(ping) = peer.ping {
+                        write!(f, " {} ms", ping.as_millis())?;
+                    }
+                }
+                Ok(())
+            }
+            CommandOutput::Metrics(metrics) => write!(f, "{}", metrics.to_prometheus().trim_end()),
+            CommandOutput::Timeline(page) if page.entries.is_empty() => write!(f, "Timeline is empty"),
+            CommandOutput::Timeline(page) => {
+                write!(f, "Timeline:")?;
+                for entry in &page.entries {
+                    write!(f, "\n[{}] {}: {}", entry.timestamp, entry.reference.author, entry.post.text)?;
+                    match &entry.reason {
+                        TimelineReason::Posted => (),
+                        TimelineReason::Liked(by) => write!(f, " (liked by {by})")?,
+                        TimelineReason::Replied(by) => write!(f, " (replied by {by})")?,
+                        TimelineReason::Reshared(by) => write!(f, " (reshared by {by})")?,
+                    }
+                }
+                if let Some(next) = &page.next {
+                    write!(f, "\nNext page: --cursor {next}")?;
+                }
+                Ok(())
+            }
+            CommandOutput::Published(desc) => write!(f, "Published version {}", desc.timestamp),
+            CommandOutput::Posted(reference) => {
+                write!(f, "Posted ")?;
+                for byte in reference.hash {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Account(account) => {
+                write!(f, "{} ({} followers, {} following)", account.username, account.follower_count, account.following_count)?;
+                for post in &account.posts {
+                    write!(f, "\n[{}] {}", post.timestamp, post.text)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Error(e) => write!(f, "Error: {e}"),
+        }
+    }
+}
@@ -17,6 +17,16 @@ impl Command {
     }
 }
 
+/// Parses a standalone destinator sequence such as `2-5,7`.
+pub fn parse_destinators(input: &str) -> Result<Vec<usize>, CommandParsingError> {
+    let input = format!("{} ", input.trim());
+    let (rest, destinators) = read_destinators(input.as_bytes())?;
+    if !rest.is_empty() {
+        return Err(CommandParsingError::Prefix("Unexpected characters after destinators"));
+    }
+    Ok(destinators)
+}
+
 enum DestinatorItem {
     Single(usize),
     Range(usize, usize),
@@ -24,10 +34,7 @@ enum DestinatorItem {
 
 fn read_number(input: &[u8]) -> Result<(&[u8], usize), &'static str> {
     let mut i = 0;
-    loop {
-        if !input[i].is_ascii_digit() {
-            break;
-        }
+    while i < input.len() && input[i].is_ascii_digit() {
         i += 1;
     }
 
@@ -17,42 +17,61 @@ pub mod prelude;
 pub mod account;
 pub mod segmented_array;
 pub mod hash;
+pub mod random;
+pub mod clock;
+pub mod rpc;
+pub mod prometheus;
+#[cfg(feature = "test")]
+pub mod simulation;
 
 use prelude::*;
-
 #[cfg(feature = "test")]
+use stream::testing::{LinkModel, LinkConditions};
+
+/// Number of simulated nodes, that new nodes bootstrap from. Stays zero outside of simulations.
 pub static mut NODE_COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
 #[cfg(feature = "test")]
-pub static mut RUNNING_COMMAND_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
-#[cfg(feature = "test")]
 lazy_static::lazy_static!(
     pub static ref LISTENERS: Arc<Mutex<Vec<Sender<TcpStream>>>> = Arc::new(Mutex::new(Vec::new()));
+    /// Conditions of the simulated links between nodes
+    pub static ref LINK_MODEL: std::sync::RwLock<Box<dyn LinkModel>> = std::sync::RwLock::new(Box::new(LinkConditions::default()));
 );
 
-// TODO [#1]: error handling
 #[cfg(feature = "test")]
-pub async fn connect(addr: String) -> Option<TcpStream> {
+pub(crate) fn local_addr_index(addr: &str) -> usize {
     if !addr.starts_with("local-") {
         panic!("Only local-* addresses are supported for testing");
     }
-    
-    let (our_stream, their_stream) = TcpStream::new();
+    addr[6..].parse::<usize>().unwrap()
+}
+
+// TODO [#1]: error handling
+#[cfg(feature = "test")]
+pub async fn connect(our_addr: &str, addr: String) -> Option<TcpStream> {
+    let from = local_addr_index(our_addr);
+    let to = local_addr_index(&addr);
+    if simulation::are_partitioned(from, to) {
+        return None;
+    }
+    let conditions = LINK_MODEL.read().unwrap().conditions(from, to);
+    let (our_stream, their_stream) = TcpStream::new(conditions, (from, to));
 
     let listeners = LISTENERS.lock().await;
-    let sender = match listeners.get(addr[6..].parse::<usize>().unwrap()) {
+    let sender = match listeners.get(to) {
         Some(s) => s,
         None => return None,
     };
 
+    // The send fails if the node is dead
     match sender.send(their_stream).await {
         Ok(_) => Some(our_stream),
-        Err(e) => panic!("{}", e),
+        Err(_) => None,
     }
 }
 
 #[cfg(not(feature = "test"))]
-pub async fn connect(_addr: String) -> Option<TcpStream> {
-    unimplemented!()
+pub async fn connect(_our_addr: &str, addr: String) -> Option<TcpStream> {
+    TcpStream::connect(addr).await.ok()
 }
 
 pub mod constants {
@@ -63,6 +82,15 @@ pub mod constants {
     pub const MAX_DHT_PEERS_RETURNED: u16 = 32;
     pub const KADEMLIA_BUCKET_SIZE: usize = 8;
     pub const KADEMLIA_ALPHA: usize = 3;
+    pub const MAX_MIGRATION_HOPS: usize = 8;
+    /// Peers we disconnect from on request are refused for that long
+    pub const REQUESTED_DISCONNECT_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(600);
+    /// Accounts found not to have migrated are looked up again after that long
+    pub const MIGRATION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+    /// Connections are dropped if the handshake takes longer
+    pub const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(40);
+    /// Signed records dated further than that in the future are refused
+    pub const MAX_CLOCK_DRIFT: std::time::Duration = std::time::Duration::from_secs(300);
     #[cfg(feature = "test")]
     pub const RSA_KEY_LENGHT: usize = 1024;
     #[cfg(not(feature = "test"))]
@@ -1,6 +1,24 @@
 // Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
+//! Structured logging built on [`tracing`].
+//!
+//! Events are emitted within spans that identify where they come from:
+//!
+//! - `node{addr, peer_id}` wraps everything a node does,
+//! - `peer{remote}` wraps the handling of packets received from a peer,
+//! - `packet{kind, request_id}` wraps the handling of a single packet,
+//! - `request{remote, request_id}` wraps requests we send and the wait for their response.
+//!
+//! Output is filtered by the `RUST_LOG` environment variable, which can select nodes through span fields:
+//! `RUST_LOG='error,[node{addr=local-3}]=trace'` prints errors from everyone and everything from node 3.
+//!
+//! Context spans are created at the error level, so that they are enabled as soon as any event is.
+//! As filters only consider entered spans, tasks running in a child span must also be instrumented with the node span.
+//! Each node additionally has a [`LogLevel`] that can be lowered at runtime to silence it.
+
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry};
+
 #[derive(Clone)]
 pub struct LogLevel {
     value: std::sync::Arc<std::sync::atomic::AtomicU8>,
@@ -12,7 +30,7 @@ impl LogLevel {
             value: std::sync::Arc::new(std::sync::atomic::AtomicU8::new(value)),
         }
     }
-    
+
     pub fn load(&self) -> u8 {
         self.value.load(std::sync::atomic::Ordering::Relaxed)
     }
@@ -22,11 +40,50 @@ impl LogLevel {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            s => Err(format!("Unknown log format {s}, expected text or json")),
+        }
+    }
+}
+
+/// Installs the global subscriber, printing logs to stderr.
+/// Only errors are printed when `RUST_LOG` is not set.
+///
+/// Does nothing if a subscriber is already installed.
+pub fn init(format: LogFormat) {
+    init_with(format, None)
+}
+
+/// Same as [`init`], with an additional layer receiving the same events.
+pub fn init_with(format: LogFormat, extra: Option<Box<dyn Layer<Registry> + Send + Sync>>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let stderr = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(std::io::stderr).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().with_writer(std::io::stderr).boxed(),
+    };
+    let _ = Registry::default().with(extra).with(stderr).with(filter).try_init();
+}
+
 #[macro_export]
 macro_rules! error {
     ($i:expr, $($arg:expr),+) => {
         if $i.load() >= 1 {
-            log::error!($($arg,)+);
+            tracing::error!($($arg,)+);
         }
     }
 }
@@ -35,7 +92,7 @@ macro_rules! error {
 macro_rules! warn {
     ($i:expr, $($arg:expr),+) => {
         if $i.load() >= 2 {
-            log::warn!($($arg,)+);
+            tracing::warn!($($arg,)+);
         }
     }
 }
@@ -44,7 +101,7 @@ macro_rules! warn {
 macro_rules! info {
     ($i:expr, $($arg:expr),+) => {
         if $i.load() >= 3 {
-            log::info!($($arg,)+);
+            tracing::info!($($arg,)+);
         }
     }
 }
@@ -53,7 +110,7 @@ macro_rules! info {
 macro_rules! debug {
     ($i:expr, $($arg:expr),+) => {
         if $i.load() >= 4 {
-            log::debug!($($arg,)+);
+            tracing::debug!($($arg,)+);
         }
     }
 }
@@ -62,8 +119,7 @@ macro_rules! debug {
 macro_rules! trace {
     ($i:expr, $($arg:expr),+) => {
         if $i.load() >= 5 {
-            log::trace!($($arg,)+);
+            tracing::trace!($($arg,)+);
         }
     }
 }
-
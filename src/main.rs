@@ -1,10 +1,96 @@
 // Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
-#[allow(unused_imports)]
-use tewta::{stream::*, commands::*, node::*, packets::*, peers::*, util::*, logging::*, *};
+//! Runs a node, controlled through its [local API](tewta::rpc) with `tewtactl`.
+
+use std::{net::SocketAddr, sync::Arc};
+use structopt::StructOpt;
+use tewta::{commands::*, logging::{self, LogFormat}, node::*, prometheus, rpc::{self, RpcEndpoint}};
+
+/// Runs a node until it is told to quit.
+///
+/// Logs are filtered by RUST_LOG.
+#[derive(StructOpt)]
+#[structopt(name = "tewta")]
+struct Args {
+    /// Address other nodes connect to
+    #[structopt(short, long, default_value = "0.0.0.0:7422")]
+    listen: SocketAddr,
+    /// Addresses of nodes to connect to on startup
+    #[structopt(short, long)]
+    bootstrap: Vec<String>,
+    /// Unix socket path or loopback TCP address of the control API
+    #[structopt(short, long)]
+    endpoint: Option<RpcEndpoint>,
+    /// Log format, text or json
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+    #[structopt(flatten)]
+    config: NodeConfigArgs,
+}
+
+#[cfg(not(feature = "test"))]
+async fn listen(node: Arc<Node>, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let node = Arc::clone(&node);
+        tokio::spawn(async move {
+            node.on_connection(stream).await;
+        });
+    }
+}
+
+#[cfg(feature = "test")]
+async fn listen(_node: Arc<Node>, _addr: SocketAddr) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "networking is simulated when built with the test feature"))
+}
 
 #[tokio::main]
 async fn main() {
-    unimplemented!()
+    let args = Args::from_args();
+    logging::init(args.log_format);
+    let endpoint = args.endpoint.unwrap_or_default();
+
+    let config = args.config.load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(2);
+    });
+    let node = Node::new(args.listen.to_string(), config).await;
+    let (command_receiver, command_sender) = CommandReceiver::new();
+    let node2 = Arc::clone(&node);
+    tokio::spawn(async move {
+        if let Err(e) = listen(node2, args.listen).await {
+            eprintln!("Could not listen on {}: {e}", args.listen);
+            std::process::exit(1);
+        }
+    });
+    if let Some(addr) = node.config.metrics_addr {
+        let command_sender = command_sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = prometheus::serve(addr, command_sender).await {
+                eprintln!("Could not serve metrics on {addr}: {e}");
+                std::process::exit(1);
+            }
+        });
+    }
+    tokio::spawn(async move {
+        if let Err(e) = rpc::serve(&endpoint, command_sender).await {
+            eprintln!("Could not serve the control API on {endpoint}: {e}");
+            std::process::exit(1);
+        }
+    });
+
+    for addr in args.bootstrap {
+        if let CommandOutput::Error(e) = node.on_command(Command::Connect { addr: addr.clone() }).await {
+            eprintln!("Could not bootstrap from {addr}: {e}");
+        }
+    }
+
+    while !node.is_stopped() {
+        let (command, replier) = command_receiver.wait_command().await;
+        replier.reply(node.on_command(command).await);
+    }
+    // Lets the control API send the reply to the quit command
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 }
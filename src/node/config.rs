@@ -0,0 +1,314 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, path::{Path, PathBuf}};
+use structopt::StructOpt;
+
+/// Tunable parameters of a [`Node`].
+///
+/// Can be loaded from a TOML file in which durations are written like `"100s"` or `"500ms"`.
+/// Missing fields keep their default value.
+///
+/// ```toml
+/// bucket_size = 8
+/// ping_interval = "100s"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Number of peers we want in each bucket (`K` in Kademlia)
+    pub bucket_size: usize,
+    /// Number of concurrent requests during lookups
+    pub alpha: usize,
+    /// Connections sending larger packets are refused
+    pub max_packet_size: u32,
+    /// Lenght of the RSA key generated for new nodes
+    pub rsa_key_length: usize,
+    /// Time between two pings of each peer
+    #[serde(with = "duration")]
+    pub ping_interval: Duration,
+    /// Peers that do not reply to a ping within that time are disconnected
+    #[serde(with = "duration")]
+    pub pong_timeout: Duration,
+    /// Time between two refreshes of the buckets
+    #[serde(with = "duration")]
+    pub refresh_interval: Duration,
+    /// Maximum number of followed accounts we help distribute
+    pub max_mirrored_accounts: usize,
+    /// Maximum total size of the snapshots of followed accounts we store, in bytes
+    pub max_mirrored_bytes: usize,
+    /// Time between two updates of the followed accounts we distribute
+    #[serde(with = "duration")]
+    pub mirror_interval: Duration,
+    /// Cached addresses of followed accounts are looked up again once that old
+    #[serde(with = "duration")]
+    pub mention_max_age: Duration,
+    /// Follow records are applied to our followers and published at most once per interval
+    #[serde(with = "duration")]
+    pub follower_update_interval: Duration,
+    /// Loopback address to serve [Prometheus metrics](crate::prometheus) on, if any
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            bucket_size: KADEMLIA_BUCKET_SIZE,
+            alpha: KADEMLIA_ALPHA,
+            max_packet_size: MAX_PACKET_SIZE,
+            rsa_key_length: RSA_KEY_LENGHT,
+            ping_interval: Duration::from_secs(100),
+            pong_timeout: Duration::from_secs(30),
+            refresh_interval: Duration::from_secs(100),
+            max_mirrored_accounts: 256,
+            max_mirrored_bytes: 64_000_000,
+            mirror_interval: Duration::from_secs(600),
+            mention_max_age: Duration::from_secs(86400),
+            follower_update_interval: Duration::from_secs(60),
+            metrics_addr: None,
+        }
+    }
+}
+
+/// For when a node configuration cannot be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    UnknownKey(String),
+    InvalidValue { key: &'static str, message: String },
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{e}"),
+            ConfigError::Toml(e) => write!(f, "{e}"),
+            ConfigError::UnknownKey(key) => write!(f, "Unknown configuration key: {key}"),
+            ConfigError::InvalidValue { key, message } => write!(f, "Invalid value for {key}: {message}"),
+        }
+    }
+}
+
+fn invalid(key: &'static str, message: impl ToString) -> ConfigError {
+    ConfigError::InvalidValue { key, message: message.to_string() }
+}
+
+impl NodeConfig {
+    pub fn from_toml(input: &str) -> Result<NodeConfig, ConfigError> {
+        let config: NodeConfig = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<NodeConfig, ConfigError> {
+        NodeConfig::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Sets a single field from its textual value.
+    /// Keys are the TOML field names, in which dashes can be used instead of underscores.
+    /// The configuration is left untouched if the value is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let mut config = self.clone();
+        match key.replace('-', "_").as_str() {
+            "bucket_size" => config.bucket_size = value.parse().map_err(|e| invalid("bucket_size", e))?,
+            "alpha" => config.alpha = value.parse().map_err(|e| invalid("alpha", e))?,
+            "max_packet_size" => config.max_packet_size = value.parse().map_err(|e| invalid("max_packet_size", e))?,
+            "rsa_key_length" => config.rsa_key_length = value.parse().map_err(|e| invalid("rsa_key_length", e))?,
+            "ping_interval" => config.ping_interval = parse_duration(value).map_err(|e| invalid("ping_interval", e))?,
+            "pong_timeout" => config.pong_timeout = parse_duration(value).map_err(|e| invalid("pong_timeout", e))?,
+            "refresh_interval" => config.refresh_interval = parse_duration(value).map_err(|e| invalid("refresh_interval", e))?,
+            "max_mirrored_accounts" => config.max_mirrored_accounts = value.parse().map_err(|e| invalid("max_mirrored_accounts", e))?,
+            "max_mirrored_bytes" => config.max_mirrored_bytes = value.parse().map_err(|e| invalid("max_mirrored_bytes", e))?,
+            "mirror_interval" => config.mirror_interval = parse_duration(value).map_err(|e| invalid("mirror_interval", e))?,
+            "mention_max_age" => config.mention_max_age = parse_duration(value).map_err(|e| invalid("mention_max_age", e))?,
+            "follower_update_interval" => config.follower_update_interval = parse_duration(value).map_err(|e| invalid("follower_update_interval", e))?,
+            "metrics_addr" => config.metrics_addr = Some(value.parse().map_err(|e| invalid("metrics_addr", e))?),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        config.validate()?;
+        *self = config;
+        Ok(())
+    }
+
+    /// Rejects values the node cannot run with.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.bucket_size == 0 {
+            return Err(invalid("bucket_size", "must be at least 1"));
+        }
+        if self.alpha == 0 {
+            return Err(invalid("alpha", "must be at least 1"));
+        }
+        if self.rsa_key_length < 512 {
+            return Err(invalid("rsa_key_length", "must be at least 512"));
+        }
+        for (key, value) in [("ping_interval", self.ping_interval), ("pong_timeout", self.pong_timeout), ("refresh_interval", self.refresh_interval), ("mirror_interval", self.mirror_interval), ("follower_update_interval", self.follower_update_interval)] {
+            if value.is_zero() {
+                return Err(invalid(key, "must not be zero"));
+            }
+        }
+        if matches!(self.metrics_addr, Some(addr) if !addr.ip().is_loopback()) {
+            return Err(invalid("metrics_addr", "must be a loopback address"));
+        }
+        Ok(())
+    }
+}
+
+/// Command line flags overriding values of the configuration file.
+#[derive(Debug, Default, StructOpt)]
+pub struct NodeConfigArgs {
+    /// TOML file to load the node configuration from
+    #[structopt(long = "config", parse(from_os_str))]
+    pub config_file: Option<PathBuf>,
+    #[structopt(long)]
+    pub bucket_size: Option<usize>,
+    #[structopt(long)]
+    pub alpha: Option<usize>,
+    #[structopt(long)]
+    pub max_packet_size: Option<u32>,
+    #[structopt(long)]
+    pub rsa_key_length: Option<usize>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub ping_interval: Option<Duration>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub pong_timeout: Option<Duration>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub refresh_interval: Option<Duration>,
+    #[structopt(long)]
+    pub max_mirrored_accounts: Option<usize>,
+    #[structopt(long)]
+    pub max_mirrored_bytes: Option<usize>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub mirror_interval: Option<Duration>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub mention_max_age: Option<Duration>,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub follower_update_interval: Option<Duration>,
+    #[structopt(long)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl NodeConfigArgs {
+    /// Loads the configuration file, if any, and applies the flags on top of it.
+    pub fn load(&self) -> Result<NodeConfig, ConfigError> {
+        let mut config = match &self.config_file {
+            Some(path) => NodeConfig::load(path)?,
+            None => NodeConfig::default(),
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn apply(&self, config: &mut NodeConfig) {
+        if let Some(bucket_size) = self.bucket_size {
+            config.bucket_size = bucket_size;
+        }
+        if let Some(alpha) = self.alpha {
+            config.alpha = alpha;
+        }
+        if let Some(max_packet_size) = self.max_packet_size {
+            config.max_packet_size = max_packet_size;
+        }
+        if let Some(rsa_key_length) = self.rsa_key_length {
+            config.rsa_key_length = rsa_key_length;
+        }
+        if let Some(ping_interval) = self.ping_interval {
+            config.ping_interval = ping_interval;
+        }
+        if let Some(pong_timeout) = self.pong_timeout {
+            config.pong_timeout = pong_timeout;
+        }
+        if let Some(refresh_interval) = self.refresh_interval {
+            config.refresh_interval = refresh_interval;
+        }
+        if let Some(max_mirrored_accounts) = self.max_mirrored_accounts {
+            config.max_mirrored_accounts = max_mirrored_accounts;
+        }
+        if let Some(max_mirrored_bytes) = self.max_mirrored_bytes {
+            config.max_mirrored_bytes = max_mirrored_bytes;
+        }
+        if let Some(mirror_interval) = self.mirror_interval {
+            config.mirror_interval = mirror_interval;
+        }
+        if let Some(mention_max_age) = self.mention_max_age {
+            config.mention_max_age = mention_max_age;
+        }
+        if let Some(follower_update_interval) = self.follower_update_interval {
+            config.follower_update_interval = follower_update_interval;
+        }
+        if let Some(metrics_addr) = self.metrics_addr {
+            config.metrics_addr = Some(metrics_addr);
+        }
+    }
+}
+
+/// Durations are written the way [`parse_duration`] reads them.
+mod duration {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        match value.subsec_nanos() {
+            0 => serializer.collect_str(&format_args!("{}s", value.as_secs())),
+            _ => serializer.collect_str(&format_args!("{}ms", value.as_secs_f64() * 1000.0)),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse_duration(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_and_override() {
+        let mut config = NodeConfig::from_toml("
+            bucket_size = 4
+            ping_interval = \"10s\"
+            pong_timeout = \"500ms\"
+        ").unwrap();
+        assert_eq!(config.bucket_size, 4);
+        assert_eq!(config.alpha, KADEMLIA_ALPHA);
+        assert_eq!(config.ping_interval, Duration::from_secs(10));
+        assert_eq!(config.pong_timeout, Duration::from_millis(500));
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(NodeConfig::from_toml(&serialized).unwrap(), config);
+
+        let args = NodeConfigArgs::from_iter(["tewta", "--alpha", "1", "--refresh-interval", "2m", "--metrics-addr", "127.0.0.1:9100"]);
+        args.apply(&mut config);
+        assert_eq!(config.bucket_size, 4);
+        assert_eq!(config.alpha, 1);
+        assert_eq!(config.refresh_interval, Duration::from_secs(120));
+        assert_eq!(config.metrics_addr, Some(SocketAddr::from(([127, 0, 0, 1], 9100))));
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(NodeConfig::from_toml(&serialized).unwrap(), config);
+
+        config.set("max-packet-size", "4096").unwrap();
+        assert_eq!(config.max_packet_size, 4096);
+        assert!(matches!(config.set("bucket_size", "0"), Err(ConfigError::InvalidValue { key: "bucket_size", .. })));
+        assert!(matches!(config.set("buckets", "4"), Err(ConfigError::UnknownKey(_))));
+        assert!(matches!(config.set("metrics-addr", "0.0.0.0:9100"), Err(ConfigError::InvalidValue { key: "metrics_addr", .. })));
+        assert!(matches!(NodeConfig::from_toml("bucket_sise = 4"), Err(ConfigError::Toml(_))));
+        assert!(matches!(NodeConfig::from_toml("ping_interval = \"10\""), Err(ConfigError::Toml(_))));
+    }
+}
@@ -2,6 +2,7 @@
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
 use crate::prelude::*;
+use tracing::Instrument;
 
 #[cfg(not(feature = "test"))]
 pub type ReadHalf = tokio::net::tcp::OwnedReadHalf;
@@ -24,6 +25,8 @@ struct PeerInfo {
 
 pub struct ConnectionPool {
     connections: Mutex<BTreeMap<PeerID, PeerInfo>>,
+    /// Peers we disconnected from on request, with when we accept them again
+    avoided: Mutex<BTreeMap<PeerID, Instant>>,
     our_peer_id: PeerID,
     ll: LogLevel,
     node_ref: UnsafeCell<Weak<Node>>,
@@ -35,6 +38,7 @@ impl ConnectionPool {
     pub fn new(our_peer_id: PeerID, ll: LogLevel) -> ConnectionPool {
         ConnectionPool {
             connections: Mutex::new(BTreeMap::new()),
+            avoided: Mutex::new(BTreeMap::new()),
             our_peer_id,
             ll,
             node_ref: UnsafeCell::new(Weak::new()),
@@ -50,7 +54,7 @@ impl ConnectionPool {
         *self.node_ref.get() = node_ref;
     }
 
-    fn get_node(&self) -> Option<Arc<Node>> {
+    pub(super) fn get_node(&self) -> Option<Arc<Node>> {
         // JUSTIFICATION
         //  Benefit
         //      We have to use UnsafeCell in order to have a reference to our parent struct.
@@ -73,6 +77,7 @@ impl ConnectionPool {
         let node = self.get_node().unwrap();
 
         // Serialize packet
+        let kind = p.kind();
         let p = match p.raw_bytes(&PROTOCOL_SETTINGS) {
             Ok(p) => p,
             Err(e) => {
@@ -95,8 +100,15 @@ impl ConnectionPool {
         let len = p.len() as u32;
         let mut buf = [0u8; 4];
         buf.copy_from_slice(&len.to_be_bytes());
-        peer.write_stream.write_all(&buf).await.unwrap();
-        peer.write_stream.write_all(&p).await.unwrap();
+        if let Err(e) = peer.write_stream.write_all(&buf).await {
+            warn!(node.ll, "failed to write packet to {}: {}", peer_id, e);
+            return;
+        }
+        if let Err(e) = peer.write_stream.write_all(&p).await {
+            warn!(node.ll, "failed to write packet to {}: {}", peer_id, e);
+            return;
+        }
+        node.metrics.packet_sent(kind, p.len() + 4);
         trace!(node.ll, "packet written to {}: {:?}", peer_id, p);
     }
 
@@ -109,6 +121,32 @@ impl ConnectionPool {
         };
     }
 
+    pub async fn ping_nanos(&self, n: &PeerID) -> Option<usize> {
+        let connections = self.connections.lock().await;
+        connections.get(n).and_then(|p| p.ping_nanos)
+    }
+
+    /// Removes a peer whose connection broke.
+    /// Unlike [`ConnectionPool::disconnect`], no quit packet is sent as the stream is unusable.
+    async fn on_connection_lost(&self, n: PeerID) {
+        let node = self.get_node().unwrap();
+
+        let mut connections = self.connections.lock().await;
+        if connections.remove(&n).is_some() {
+            std::mem::drop(connections);
+            node.on_disconnect.event(n).await;
+        }
+    }
+
+    /// Closes all connections without notifying anyone.
+    #[cfg(feature = "test")]
+    pub async fn drop_all(&self) {
+        let mut connections = self.connections.lock().await;
+        for (_, peer) in std::mem::take(&mut *connections) {
+            peer.read_stream_task.abort();
+        }
+    }
+
     pub async fn disconnect(&self, n: PeerID, quit_packet: QuitPacket) {
         let node = self.get_node().unwrap();
 
@@ -127,6 +165,18 @@ impl ConnectionPool {
         }
     }
 
+    /// Refuses connections with a peer for some time, so that it is not rediscovered right after we disconnected from it.
+    pub async fn avoid(&self, peer_id: PeerID, duration: Duration) {
+        self.avoided.lock().await.insert(peer_id, Instant::now() + duration);
+    }
+
+    pub async fn is_avoided(&self, peer_id: &PeerID) -> bool {
+        let mut avoided = self.avoided.lock().await;
+        let now = Instant::now();
+        avoided.retain(|_, until| *until > now);
+        avoided.contains_key(peer_id)
+    }
+
     pub async fn insert(&self, peer_id: PeerID, mut r: ReadHalf, mut w: WriteHalf, addr: String) -> Result<(), ()> {
         let mut connections = self.connections.lock().await;
         if connections.contains_key(&peer_id) {
@@ -147,24 +197,39 @@ impl ConnectionPool {
 
         // Listen for messages from the remote node
         let node = Weak::clone(unsafe {&*self.node_ref.get()});
+        let (max_packet_size, node_span) = match node.upgrade() {
+            Some(node) => (node.config.max_packet_size, node.span.clone()),
+            None => (MAX_PACKET_SIZE, tracing::Span::none()),
+        };
+        // The node span is entered too so that filters selecting nodes apply
+        let span = tracing::error_span!(parent: &node_span, "peer", remote = %peer_id);
         let peer_id2 = peer_id.clone();
         let handle = tokio::spawn(async move {
-            loop {
+            let e = loop {
                 // TODO [#22]: Aes encryption
                 // For receiving and sending
 
                 // Read packet
-                let packet_size = r.read_u32().await.unwrap();
-                if packet_size >= MAX_PACKET_SIZE {
+                let packet_size = match r.read_u32().await {
+                    Ok(packet_size) => packet_size,
+                    Err(e) => break e,
+                };
+                if packet_size >= max_packet_size {
                     warn!(node.upgrade().unwrap().ll, "packet size too large");
                     unimplemented!("Recovery of packet size too large");
                 }
                 let mut packet = Vec::with_capacity(packet_size as usize);
                 unsafe {packet.set_len(packet_size as usize)};
-                r.read_exact(&mut packet).await.unwrap();
+                if let Err(e) = r.read_exact(&mut packet).await {
+                    break e;
+                }
 
                 // Parse packet
-                let packet: Packet = match Parcel::from_raw_bytes(&packet, &PROTOCOL_SETTINGS) {
+                let parsed: Result<Packet, _> = Parcel::from_raw_bytes(&packet, &PROTOCOL_SETTINGS);
+                if let Some(node) = node.upgrade() {
+                    node.metrics.packet_received(parsed.as_ref().ok().map(Packet::kind), packet.len() + 4);
+                }
+                let packet = match parsed {
                     Ok(p) => p,
                     Err(e) => {
                         warn!(node.upgrade().unwrap().ll, "Failed to parse packet {:?}", e);
@@ -174,9 +239,18 @@ impl ConnectionPool {
 
                 // Handle packet
                 // Warning: This blocks the packet receiving loop.
-                node.upgrade().unwrap().on_packet(peer_id2.clone(), packet).await;
+                match node.upgrade() {
+                    Some(node) => node.on_packet(peer_id2.clone(), packet).await,
+                    None => return,
+                }
+            };
+
+            // The connection is broken
+            if let Some(node) = node.upgrade() {
+                warn!(node.ll, "connection to {} lost: {}", peer_id2, e);
+                node.connections.on_connection_lost(peer_id2).await;
             }
-        });
+        }.instrument(span).instrument(node_span));
 
         // Insert peer
         let peer = PeerInfo {
@@ -212,7 +286,7 @@ impl ConnectionPool {
         // TODO [#28]: Create an offline_peers store to return better results
         // In order to increase the strenght of the network
         use rand::seq::SliceRandom;
-        peers.shuffle(&mut OsRng);
+        peers.shuffle(&mut rng());
 
         let max_len = std::cmp::min(p.limit, MAX_DISCOVERY_PEERS_RETURNED) as usize;
         while peers.len() > max_len {
@@ -236,6 +310,12 @@ impl ConnectionPool {
         connections.iter().map(|(n, p)| (n.clone(), p.addr.clone())).collect()
     }
 
+    /// Returns all connected node IDs along with their address and ping
+    pub async fn peers_with_pings(&self) -> Vec<(PeerID, String, Option<usize>)> {
+        let connections = self.connections.lock().await;
+        connections.iter().map(|(n, p)| (n.clone(), p.addr.clone(), p.ping_nanos)).collect()
+    }
+
     pub async fn contains(&self, peer_id: &PeerID) -> bool {
         let connections = self.connections.lock().await;
         connections.contains_key(peer_id)
@@ -254,17 +334,19 @@ impl ConnectionPool {
     /// Refresh buckets and discovers new peers.  
     /// This will return immediately as tasks are spawned.
     pub async fn refresh_buckets(&self) {
+        let node = self.get_node().unwrap();
         'higher: for bucket_level in 0..128 {
             for bucket_id in 0..3 {
                 let peers = self.peers_on_bucket(bucket_level, bucket_id).await;
 
-                if peers.len() < KADEMLIA_BUCKET_SIZE {
-                    trace!(self.ll, "Bucket {bucket_level} {} is missing peers ({}/{})", (['A', 'B', 'C'][bucket_id]), (peers.len()), KADEMLIA_BUCKET_SIZE);
+                if peers.len() < node.config.bucket_size {
+                    trace!(self.ll, "Bucket {bucket_level} {} is missing peers ({}/{})", (['A', 'B', 'C'][bucket_id]), (peers.len()), (node.config.bucket_size));
 
-                    let node = self.get_node().unwrap();
+                    let node = Arc::clone(&node);
+                    let span = node.span.clone();
                     spawn(async move {
                         node.discover_peers_in_bucket(bucket_level, bucket_id).await;
-                    });
+                    }.instrument(span));
                 }
 
                 if peers.is_empty() {
@@ -278,24 +360,17 @@ impl ConnectionPool {
         }
     }
 
-    pub async fn debug_buckets(&self) {
-        let mut message = String::from("Buckets:\n");
+    /// Lists non-empty buckets as `(bucket_level, bucket_id, peers)`.
+    pub async fn buckets(&self) -> Vec<(usize, usize, Vec<PeerID>)> {
+        let mut buckets = Vec::new();
         for bucket_level in 0..128 {
             for bucket_id in 0..3 {
                 let peers = self.peers_on_bucket(bucket_level, bucket_id).await;
-                if peers.is_empty() {
-                    continue;
+                if !peers.is_empty() {
+                    buckets.push((bucket_level, bucket_id, peers));
                 }
-                message.push_str(&format!("{}-{} ({}): ", bucket_level, ['A', 'B', 'C'][bucket_id], peers.len()));
-                for peer in peers.iter() {
-                    message.push_str(&format!("{}, ", peer));
-                }
-                message.push('\n');
             }
         }
-        if message.is_empty() {
-            message = "No buckets".to_string();
-        }
-        log::info!("{}", message);
+        buckets
     }
 }
@@ -1,9 +1,9 @@
 // Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
+use crate::prelude::*;
 use std::sync::atomic::AtomicU32;
 
-#[derive(Default)]
 pub struct Counter {
     value: AtomicU32,
 }
@@ -13,3 +13,12 @@ impl Counter {
         self.value.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 }
+
+impl Default for Counter {
+    /// Starts at a random value so that ids are not predictable by peers.
+    fn default() -> Counter {
+        Counter {
+            value: AtomicU32::new(rng().gen()),
+        }
+    }
+}
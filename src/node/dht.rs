@@ -2,15 +2,62 @@
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
 use crate::prelude::*;
+use tracing::Instrument;
 
 /// A record of contact information to a peer claiming to distribute a snapshot of the account.
-#[derive(Debug, Clone, protocol_derive::Protocol)]
+#[derive(Debug, Clone, protocol_derive::Protocol, serde::Serialize)]
 pub struct DhtValue {
+    /// The peer distributing the snapshot
+    pub provider: PeerID,
+    /// Cached address of the provider. Might have changed
     pub cached_addr: Option<String>,
     pub account_snapshot_desc: SignedData<AccountSnapshotDescriptor>,
+    /// Set when the provider took over the account of the key, in which case the descriptor is that of the provider
+    pub migration: Option<SignedData<Migration>>,
+    /// Set when the provider announces an interaction under the [key of the post](PostRef::interactions_key), proving it belongs there
+    pub interaction: Option<SignedData<Interaction>>,
     // pub peer_id: PeerID, // ommited as it is obtainable from SignedData<DhtValue>
 }
 
+impl DhtValue {
+    /// Whether the value carries an interaction of the provider that belongs under `key`.
+    /// Providers can only announce their own account under keys of posts they interacted with.
+    pub fn proves_interaction(&self, key: &KeyID) -> bool {
+        match &self.interaction {
+            Some(interaction) => matches!(interaction.verify(), Ok(signer) if signer == self.provider && &interaction.data_unverified().target.interactions_key() == key),
+            None => false,
+        }
+    }
+}
+
+/// Merges the values of several stores, keeping the latest value of each provider.
+/// Values must have been announced by the provider about its own account, under its key, a key it migrated from or that of a post it interacted with.
+pub(super) fn merge_announcements(key: &KeyID, stores: Vec<Vec<DhtValue>>) -> Vec<DhtValue> {
+    let mut latest: BTreeMap<PeerID, DhtValue> = BTreeMap::new();
+    for value in stores.into_iter().flatten() {
+        if !matches!(value.account_snapshot_desc.verify(), Ok(signer) if signer == value.provider) {
+            continue;
+        }
+        if &value.provider != key && value.migration.is_none() && !value.proves_interaction(key) {
+            continue;
+        }
+        let timestamp = value.account_snapshot_desc.data_unverified().timestamp;
+        match latest.get(&value.provider) {
+            Some(previous) if previous.account_snapshot_desc.data_unverified().timestamp >= timestamp => (),
+            _ => { latest.insert(value.provider.clone(), value); },
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// A provider stopping to distribute an account.
+#[derive(Debug, Clone, protocol_derive::Protocol)]
+pub struct DhtWithdrawal {
+    pub key_id: KeyID,
+    /// Values of the provider are removed up to this descriptor timestamp, so that newer values cannot be withdrawn by replaying this
+    pub timestamp: u64,
+}
+
 #[derive(Debug, Clone, protocol_derive::Protocol)]
 pub struct DhtSignature {
     pub public_key: Vec<u8>,
@@ -20,9 +67,22 @@ pub struct DhtSignature {
 #[derive(Default)]
 pub struct DhtStore {
     table: Mutex<BTreeMap<KeyID, Vec<DhtValue>>>,
+    /// Backup of each account, as first seen in a descriptor of its owner
+    backups: Mutex<BTreeMap<KeyID, PeerID>>,
 }
 
 impl DhtStore {
+    /// The backup of the account of a key, as first seen in a descriptor of its owner.
+    /// It is kept when values are withdrawn or evicted, so that a compromised primary key cannot replace it later.
+    pub async fn backup(&self, key: &KeyID) -> Option<PeerID> {
+        self.backups.lock().await.get(key).cloned()
+    }
+
+    /// Remembers the backup of an account, unless we already know one.
+    pub async fn remember_backup(&self, key: KeyID, backup: PeerID) {
+        self.backups.lock().await.entry(key).or_insert(backup);
+    }
+
     pub async fn get(&self, key: &KeyID) -> Option<Vec<DhtValue>> {
         let mut table = self.table.lock().await;
         let values = table.get(key);
@@ -35,22 +95,105 @@ impl DhtStore {
         values.cloned()
     }
 
-    pub async fn set(&self, key: KeyID, value: DhtValue) {
-        // TODO [#52]: check data (signature and timestamps)
+    /// All the values we store, by key.
+    pub async fn entries(&self) -> Vec<(KeyID, Vec<DhtValue>)> {
+        let table = self.table.lock().await;
+        table.iter().filter(|(_, values)| !values.is_empty()).map(|(key, values)| (key.clone(), values.clone())).collect()
+    }
+
+    /// Number of keys and number of values we store.
+    pub async fn sizes(&self) -> (usize, usize) {
+        let table = self.table.lock().await;
+        let keys = table.values().filter(|values| !values.is_empty()).count();
+        (keys, table.values().map(|values| values.len()).sum())
+    }
+
+    /// Stores a value, replacing the value of the same provider if it is older.
+    /// Returns `false` if we already had a value of the same provider that was at least as recent.
+    /// Signatures are not checked here, so values coming from other peers should be verified first.
+    ///
+    /// At most [`MAX_DHT_VALUES_RETURNED`] values are kept per key.
+    /// When full, the oldest value is evicted, except the one provided by the owner of the key, and values older than all others are refused.
+    pub async fn set(&self, key: KeyID, value: DhtValue) -> bool {
+        let timestamp = value.account_snapshot_desc.data_unverified().timestamp;
+        let mut table = self.table.lock().await;
+        let values = table.entry(key.clone()).or_insert_with(Vec::new);
+        if let Some(old) = values.iter_mut().find(|v| v.provider == value.provider) {
+            if old.account_snapshot_desc.data_unverified().timestamp >= timestamp {
+                return false;
+            }
+            *old = value;
+            return true;
+        }
+        match values.len() < MAX_DHT_VALUES_RETURNED as usize {
+            true => {
+                values.push(value);
+                true
+            }
+            false => {
+                let oldest = values.iter().enumerate()
+                    .filter(|(_, v)| v.provider != key)
+                    .min_by_key(|(_, v)| v.account_snapshot_desc.data_unverified().timestamp)
+                    .map(|(i, v)| (i, v.account_snapshot_desc.data_unverified().timestamp));
+                match oldest {
+                    Some((i, oldest_timestamp)) if oldest_timestamp < timestamp || value.provider == key => {
+                        values[i] = value;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    /// Removes the value of a provider if its descriptor is not newer than `timestamp`.
+    /// Returns whether a value was removed.
+    pub async fn withdraw(&self, key: &KeyID, provider: &PeerID, timestamp: u64) -> bool {
         let mut table = self.table.lock().await;
-        table.entry(key).or_insert_with(Vec::new).push(value);
+        let values = match table.get_mut(key) {
+            Some(values) => values,
+            None => return false,
+        };
+        let len = values.len();
+        values.retain(|v| &v.provider != provider || v.account_snapshot_desc.data_unverified().timestamp > timestamp);
+        let removed = values.len() < len;
+        if values.is_empty() {
+            table.remove(key);
+        }
+        removed
     }
 }
 
 #[derive(Debug)]
-enum SingleProviderLookupError {
+pub(super) enum SingleProviderLookupError {
     FailedToConnect,
-    RequestIdMismatch,
+    Timeout,
     IoError(std::io::Error),
     ProtocolError(protocol::Error),
     HandshakeError(HandshakeError),
 }
 
+/// Releases a temporary connection if the request using it is dropped, which lookups do with the requests still running once they found what they were looking for.
+struct ReleaseOnDrop<'a> {
+    node: &'a Node,
+    peer_id: PeerID,
+    temporary: bool,
+}
+
+impl Drop for ReleaseOnDrop<'_> {
+    fn drop(&mut self) {
+        if !self.temporary {
+            return;
+        }
+        if let Some(node) = self.node.connections.get_node() {
+            let peer_id = self.peer_id.clone();
+            spawn(async move {
+                node.release_provider(peer_id, true).await;
+            });
+        }
+    }
+}
+
 impl From<std::io::Error> for SingleProviderLookupError {
     fn from(e: std::io::Error) -> Self {
         SingleProviderLookupError::IoError(e)
@@ -65,81 +208,238 @@ impl From<protocol::Error> for SingleProviderLookupError {
 
 impl Node {
     async fn dht_lookup_on_already_connected_provider(&self, key: &KeyID, peer_id: &PeerID) -> Result<DhtLookupResult, SingleProviderLookupError> {
-        // Send request
         let request_id = self.dht_req_counter.next();
-        let p = Packet::FindDhtValue(FindDhtValuePacket {
-            request_id,
-            key: key.clone(),
-            limit_peers: MAX_DHT_PEERS_RETURNED,
-            limit_values: MAX_DHT_VALUES_RETURNED,
-        });
-        let resp_receiver = self.on_find_dht_value_resp_packet.listen().await;
-        self.connections.send_packet(peer_id, p).await;
-
-        // Wait for response
-        let resp = loop {
-            let (n, p) = resp_receiver.recv().await.unwrap();
-            if p.request_id == request_id && &n == peer_id {
-                break p;
-            }
-        };
+        async move {
+            // Send request
+            let p = Packet::FindDhtValue(FindDhtValuePacket {
+                request_id,
+                key: key.clone(),
+                limit_peers: MAX_DHT_PEERS_RETURNED,
+                limit_values: MAX_DHT_VALUES_RETURNED,
+            });
+            let resp_receiver = self.on_find_dht_value_resp_packet.listen().await;
+            self.connections.send_packet(peer_id, p).await;
+
+            // Wait for response
+            let resp = loop {
+                let (n, p) = resp_receiver.recv().await.unwrap();
+                if p.request_id == request_id && &n == peer_id {
+                    break p;
+                }
+            };
+            trace!(self.ll, "Got response");
 
-        Ok(resp.result)
+            Ok(resp.result)
+        }.instrument(tracing::error_span!("request", remote = %peer_id, request_id)).await
     }
 
-    async fn dht_lookup_on_single_provider(&self, key: &KeyID, (peer_id, addr): (PeerID, String)) -> Result<DhtLookupResult, SingleProviderLookupError> {
+    /// Makes sure we are connected to a provider, connecting temporarily if we were not.
+    /// Returns whether the connection is temporary and should be closed with [`Node::release_provider`].
+    pub(super) async fn reach_provider(&self, (peer_id, addr): (PeerID, String)) -> Result<(PeerID, bool), SingleProviderLookupError> {
         use SingleProviderLookupError::*;
-        debug!(self.ll, "DHT lookup on single provider: {}", peer_id);
 
         if self.connections.contains(&peer_id).await {
             debug!(self.ll, "Already connected to peer: {}", peer_id);
-            return self.dht_lookup_on_already_connected_provider(key, &peer_id).await;
+            return Ok((peer_id, false));
         }
 
         // TODO [#39]: Handshake coherence
         // Here we are handshaking but we don't insert the node so it does not benefits from all features our node may provide.
         // It's ok but we have to tell the other node to not consider ourselves like a long-time node, but rather a short term connection that will only exchange one request and response.
 
-        let (r, w) = connect(addr).await.ok_or(FailedToConnect)?.into_split();
+        let (r, w) = connect(&self.addr, addr).await.ok_or(FailedToConnect)?.into_split();
         debug!(self.ll, "Connected to {}", peer_id);
-        let peer_id = self.handshake(r, w, Some(peer_id)).await.map_err(HandshakeError)?;
-        debug!(self.ll, "Handshake with {} completed", peer_id);
-        
-        // Send the lookup request
-        debug!(self.ll, "Sending lookup request to {}", peer_id);
+        // Requests are waiting for this connection, so we give up sooner than for incoming ones
+        match timeout(Duration::from_secs(10), self.handshake(r, w, Some(peer_id.clone()))).await {
+            Ok(Ok(peer_id)) => {
+                debug!(self.ll, "Handshake with {} completed", peer_id);
+                Ok((peer_id, true))
+            }
+            // The provider connected to us in the meantime
+            Ok(Err(crate::node::HandshakeError::AlreadyConnected)) if self.connections.contains(&peer_id).await => Ok((peer_id, false)),
+            Ok(Err(e)) => Err(HandshakeError(e)),
+            Err(_) => {
+                self.metrics.handshake_failed("Timeout");
+                Err(Timeout)
+            }
+        }
+    }
+
+    pub(super) async fn release_provider(&self, peer_id: PeerID, temporary: bool) {
+        if temporary {
+            let quit_packet = QuitPacket {
+                reason_code: String::from("MissionAccomplished"),
+                message: None,
+                report_fault: false,
+            };
+            self.connections.disconnect(peer_id, quit_packet).await;
+        }
+    }
+
+    async fn dht_lookup_on_single_provider(&self, key: &KeyID, provider: (PeerID, String)) -> Result<DhtLookupResult, SingleProviderLookupError> {
+        debug!(self.ll, "DHT lookup on single provider: {}", provider.0);
+        let (peer_id, temporary) = self.reach_provider(provider).await?;
+        let mut guard = ReleaseOnDrop { node: self, peer_id: peer_id.clone(), temporary };
+        // Only the request is given up on timeout, as temporary connections must still be released
+        let result = timeout(Duration::from_secs(10), self.dht_lookup_on_already_connected_provider(key, &peer_id)).await;
+        guard.temporary = false;
+        self.release_provider(peer_id, temporary).await;
+        result.unwrap_or(Err(SingleProviderLookupError::Timeout))
+    }
+
+    async fn find_peer_on_single_provider(&self, target: &PeerID, provider: (PeerID, String)) -> Result<Vec<(PeerID, String)>, SingleProviderLookupError> {
+        debug!(self.ll, "Peer lookup on single provider: {}", provider.0);
+        let (peer_id, temporary) = self.reach_provider(provider).await?;
+        let mut guard = ReleaseOnDrop { node: self, peer_id: peer_id.clone(), temporary };
+
         let request_id = self.dht_req_counter.next();
-        let resp_listerner = self.on_find_dht_value_resp_packet.listen().await;
-        self.connections.send_packet(&peer_id, Packet::FindDhtValue(FindDhtValuePacket {
-            request_id,
-            key: key.clone(),
-            limit_peers: MAX_DHT_PEERS_RETURNED,
-            limit_values: MAX_DHT_VALUES_RETURNED,
-        })).await;
-
-        // Receive the lookup response
-        debug!(self.ll, "Waiting for response from {}", peer_id);
-        let p = loop {
-            let (n, p) = resp_listerner.recv().await.unwrap();
-            if p.request_id == request_id && n == peer_id {
-                break p;
+        let resp = async {
+            let resp_receiver = self.on_find_peer_resp_packet.listen().await;
+            self.connections.send_packet(&peer_id, Packet::FindPeer(FindPeerPacket {
+                request_id,
+                peer_id: target.clone(),
+                limit: MAX_DHT_PEERS_RETURNED,
+            })).await;
+            loop {
+                let (n, p) = resp_receiver.recv().await.unwrap();
+                if p.request_id == request_id && n == peer_id {
+                    trace!(self.ll, "Got response");
+                    break p;
+                }
             }
-        };
+        }.instrument(tracing::error_span!("request", remote = %peer_id, request_id));
+        let resp = timeout(Duration::from_secs(10), resp).await;
 
-        // Disconnect
-        let quit_packet = QuitPacket {
-            reason_code: String::from("MissionAccomplished"),
-            message: None,
-            report_fault: false,
-        };
-        self.connections.disconnect(peer_id, quit_packet).await;
+        guard.temporary = false;
+        self.release_provider(peer_id, temporary).await;
+        Ok(resp.map_err(|_| SingleProviderLookupError::Timeout)?.peers)
+    }
+
+    /// Looks for the address of a peer by iteratively asking the closest peers we know about.
+    /// Gives up once the [`NodeConfig::bucket_size`] closest peers we queried did not know any closer peer.
+    pub async fn find_peer(&self, target: PeerID) -> Option<String> {
+        debug!(self.ll, "Peer lookup: {}", target);
+        self.lookup_peers(&target, true).await.0
+    }
+
+    /// Finds the [`NodeConfig::bucket_size`] peers the closest to a key, which are the ones storing its values.
+    pub async fn closest_peers(&self, key: &KeyID) -> Vec<(PeerID, String)> {
+        debug!(self.ll, "Closest peers lookup: {}", key);
+        let mut peers = self.lookup_peers(key, false).await.1;
+        peers.extend(self.connections.peers_with_addrs().await);
+        peers.sort_by_key(|(peer_id, _)| peer_id.distance(key));
+        peers.dedup_by(|(a, _), (b, _)| a == b);
+        peers.truncate(self.config.bucket_size);
+        peers
+    }
+
+    /// Returns the address of the target if `stop_if_found` and it was found, and the peers that answered us.
+    async fn lookup_peers(&self, target: &PeerID, stop_if_found: bool) -> (Option<String>, Vec<(PeerID, String)>) {
+        let mut providers = self.connections.peers_with_addrs().await;
+        if let Some((_, addr)) = providers.iter().find(|(peer_id, _)| peer_id == target).filter(|_| stop_if_found) {
+            return (Some(addr.clone()), Vec::new());
+        }
+        providers.sort_by_key(|(peer_id, _)| peer_id.distance(target));
+        providers.reverse();
+
+        let mut already_queried = BTreeSet::new();
+        let mut closest_queried: Vec<Box<[u8; 32]>> = Vec::new();
+        let mut answered = Vec::new();
+        let mut concurrent_lookups = Vec::new();
+
+        loop {
+            // Fill with new lookups
+            while concurrent_lookups.len() < self.config.alpha {
+                let provider = match providers.pop() {
+                    Some(provider) => provider,
+                    None => break,
+                };
+                let distance = provider.0.distance(target);
+                // A peer we are looking for might only be known by peers farther than the closest ones, so we keep asking a few more
+                let done = match stop_if_found {
+                    true => already_queried.len() >= self.config.bucket_size * 4,
+                    false => closest_queried.len() >= self.config.bucket_size && distance > closest_queried[self.config.bucket_size - 1],
+                };
+                if done {
+                    providers.clear();
+                    break;
+                }
+                let i = closest_queried.binary_search(&distance).unwrap_or_else(|i| i);
+                closest_queried.insert(i, distance);
+                already_queried.insert(provider.clone());
+                concurrent_lookups.push(Box::pin(async move {
+                    let result = self.find_peer_on_single_provider(target, provider.clone()).await;
+                    (provider, result)
+                }));
+            }
+            if concurrent_lookups.is_empty() {
+                debug!(self.ll, "Peer lookup ended after querying {} peers", already_queried.len());
+                return (None, answered);
+            }
 
-        if p.request_id != request_id {
-            return Err(RequestIdMismatch);
+            // Wait for any lookup to finish
+            let ((provider, first_result), _, other_lookups) = futures::future::select_all(concurrent_lookups).await;
+            concurrent_lookups = other_lookups;
+            match first_result {
+                Ok(peers) => {
+                    answered.push(provider);
+                    if let Some((_, addr)) = peers.iter().find(|(peer_id, _)| peer_id == target).filter(|_| stop_if_found) {
+                        return (Some(addr.clone()), answered);
+                    }
+                    providers.extend(peers);
+                    providers.retain(|r| !already_queried.contains(r) && r.0 != self.peer_id);
+                    providers.sort_by_key(|(peer_id, _)| peer_id.distance(target));
+                    providers.dedup();
+                    providers.reverse();
+                }
+                Err(e) => warn!(self.ll, "Peer lookup failed: {:?}", e),
+            }
         }
-        Ok(p.result)
     }
 
+    /// Looks up the values of an account, which are signed by its owner.
+    /// Values of our own account are read from our store, as lookups never query ourselves.
     pub async fn dht_lookup(&self, key: KeyID) -> Option<Vec<DhtValue>> {
+        let start = Instant::now();
+        if key == self.peer_id {
+            let mut values = self.dht.get(&key).await.unwrap_or_default();
+            values.retain(|v| matches!(v.account_snapshot_desc.verify(), Ok(signer) if signer == key));
+            if !values.is_empty() {
+                self.metrics.lookup_completed(true, 0, start.elapsed());
+                return Some(values);
+            }
+        }
+        let (values, steps) = self.dht_lookup_counting_steps(key).await;
+        self.metrics.lookup_completed(values.is_some(), steps, start.elapsed());
+        values
+    }
+
+    /// Looks up values that providers announced about their own account under a key that is not a peer, like [`PostRef::interactions_key`].
+    ///
+    /// Unlike [`Node::dht_lookup`], there are many providers announcing independently, so we merge the values of all the closest peers.
+    /// Only the latest value of each provider is kept.
+    pub async fn dht_lookup_announcements(&self, key: KeyID) -> Vec<DhtValue> {
+        let stores = self.dht_lookup_stores(&key).await;
+        merge_announcements(&key, stores)
+    }
+
+    /// Values stored by each of the peers the closest to a key, and by us.
+    pub(super) async fn dht_lookup_stores(&self, key: &KeyID) -> Vec<Vec<DhtValue>> {
+        debug!(self.ll, "DHT announcements lookup: {}", key);
+        let peers = self.closest_peers(key).await;
+        let lookups = peers.into_iter().map(|provider| self.dht_lookup_on_single_provider(key, provider));
+
+        let mut stores = vec![self.dht.get(key).await.unwrap_or_default()];
+        for result in futures::future::join_all(lookups).await {
+            if let Ok(DhtLookupResult::Found(found)) = result {
+                stores.push(found);
+            }
+        }
+        stores
+    }
+
+    /// Returns the values found and the number of providers queried.
+    async fn dht_lookup_counting_steps(&self, key: KeyID) -> (Option<Vec<DhtValue>>, usize) {
         debug!(self.ll, "DHT lookup: {}", key);
 
         let mut already_queried = BTreeSet::new();
@@ -154,17 +454,18 @@ impl Node {
 
         loop {
             // Fill with new lookups
-            while concurrent_lookups.len() < KADEMLIA_ALPHA && !should_complete {
+            while concurrent_lookups.len() < self.config.alpha && !should_complete {
                 if let Some(provider) = providers.pop() {
                     if provider.0 == key {
                         should_complete = true;
                     }
                     already_queried.insert(provider.clone());
                     steps += 1;
-                    concurrent_lookups.push(Box::pin(self.dht_lookup_on_single_provider(&key, provider)));
+                    let key = &key;
+                    concurrent_lookups.push(Box::pin(self.dht_lookup_on_single_provider(key, provider)));
                 } else if concurrent_lookups.is_empty() {
                     warn!(self.ll, "Lookup failed, no providers");
-                    return None;
+                    return (None, steps);
                 } else {
                     break;
                 }
@@ -173,16 +474,22 @@ impl Node {
             // Stop looking if we found the best node already
             if concurrent_lookups.is_empty() && should_complete {
                 warn!(self.ll, "Found best node but didn't get anything from it.");
-                return None;
+                return (None, steps);
             }
 
             // Wait for any lookup to finish
             let (first_result, _, other_lookups) = futures::future::select_all(concurrent_lookups).await;
             concurrent_lookups = other_lookups;
             match first_result {
-                Ok(DhtLookupResult::Found(values)) => {
+                Ok(DhtLookupResult::Found(mut values)) => {
+                    // Values are published by the owner of the key
+                    values.retain(|v| matches!(v.account_snapshot_desc.verify(), Ok(signer) if signer == key));
+                    if values.is_empty() {
+                        warn!(self.ll, "DHT lookup got values that were not signed by the owner of the key");
+                        continue;
+                    }
                     debug!(self.ll, "DHT lookup found {} values in {steps} steps.", values.len());
-                    return Some(values);
+                    return (Some(values), steps);
                 }
                 Ok(DhtLookupResult::NotFound(peers)) => {
                     // TODO [#42]: Prevent DOS
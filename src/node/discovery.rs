@@ -2,6 +2,7 @@
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
 use crate::prelude::*;
+use tracing::Instrument;
 
 impl Node {
     pub async fn discover_peers_in_bucket(&self, bucket_level: usize, bucket_id: usize) {
@@ -20,7 +21,7 @@ impl Node {
         let mut providers = self.connections.peers_on_bucket_and_under(bucket_level).await;
         let mut candidates: Vec<(PeerID, String)> = Vec::new();
         let mut old_candidates: BTreeSet<(PeerID, String)> = BTreeSet::new();
-        let mut missing_peers = KADEMLIA_BUCKET_SIZE - self.connections.peers_on_bucket(bucket_level, bucket_id).await.len();
+        let mut missing_peers = self.config.bucket_size.saturating_sub(self.connections.peers_on_bucket(bucket_level, bucket_id).await.len());
 
         while missing_peers > 0 {
             if let Some((peer_id, addr)) = candidates.pop() {
@@ -31,14 +32,14 @@ impl Node {
                 old_candidates.insert((peer_id.clone(), addr.clone()));
 
                 // Make sure this is a valid peer suggestion
-                if self.connections.contains(&peer_id).await {
+                if self.connections.contains(&peer_id).await || self.connections.is_avoided(&peer_id).await {
                     continue;
                 }
                 if !peer_id.matches(&target, &mask) {
                     warn!(self.ll, "Response contains peers that do not match request");
                 }
                 
-                let (r, w) = match connect(addr).await {
+                let (r, w) = match connect(&self.addr, addr).await {
                     Some(s) => s.into_split(),
                     None => continue,
                 };
@@ -60,17 +61,25 @@ impl Node {
                     limit: MAX_DISCOVERY_PEERS_RETURNED,
                 });
     
-                // TODO [#31]: Add timeout
-    
-                let resp_receiver = self.on_discover_peers_resp_packet.listen().await;
-                self.connections.send_packet(&provider, p).await;
-    
-                loop {
-                    let (n, resp) = resp_receiver.recv().await.unwrap();
-                    if resp.request_id == request_id && n == provider {
-                        candidates = resp.peers;
-                        break;
+                let resp = async {
+                    let resp_receiver = self.on_discover_peers_resp_packet.listen().await;
+                    self.connections.send_packet(&provider, p).await;
+
+                    let resp = timeout(Duration::from_secs(10), async {
+                        loop {
+                            let (n, resp) = resp_receiver.recv().await.unwrap();
+                            if resp.request_id == request_id && n == provider {
+                                break resp;
+                            }
+                        }
+                    }).await;
+                    if resp.is_err() {
+                        warn!(self.ll, "Discovery request to {} timed out", provider);
                     }
+                    resp
+                }.instrument(tracing::error_span!("request", remote = %provider, request_id)).await;
+                if let Ok(resp) = resp {
+                    candidates = resp.peers;
                 }
             } else {
                 trace!(self.ll, "No providers available");
@@ -0,0 +1,246 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// For when we cannot follow or unfollow an account.
+#[derive(Debug)]
+pub enum FollowError {
+    /// The following list is part of our account, which has to be published first
+    NoAccount,
+    AlreadyFollowing,
+    NotFollowing,
+    /// The end of our following list is unknown, so nothing can be appended to it
+    Pruned,
+    Fetch(FetchAccountError),
+    Rsa(rsa::errors::Error),
+}
+
+impl From<FetchAccountError> for FollowError {
+    fn from(e: FetchAccountError) -> Self {
+        FollowError::Fetch(e)
+    }
+}
+
+impl From<rsa::errors::Error> for FollowError {
+    fn from(e: rsa::errors::Error) -> Self {
+        FollowError::Rsa(e)
+    }
+}
+
+impl Node {
+    /// Adds an account to the following list of our account and publishes the new version.
+    /// The followed account is then notified so that it can add us to its followers.
+    pub async fn follow(&self, peer_id: PeerID) -> Result<AccountSnapshotDescriptor, FollowError> {
+        if self.account.lock().await.is_none() {
+            return Err(FollowError::NoAccount);
+        }
+        let peer_id = self.resolve_account(peer_id).await;
+        let mention = self.lookup_mention(peer_id.clone()).await?;
+
+        let mut published = self.account.lock().await;
+        let mut account = published.as_ref().ok_or(FollowError::NoAccount)?.1.clone();
+        if Vec::from(account.following.clone()).iter().any(|mention| mention.peer_id == peer_id) {
+            return Err(FollowError::AlreadyFollowing);
+        }
+        account.following.push(mention).map_err(|_| FollowError::Pruned)?;
+        account.following_count += 1;
+        let desc = self.publish_locked(&mut published, account).await?;
+        let username = published.as_ref().map(|(_, account)| account.username.clone()).unwrap_or_default();
+        drop(published);
+
+        self.notify_followed(peer_id, username, true, desc.timestamp).await?;
+        Ok(desc)
+    }
+
+    /// Removes an account from the following list of our account, publishes the new version and notifies the account.
+    pub async fn unfollow(&self, peer_id: &PeerID) -> Result<AccountSnapshotDescriptor, FollowError> {
+        let mut published = self.account.lock().await;
+        let mut account = published.as_ref().ok_or(FollowError::NoAccount)?.1.clone();
+        let mut following = Vec::from(account.following.clone());
+        let count = following.len();
+        following.retain(|mention| &mention.peer_id != peer_id);
+        if following.len() == count {
+            return Err(FollowError::NotFollowing);
+        }
+        account.following = SegmentedArray::from(following);
+        account.following_count = account.following_count.saturating_sub(1);
+        let desc = self.publish_locked(&mut published, account).await?;
+        let username = published.as_ref().map(|(_, account)| account.username.clone()).unwrap_or_default();
+        drop(published);
+
+        self.notify_followed(peer_id.clone(), username, false, desc.timestamp).await?;
+        Ok(desc)
+    }
+
+    /// Looks up the current username and addresses of an account.
+    async fn lookup_mention(&self, peer_id: PeerID) -> Result<UserMention, FetchAccountError> {
+        let values = self.account_descriptors(peer_id.clone()).await?;
+        let providers_addrs = values.iter()
+            .filter(|(_, value)| value.provider != peer_id)
+            .filter_map(|(_, value)| Some((value.provider.clone(), value.cached_addr.clone()?)))
+            .collect();
+
+        // Only the username is needed, not the lists
+        let mut username = None;
+        for (desc, value) in values {
+            let selection = || SegmentSelection::Nothing;
+            match self.fetch_snapshot((value.provider, value.cached_addr), &desc, selection(), selection(), selection(), selection()).await {
+                Ok(snapshot) => {
+                    username = Some(snapshot.username);
+                    break;
+                }
+                Err(e) => warn!(self.ll, "Failed to fetch snapshot: {:?}", e),
+            }
+        }
+
+        Ok(UserMention {
+            username: username.ok_or(FetchAccountError::NoProvider)?,
+            cached_addr: self.find_peer(peer_id.clone()).await,
+            peer_id,
+            providers_addrs,
+            cached_at: unix_timestamp(),
+        })
+    }
+
+    /// Sends a signed [`FollowRecord`] to a followed account.
+    /// This is best effort: the account will not know about us if it cannot be reached.
+    async fn notify_followed(&self, followed: PeerID, username: String, follows: bool, timestamp: u64) -> Result<(), rsa::errors::Error> {
+        let record = FollowRecord {
+            follower: UserMention {
+                username,
+                peer_id: self.peer_id.clone(),
+                cached_addr: Some(self.addr.clone()),
+                providers_addrs: Vec::new(),
+                cached_at: unix_timestamp(),
+            },
+            followed: followed.clone(),
+            follows,
+            timestamp,
+        };
+        let packet = Packet::Follow(FollowPacket { record: record.sign(&self.rsa_public_key, &self.rsa_private_key)? });
+
+        let addr = match self.find_peer(followed.clone()).await {
+            Some(addr) => addr,
+            None => {
+                warn!(self.ll, "Could not find {} to notify it", followed);
+                return Ok(());
+            }
+        };
+        match self.reach_provider((followed.clone(), addr)).await {
+            Ok((peer_id, temporary)) => {
+                self.connections.send_packet(&peer_id, packet).await;
+                self.release_provider(peer_id, temporary).await;
+            }
+            Err(e) => warn!(self.ll, "Could not reach {} to notify it: {:?}", followed, e),
+        }
+        Ok(())
+    }
+
+    /// Queues a follow record, to be applied to our followers by [`Node::apply_follow_records`].
+    /// Records must be signed by the follower, be newer than the previous one we got from it, and not be dated in the future.
+    pub(super) async fn on_follow(&self, p: &FollowPacket) {
+        let (signer, record) = match p.record.clone().into_verified() {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!(self.ll, "Invalid signature on follow record: {:?}", e);
+                return;
+            }
+        };
+        if signer != record.follower.peer_id || record.followed != self.peer_id {
+            warn!(self.ll, "Refusing a follow record signed by {} for someone else", signer);
+            return;
+        }
+        if record.timestamp > unix_timestamp() + MAX_CLOCK_DRIFT.as_secs() {
+            warn!(self.ll, "Refusing a follow record from {} dated in the future", signer);
+            return;
+        }
+        {
+            let mut timestamps = self.follow_timestamps.lock().await;
+            if matches!(timestamps.get(&signer), Some(last) if *last >= record.timestamp) {
+                warn!(self.ll, "Refusing a replayed follow record from {}", signer);
+                return;
+            }
+            timestamps.insert(signer.clone(), record.timestamp);
+        }
+
+        // Only the latest record of each follower matters, so that followers cannot make us publish more than once per interval
+        self.pending_follows.lock().await.insert(signer, record);
+    }
+
+    /// Applies the follow records received since the last call, and publishes our account once if our followers changed.
+    /// This runs periodically with [`NodeConfig::follower_update_interval`].
+    pub async fn apply_follow_records(&self) -> Result<(), rsa::errors::Error> {
+        let records = std::mem::take(&mut *self.pending_follows.lock().await);
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut published = self.account.lock().await;
+        let mut account = match published.as_ref() {
+            Some((_, account)) => account.clone(),
+            None => return Ok(()),
+        };
+        let mut followers = Vec::from(account.followers.clone());
+        let mut changed = false;
+        for (follower, record) in records {
+            match (record.follows, followers.iter().position(|mention| mention.peer_id == follower)) {
+                (true, None) => {
+                    followers.push(record.follower);
+                    account.follower_count += 1;
+                }
+                (false, Some(i)) => {
+                    followers.remove(i);
+                    account.follower_count = account.follower_count.saturating_sub(1);
+                }
+                _ => continue,
+            }
+            changed = true;
+        }
+        if !changed {
+            return Ok(());
+        }
+        account.followers = SegmentedArray::from(followers);
+        self.publish_locked(&mut published, account).await?;
+        Ok(())
+    }
+
+    /// Looks up again the followed accounts whose cached addresses are older than `max_age`, and publishes our account if any changed.
+    /// This runs periodically with [`NodeConfig::mention_max_age`].
+    pub async fn refresh_mentions(&self, max_age: Duration) -> Result<(), rsa::errors::Error> {
+        let stale: Vec<PeerID> = match self.account.lock().await.as_ref() {
+            Some((_, account)) => Vec::from(account.following.clone()).into_iter()
+                .filter(|mention| unix_timestamp().saturating_sub(mention.cached_at) >= max_age.as_secs())
+                .map(|mention| mention.peer_id)
+                .collect(),
+            None => return Ok(()),
+        };
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let mut refreshed = BTreeMap::new();
+        for peer_id in stale {
+            match self.lookup_mention(peer_id.clone()).await {
+                Ok(mention) => { refreshed.insert(peer_id, mention); }
+                Err(e) => warn!(self.ll, "Failed to refresh the mention of {}: {}", peer_id, e),
+            }
+        }
+        if refreshed.is_empty() {
+            return Ok(());
+        }
+
+        // The following list might have changed in the meantime
+        let mut published = self.account.lock().await;
+        let mut account = match published.as_ref() {
+            Some((_, account)) => account.clone(),
+            None => return Ok(()),
+        };
+        let following = Vec::from(account.following.clone()).into_iter()
+            .map(|mention| refreshed.remove(&mention.peer_id).unwrap_or(mention))
+            .collect::<Vec<_>>();
+        account.following = SegmentedArray::from(following);
+        self.publish_locked(&mut published, account).await?;
+        Ok(())
+    }
+}
@@ -15,6 +15,8 @@ pub enum HandshakeError {
     InvalidNonceCopy,
     PacketTooLarge,
     AlreadyConnected,
+    /// We disconnected from that peer on request recently
+    Avoided,
     SamePeer, // We are connecting to ourselves!
     IdentityMismatch,
     PeerQuitted(QuitPacket),
@@ -52,6 +54,7 @@ impl ToQuit for HandshakeError {
             InvalidNonceCopy => "HandshakeError::InvalidNonceCopy",
             PacketTooLarge => "HandshakeError::PacketTooLarge",
             AlreadyConnected => "HandshakeError::AlreadyConnected",
+            Avoided => "HandshakeError::Avoided",
             SamePeer => "HandshakeError::SamePeer",
             IdentityMismatch => "HandshakeError::IdentityMismatch",
             PeerQuitted(_) => "HandshakeError::PeerQuitted",
@@ -74,10 +77,15 @@ impl Node {
     pub async fn handshake(&self, mut r: ReadHalf, mut w: WriteHalf, expected_peer_id: Option<PeerID>) -> Result<PeerID, HandshakeError> {
         match self.handshake_raw(&mut r, &mut w, expected_peer_id).await {
             Ok((peer_id, addr)) => {
-                self.connections.insert(peer_id.clone(), r, w, addr).await.map_err(|_| AlreadyConnected)?;
+                if self.connections.insert(peer_id.clone(), r, w, addr).await.is_err() {
+                    self.metrics.handshake_failed(AlreadyConnected.reason_code());
+                    return Err(AlreadyConnected);
+                }
                 Ok(peer_id)
             },
             Err(e) => {
+                self.metrics.handshake_failed(e.reason_code());
+
                 // Sent quit packet
                 let p = Packet::Quit(e.to_quit());
                 let p = p.raw_bytes(&PROTOCOL_SETTINGS)?;
@@ -86,6 +94,7 @@ impl Node {
                 plen_buf.copy_from_slice(&plen.to_be_bytes());
                 w.write_all(&plen_buf).await?;
                 w.write_all(&p).await?;
+                self.metrics.packet_sent("Quit", p.len() + 4);
 
                 Err(e)
             },
@@ -107,17 +116,19 @@ impl Node {
         plen_buf.copy_from_slice(&plen.to_be_bytes());
         w.write_all(&plen_buf).await?;
         w.write_all(&p).await?;
+        self.metrics.packet_sent("ProtocolVersion", p.len() + 4);
 
         // Receive their protocol version
         trace!(self.ll, "Receiving protocol version");
         let plen = r.read_u32().await?;
-        if plen >= MAX_PACKET_SIZE {
+        if plen >= self.config.max_packet_size {
             return Err(PacketTooLarge);
         }
         let mut p = Vec::with_capacity(plen as usize);
         unsafe {p.set_len(plen as usize)};
         r.read_exact(&mut p).await?;
         let p = Packet::from_raw_bytes(&p, &PROTOCOL_SETTINGS)?;
+        self.metrics.packet_received(Some(p.kind()), plen as usize + 4);
         match p {
             Packet::ProtocolVersion(p) => {
                 // TODO [#16]: We should also accept versions with only the patch version unequal to ours
@@ -134,7 +145,7 @@ impl Node {
         trace!(self.ll, "Sending RSA public key");
         let mut our_nonce = Vec::with_capacity(16);
         unsafe {our_nonce.set_len(16)};
-        OsRng.fill(our_nonce.as_mut_slice());
+        rng().fill(our_nonce.as_mut_slice());
         let p = Packet::InitRsa(InitRsaPacket {
             rsa_public_key_exponent: self.rsa_public_key.e().to_bytes_le(),
             rsa_public_key_modulus: self.rsa_public_key.n().to_bytes_le(),
@@ -146,17 +157,19 @@ impl Node {
         plen_buf.copy_from_slice(&plen.to_be_bytes());
         w.write_all(&plen_buf).await?;
         w.write_all(&p).await?;
+        self.metrics.packet_sent("InitRsa", p.len() + 4);
 
         // Receive their RSA public key
         trace!(self.ll, "Receiving RSA public key");
         let plen = r.read_u32().await?;
-        if plen >= MAX_PACKET_SIZE {
+        if plen >= self.config.max_packet_size {
             return Err(PacketTooLarge);
         }
         let mut p = Vec::with_capacity(plen as usize);
         unsafe {p.set_len(plen as usize)};
         r.read_exact(&mut p).await?;
         let p = Packet::from_raw_bytes(&p, &PROTOCOL_SETTINGS)?;
+        self.metrics.packet_received(Some(p.kind()), plen as usize + 4);
         let (their_public_key, their_nonce) = match p {
             Packet::InitRsa(p) => {
                 let n = rsa::BigUint::from_bytes_le(&p.rsa_public_key_modulus);
@@ -183,29 +196,34 @@ impl Node {
         if self.connections.contains(&their_peer_id).await {
             return Err(AlreadyConnected);
         }
+        // Refusing before the handshake completes keeps the peer from seeing a connection drop, which would make it reconnect right away
+        if self.connections.is_avoided(&their_peer_id).await {
+            return Err(Avoided);
+        }
 
         // Send our AES init packet
         trace!(self.ll, "Sending AES init packet");
         let mut our_aes_key_part = Vec::with_capacity(16);
         unsafe {our_aes_key_part.set_len(16)};
-        OsRng.fill(our_aes_key_part.as_mut_slice());
+        rng().fill(our_aes_key_part.as_mut_slice());
         let p = Packet::InitAes(InitAesPacket {
             aes_key_part: our_aes_key_part.clone(),
             nonce: their_nonce,
         });
         let p = p.raw_bytes(&PROTOCOL_SETTINGS)?;
         #[cfg(not(feature = "no-encryption"))]
-        let p = their_public_key.encrypt(&mut OsRng, PaddingScheme::new_oaep::<sha2::Sha256>(), &p)?;
+        let p = their_public_key.encrypt(&mut rng(), PaddingScheme::new_oaep::<sha2::Sha256>(), &p)?;
         let plen = p.len() as u32;
         let mut plen_buf = [0u8; 4];
         plen_buf.copy_from_slice(&plen.to_be_bytes());
         w.write_all(&plen_buf).await?;
         w.write_all(&p).await?;
+        self.metrics.packet_sent("InitAes", p.len() + 4);
 
         // Receive their AES init packet
         trace!(self.ll, "Receiving AES init packet");
         let plen = r.read_u32().await?;
-        if plen >= MAX_PACKET_SIZE {
+        if plen >= self.config.max_packet_size {
             return Err(PacketTooLarge);
         }
         let mut p = Vec::with_capacity(plen as usize);
@@ -215,6 +233,7 @@ impl Node {
         #[cfg(not(feature = "no-encryption"))]
         let p = self.rsa_private_key.decrypt(PaddingScheme::new_oaep::<sha2::Sha256>(), &p)?;
         let p = Packet::from_raw_bytes(&p, &PROTOCOL_SETTINGS)?;
+        self.metrics.packet_received(Some(p.kind()), plen as usize + 4);
         let mut their_aes_key_part = match p {
             Packet::InitAes(p) => {
                 if p.aes_key_part.len() != 16 {
@@ -260,17 +279,19 @@ impl Node {
         plen_buf.copy_from_slice(&plen.to_be_bytes());
         w.write_all(&plen_buf).await?;
         w.write_all(&p).await?;
+        self.metrics.packet_sent("Ehlo", p.len() + 4);
 
         // Receive their Ehlo packet
         trace!(self.ll, "Receiving their Ehlo packet");
         let plen = r.read_u32().await?;
-        if plen >= MAX_PACKET_SIZE {
+        if plen >= self.config.max_packet_size {
             return Err(PacketTooLarge);
         }
         let mut p = Vec::with_capacity(plen as usize);
         unsafe {p.set_len(plen as usize)};
         r.read_exact(&mut p).await?;
         let p = Packet::from_raw_bytes(&p, &PROTOCOL_SETTINGS)?;
+        self.metrics.packet_received(Some(p.kind()), plen as usize + 4);
         let addr = match p {
             Packet::Ehlo(p) => p.addr,
             Packet::Quit(p) => return Err(PeerQuitted(p)),
@@ -0,0 +1,220 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use std::{fmt::Write, sync::{atomic::{AtomicU64, Ordering::Relaxed}, Mutex as SyncMutex}};
+
+/// Counters sharing a name, distinguished by a label.
+#[derive(Default)]
+struct LabeledCounter {
+    values: SyncMutex<BTreeMap<&'static str, u64>>,
+}
+
+impl LabeledCounter {
+    fn add(&self, label: &'static str, value: u64) {
+        *self.values.lock().unwrap().entry(label).or_default() += value;
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.values.lock().unwrap().iter().map(|(label, value)| (label.to_string(), *value)).collect()
+    }
+}
+
+struct Histogram {
+    /// Upper bounds of the buckets, in increasing order
+    bounds: &'static [f64],
+    /// Number of observations in each bucket, plus one for observations above all bounds
+    counts: SyncMutex<(Vec<u64>, f64)>,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: SyncMutex::new((vec![0; bounds.len() + 1], 0.0)),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let i = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        let mut counts = self.counts.lock().unwrap();
+        counts.0[i] += 1;
+        counts.1 += value;
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let counts = self.counts.lock().unwrap();
+        let mut cumulative = 0;
+        let buckets = self.bounds.iter().zip(&counts.0).map(|(bound, count)| {
+            cumulative += count;
+            (*bound, cumulative)
+        }).collect();
+        HistogramSnapshot {
+            buckets,
+            sum: counts.1,
+            count: counts.0.iter().sum(),
+        }
+    }
+}
+
+/// What a node did since it started.
+/// Recording is cheap and never blocks on the async runtime.
+pub struct Metrics {
+    packets_received: LabeledCounter,
+    packets_sent: LabeledCounter,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    handshake_failures: LabeledCounter,
+    lookups: LabeledCounter,
+    lookup_hops: Histogram,
+    lookup_duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            packets_received: LabeledCounter::default(),
+            packets_sent: LabeledCounter::default(),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            handshake_failures: LabeledCounter::default(),
+            lookups: LabeledCounter::default(),
+            lookup_hops: Histogram::new(&[1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 34.0]),
+            lookup_duration: Histogram::new(&[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+        }
+    }
+}
+
+impl Metrics {
+    /// Records a packet of `bytes` bytes, including its length prefix.
+    /// Packets that could not be parsed have no kind.
+    pub fn packet_received(&self, kind: Option<&'static str>, bytes: usize) {
+        self.packets_received.add(kind.unwrap_or("Invalid"), 1);
+        self.bytes_received.fetch_add(bytes as u64, Relaxed);
+    }
+
+    pub fn packet_sent(&self, kind: &'static str, bytes: usize) {
+        self.packets_sent.add(kind, 1);
+        self.bytes_sent.fetch_add(bytes as u64, Relaxed);
+    }
+
+    pub fn handshake_failed(&self, reason: &'static str) {
+        self.handshake_failures.add(reason.trim_start_matches("HandshakeError::"), 1);
+    }
+
+    /// Records a DHT lookup that sent `hops` requests.
+    pub fn lookup_completed(&self, found: bool, hops: usize, duration: Duration) {
+        self.lookups.add(if found { "found" } else { "not_found" }, 1);
+        self.lookup_hops.observe(hops as f64);
+        self.lookup_duration.observe(duration.as_secs_f64());
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HistogramSnapshot {
+    /// Cumulative counts, as `(upper_bound, count)`
+    pub buckets: Vec<(f64, u64)>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// The state of a node's [`Metrics`], along with gauges measured when taking the snapshot.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MetricsSnapshot {
+    /// By packet kind
+    pub packets_received: BTreeMap<String, u64>,
+    /// By packet kind
+    pub packets_sent: BTreeMap<String, u64>,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// By [`HandshakeError`] variant
+    pub handshake_failures: BTreeMap<String, u64>,
+    /// By result (`found` or `not_found`)
+    pub lookups: BTreeMap<String, u64>,
+    pub lookup_hops: HistogramSnapshot,
+    /// In seconds
+    pub lookup_duration: HistogramSnapshot,
+    pub connections: usize,
+    /// Non-empty buckets, as `(bucket_level, bucket_id, peer_count)`
+    pub buckets: Vec<(usize, usize, usize)>,
+    pub dht_keys: usize,
+    pub dht_values: usize,
+}
+
+impl MetricsSnapshot {
+    /// Renders the snapshot in the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(out, "# HELP tewta_{name} {help}\n# TYPE tewta_{name} {kind}");
+        };
+
+        header(&mut out, "packets_received_total", "counter", "Packets received, by kind");
+        for (kind, count) in &self.packets_received {
+            let _ = writeln!(out, "tewta_packets_received_total{{kind=\"{kind}\"}} {count}");
+        }
+        header(&mut out, "packets_sent_total", "counter", "Packets sent, by kind");
+        for (kind, count) in &self.packets_sent {
+            let _ = writeln!(out, "tewta_packets_sent_total{{kind=\"{kind}\"}} {count}");
+        }
+        header(&mut out, "bytes_received_total", "counter", "Bytes received, including length prefixes");
+        let _ = writeln!(out, "tewta_bytes_received_total {}", self.bytes_received);
+        header(&mut out, "bytes_sent_total", "counter", "Bytes sent, including length prefixes");
+        let _ = writeln!(out, "tewta_bytes_sent_total {}", self.bytes_sent);
+        header(&mut out, "handshake_failures_total", "counter", "Failed handshakes, by reason");
+        for (reason, count) in &self.handshake_failures {
+            let _ = writeln!(out, "tewta_handshake_failures_total{{reason=\"{reason}\"}} {count}");
+        }
+        header(&mut out, "lookups_total", "counter", "DHT lookups, by result");
+        for (result, count) in &self.lookups {
+            let _ = writeln!(out, "tewta_lookups_total{{result=\"{result}\"}} {count}");
+        }
+        header(&mut out, "lookup_hops", "histogram", "Requests sent by DHT lookups");
+        write_histogram(&mut out, "lookup_hops", &self.lookup_hops);
+        header(&mut out, "lookup_duration_seconds", "histogram", "Duration of DHT lookups");
+        write_histogram(&mut out, "lookup_duration_seconds", &self.lookup_duration);
+        header(&mut out, "connections", "gauge", "Connected peers");
+        let _ = writeln!(out, "tewta_connections {}", self.connections);
+        header(&mut out, "bucket_peers", "gauge", "Connected peers in each non-empty bucket");
+        for (bucket_level, bucket_id, count) in &self.buckets {
+            let _ = writeln!(out, "tewta_bucket_peers{{level=\"{bucket_level}\",bucket=\"{}\"}} {count}", ['A', 'B', 'C'][*bucket_id]);
+        }
+        header(&mut out, "dht_keys", "gauge", "Keys in the DHT store");
+        let _ = writeln!(out, "tewta_dht_keys {}", self.dht_keys);
+        header(&mut out, "dht_values", "gauge", "Values in the DHT store");
+        let _ = writeln!(out, "tewta_dht_values {}", self.dht_values);
+
+        out
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, histogram: &HistogramSnapshot) {
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(out, "tewta_{name}_bucket{{le=\"{bound}\"}} {count}");
+    }
+    let _ = writeln!(out, "tewta_{name}_bucket{{le=\"+Inf\"}} {}", histogram.count);
+    let _ = writeln!(out, "tewta_{name}_sum {}", histogram.sum);
+    let _ = writeln!(out, "tewta_{name}_count {}", histogram.count);
+}
+
+impl Node {
+    /// Takes a snapshot of our [`Metrics`] and measures gauges.
+    pub async fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let metrics = &self.metrics;
+        let (dht_keys, dht_values) = self.dht.sizes().await;
+        MetricsSnapshot {
+            packets_received: metrics.packets_received.snapshot(),
+            packets_sent: metrics.packets_sent.snapshot(),
+            bytes_received: metrics.bytes_received.load(Relaxed),
+            bytes_sent: metrics.bytes_sent.load(Relaxed),
+            handshake_failures: metrics.handshake_failures.snapshot(),
+            lookups: metrics.lookups.snapshot(),
+            lookup_hops: metrics.lookup_hops.snapshot(),
+            lookup_duration: metrics.lookup_duration.snapshot(),
+            connections: self.connections.len().await,
+            buckets: self.connections.buckets().await.into_iter().map(|(level, id, peers)| (level, id, peers.len())).collect(),
+            dht_keys,
+            dht_values,
+        }
+    }
+}
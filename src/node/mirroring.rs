@@ -0,0 +1,123 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// A followed account we help distribute.
+#[derive(Debug, Clone)]
+pub struct MirroredAccount {
+    pub desc: AccountSnapshotDescriptor,
+    /// Size of the snapshot, counted against [`NodeConfig::max_mirrored_bytes`]
+    pub size: usize,
+}
+
+/// For when we cannot distribute a followed account.
+#[derive(Debug)]
+pub enum MirrorError {
+    Fetch(FetchAccountError),
+    /// Distributing the account would exceed [`NodeConfig::max_mirrored_accounts`] or [`NodeConfig::max_mirrored_bytes`]
+    QuotaExceeded,
+    /// The update changes the [backup](AccountData::backup_peer_id) of the account
+    BackupChanged,
+    Rsa(rsa::errors::Error),
+}
+
+impl From<FetchAccountError> for MirrorError {
+    fn from(e: FetchAccountError) -> Self {
+        MirrorError::Fetch(e)
+    }
+}
+
+impl From<rsa::errors::Error> for MirrorError {
+    fn from(e: rsa::errors::Error) -> Self {
+        MirrorError::Rsa(e)
+    }
+}
+
+impl Node {
+    /// Downloads the latest snapshot of an account if newer than ours, then serves it and announces ourselves as one of its providers.
+    pub async fn mirror_account(&self, peer_id: PeerID) -> Result<AccountSnapshotDescriptor, MirrorError> {
+        let current = self.mirrors.lock().await.get(&peer_id).map(|mirror| mirror.desc.clone());
+        let mut values = self.account_descriptors(peer_id.clone()).await?;
+        values.retain(|(desc, _)| current.as_ref().map(|current| desc.timestamp > current.timestamp).unwrap_or(true));
+        if values.is_empty() {
+            return current.ok_or(MirrorError::Fetch(FetchAccountError::NotFound));
+        }
+
+        // Descriptors tell the size of the snapshot, so that we don't download accounts we could not distribute
+        let remaining = Self::remaining_quota(&self.config, &*self.mirrors.lock().await, &peer_id);
+        values.retain(|(desc, _)| matches!(remaining, Some(remaining) if desc.size <= remaining as u64));
+        if values.is_empty() {
+            warn!(self.ll, "Not distributing {} as it would exceed our quotas", peer_id);
+            return Err(MirrorError::QuotaExceeded);
+        }
+
+        let (signed_desc, snapshot) = self.fetch_described_snapshot(values).await?;
+        let desc = signed_desc.data_unverified().clone();
+        if matches!(&current, Some(current) if current.backup_peer_id != desc.backup_peer_id) {
+            warn!(self.ll, "Refusing an update of {} that changes its backup", peer_id);
+            return Err(MirrorError::BackupChanged);
+        }
+        let size = snapshot.raw_bytes(&PROTOCOL_SETTINGS).map(|bytes| bytes.len()).unwrap_or(usize::MAX);
+
+        {
+            // Quotas might have been used while downloading, and descriptors could lie about the size
+            let mut mirrors = self.mirrors.lock().await;
+            if !matches!(Self::remaining_quota(&self.config, &mirrors, &peer_id), Some(remaining) if size <= remaining) {
+                warn!(self.ll, "Not distributing {} as it would exceed our quotas", peer_id);
+                return Err(MirrorError::QuotaExceeded);
+            }
+
+            self.snapshots.insert(snapshot).await;
+            if let Some(old) = mirrors.insert(peer_id.clone(), MirroredAccount { desc: desc.clone(), size }) {
+                if old.desc.hash != desc.hash {
+                    self.snapshots.remove(&old.desc.hash).await;
+                }
+            }
+        }
+        self.provide(peer_id.clone(), signed_desc).await?;
+        debug!(self.ll, "Distributing version {} of {}", desc.timestamp, peer_id);
+
+        Ok(desc)
+    }
+
+    /// Bytes we can still use to distribute an account, or `None` if we already distribute as many other accounts as allowed.
+    fn remaining_quota(config: &NodeConfig, mirrors: &BTreeMap<PeerID, MirroredAccount>, peer_id: &PeerID) -> Option<usize> {
+        let others = mirrors.iter().filter(|(mirrored, _)| *mirrored != peer_id);
+        let (count, used) = others.fold((0, 0usize), |(count, used), (_, mirror)| (count + 1, used.saturating_add(mirror.size)));
+        match count >= config.max_mirrored_accounts {
+            true => None,
+            false => Some(config.max_mirrored_bytes.saturating_sub(used)),
+        }
+    }
+
+    /// Forgets a mirrored account and withdraws our provider record.
+    /// Returns `false` if we were not distributing it.
+    pub async fn stop_mirroring(&self, peer_id: PeerID) -> Result<bool, rsa::errors::Error> {
+        let mirror = match self.mirrors.lock().await.remove(&peer_id) {
+            Some(mirror) => mirror,
+            None => return Ok(false),
+        };
+        self.snapshots.remove(&mirror.desc.hash).await;
+        self.unprovide(peer_id.clone(), mirror.desc.timestamp).await?;
+        debug!(self.ll, "Stopped distributing {}", peer_id);
+        Ok(true)
+    }
+
+    /// Makes mirrored accounts match the following list of our published account, and updates them.
+    pub async fn sync_mirrors(&self) {
+        let following = self.following().await;
+
+        let mirrored: Vec<PeerID> = self.mirrors.lock().await.keys().cloned().collect();
+        for peer_id in mirrored.into_iter().filter(|peer_id| !following.contains(peer_id)) {
+            if let Err(e) = self.stop_mirroring(peer_id).await {
+                warn!(self.ll, "Failed to withdraw provider record: {}", e);
+            }
+        }
+        for peer_id in following {
+            if let Err(e) = self.mirror_account(peer_id.clone()).await {
+                warn!(self.ll, "Failed to distribute {}: {:?}", peer_id, e);
+            }
+        }
+    }
+}
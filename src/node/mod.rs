@@ -17,3 +17,21 @@ mod dht;
 pub use dht::*;
 mod discovery;
 pub use discovery::*;
+mod config;
+pub use config::*;
+mod metrics;
+pub use metrics::*;
+mod snapshots;
+pub use snapshots::*;
+mod publishing;
+pub use publishing::*;
+mod mirroring;
+pub use mirroring::*;
+mod timeline;
+pub use timeline::*;
+mod trust;
+pub use trust::*;
+mod recovery;
+pub use recovery::*;
+mod following;
+pub use following::*;
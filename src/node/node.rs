@@ -2,21 +2,46 @@
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
 use crate::prelude::*;
+use tracing::{Instrument, field::Empty};
 
 pub struct Node {
     pub connections: ConnectionPool,
     pub dht: DhtStore,
+    pub snapshots: SnapshotStore,
+    /// The account we published, with its latest descriptor
+    pub account: Mutex<Option<(AccountSnapshotDescriptor, AccountData)>>,
+    /// Followed accounts we help distribute
+    pub mirrors: Mutex<BTreeMap<PeerID, MirroredAccount>>,
+    /// Peers we stored values about our account on, which keep getting updates after others become closer
+    pub own_value_stores: Mutex<BTreeSet<PeerID>>,
+    /// Accounts whose posts appear in our timeline
+    pub timeline_cache: Mutex<BTreeMap<PeerID, CachedAccount>>,
+    /// Migrations we followed, and accounts we found had not migrated
+    pub resolved_accounts: Mutex<BTreeMap<PeerID, ResolvedAccount>>,
+    pub moderation: Mutex<ModerationLists>,
+    /// Timestamp of the latest [`FollowRecord`] of each follower, so that older ones cannot be replayed
+    pub follow_timestamps: Mutex<BTreeMap<PeerID, u64>>,
+    /// Latest follow record of each follower, not yet applied to our account
+    pub pending_follows: Mutex<BTreeMap<PeerID, FollowRecord>>,
+    /// Set by [`Command::Quit`], after which periodic tasks end and connections are refused
+    stopped: std::sync::atomic::AtomicBool,
     pub rsa_private_key: RsaPrivateKey,
     pub rsa_public_key: RsaPublicKey,
     pub peer_id: PeerID,
     pub addr: String,
+    pub config: NodeConfig,
 
     pub ll: LogLevel,
+    /// Span identifying the node in logs.
+    /// Tasks driving the node (accepting connections, running commands) should be instrumented with it.
+    pub span: tracing::Span,
+    pub metrics: Metrics,
 
     // Counters
     pub ping_id_counter: Counter,
     pub discover_peer_req_counter: Counter,
     pub dht_req_counter: Counter,
+    pub snapshot_req_counter: Counter,
 
     // Event listeners
     pub on_ping_packet: EventListeners<(PeerID, PingPacket)>,
@@ -29,33 +54,59 @@ pub struct Node {
     pub on_find_peer_packet: EventListeners<(PeerID, FindPeerPacket)>,
     pub on_find_peer_resp_packet: EventListeners<(PeerID, FindPeerRespPacket)>,
     pub on_store_dht_value_packet: EventListeners<(PeerID, StoreDhtValuePacket)>,
+    pub on_withdraw_dht_value_packet: EventListeners<(PeerID, WithdrawDhtValuePacket)>,
+    pub on_fetch_snapshot_packet: EventListeners<(PeerID, FetchSnapshotPacket)>,
+    pub on_fetch_snapshot_resp_packet: EventListeners<(PeerID, FetchSnapshotRespPacket)>,
+    pub on_follow_packet: EventListeners<(PeerID, FollowPacket)>,
 
     pub on_disconnect: EventListeners<PeerID>,
 }
 
 impl Node {
-    pub async fn new(addr: String) -> Arc<Node> {
+    pub async fn new(addr: String, config: NodeConfig) -> Arc<Node> {
         //debug!("Generating RSA key pair...");
-        let private_key = RsaPrivateKey::new(&mut OsRng, RSA_KEY_LENGHT).expect("failed to generate a key");
+        let private_key = RsaPrivateKey::new(&mut rng(), config.rsa_key_length).expect("failed to generate a key");
+        //debug!("RSA keys generated!");
+
+        Node::with_private_key(addr, private_key, config).await
+    }
+
+    /// Creates a node with an existing identity.
+    pub async fn with_private_key(addr: String, private_key: RsaPrivateKey, config: NodeConfig) -> Arc<Node> {
         let public_key = RsaPublicKey::from(&private_key);
         let peer_id = PeerID::from(&public_key);
-        //debug!("RSA keys generated!");
 
-        let log_level = LogLevel::from(1);
+        // Filtering is up to the subscriber, unless lowered at runtime
+        let log_level = LogLevel::from(5);
+        let span = tracing::error_span!("node", addr = %addr, peer_id = %peer_id);
 
         let node = Arc::new(Node {
             connections: ConnectionPool::new(peer_id.clone(), log_level.clone()),
             dht: DhtStore::default(),
+            snapshots: SnapshotStore::default(),
+            account: Mutex::new(None),
+            mirrors: Mutex::new(BTreeMap::new()),
+            own_value_stores: Mutex::new(BTreeSet::new()),
+            timeline_cache: Mutex::new(BTreeMap::new()),
+            resolved_accounts: Mutex::new(BTreeMap::new()),
+            moderation: Mutex::new(ModerationLists::default()),
+            follow_timestamps: Mutex::new(BTreeMap::new()),
+            pending_follows: Mutex::new(BTreeMap::new()),
+            stopped: std::sync::atomic::AtomicBool::new(false),
             peer_id,
             addr,
+            config,
             rsa_private_key: private_key,
             rsa_public_key: public_key,
 
             ll: log_level,
+            span,
+            metrics: Metrics::default(),
 
             ping_id_counter: Counter::default(),
             discover_peer_req_counter: Counter::default(),
             dht_req_counter: Counter::default(),
+            snapshot_req_counter: Counter::default(),
 
             on_ping_packet: EventListeners::default(),
             on_pong_packet: EventListeners::default(),
@@ -67,6 +118,10 @@ impl Node {
             on_find_peer_packet: EventListeners::default(),
             on_find_peer_resp_packet: EventListeners::default(),
             on_store_dht_value_packet: EventListeners::default(),
+            on_withdraw_dht_value_packet: EventListeners::default(),
+            on_fetch_snapshot_packet: EventListeners::default(),
+            on_fetch_snapshot_resp_packet: EventListeners::default(),
+            on_follow_packet: EventListeners::default(),
 
             on_disconnect: EventListeners::default(),
         });
@@ -83,36 +138,41 @@ impl Node {
             node.connections.set_node_ref(Arc::downgrade(&node));
         }
 
+        #[cfg(feature = "test")]
+        node.start_misbehaving();
+
         let node2 = Arc::clone(&node);
         spawn(async move {
             node2.bootstrap_peers().await;
-        });
+        }.instrument(node.span.clone()));
 
         // Continuously ping peers
         let node2 = Arc::downgrade(&node);
+        let ping_interval = node.config.ping_interval;
         spawn(async move {
             let node = node2;
             loop {
-                sleep(Duration::from_secs(100)).await;
+                sleep(ping_interval).await;
 
                 let node = match node.upgrade() {
-                    Some(node) => node,
-                    None => break,
+                    Some(node) if !node.is_stopped() => node,
+                    _ => break,
                 };
 
                 let peer_ids = node.connections.peers().await;
                 for peer_id in peer_ids {
                     let node = Arc::clone(&node);
+                    let ping_id = node.ping_id_counter.next();
+                    let span = tracing::error_span!("request", remote = %peer_id, request_id = ping_id);
                     spawn(async move {
                         // Send ping
-                        let ping_id = node.ping_id_counter.next();
+                        let pong_receiver = node.on_pong_packet.listen().await;
                         let start = Instant::now();
                         node.connections.send_packet(&peer_id, Packet::Ping(PingPacket { ping_id })).await;
 
                         // Receive pong
-                        let pong_receiver = node.on_pong_packet.listen().await;
                         let peer_id2 = &peer_id;
-                        let result = timeout(Duration::from_secs(30), async move {
+                        let result = timeout(node.config.pong_timeout, async move {
                             loop {
                                 let (n, pong) = pong_receiver.recv().await.unwrap();
                                 if pong.ping_id == ping_id && &n == peer_id2 {
@@ -134,48 +194,97 @@ impl Node {
                                 node.connections.disconnect(peer_id, quit_packet).await;
                             },
                         }
-                    });
+                    }.instrument(span).in_current_span());
                 }
             }
-        });
+        }.instrument(node.span.clone()));
 
         // Update buckets
         let node2 = Arc::downgrade(&node);
+        let refresh_interval = node.config.refresh_interval;
         spawn(async move {
             let node = node2;
             loop {
-                sleep(Duration::from_secs(100)).await;
+                sleep(refresh_interval).await;
 
                 let node = match node.upgrade() {
-                    Some(node) => node,
-                    None => break,
+                    Some(node) if !node.is_stopped() => node,
+                    _ => break,
                 };
 
                 node.connections.refresh_buckets().await;
             }
-        });
+        }.instrument(node.span.clone()));
+
+        // Keep distributing the latest version of followed accounts, and their addresses up to date
+        let node2 = Arc::downgrade(&node);
+        let mirror_interval = node.config.mirror_interval;
+        spawn(async move {
+            let node = node2;
+            loop {
+                sleep(mirror_interval).await;
+
+                let node = match node.upgrade() {
+                    Some(node) if !node.is_stopped() => node,
+                    _ => break,
+                };
+
+                node.sync_mirrors().await;
+                if let Err(e) = node.refresh_mentions(node.config.mention_max_age).await {
+                    warn!(node.ll, "Failed to refresh mentions: {:?}", e);
+                }
+            }
+        }.instrument(node.span.clone()));
+
+        // Apply follow records in batches
+        let node2 = Arc::downgrade(&node);
+        let follower_update_interval = node.config.follower_update_interval;
+        spawn(async move {
+            let node = node2;
+            loop {
+                sleep(follower_update_interval).await;
+
+                let node = match node.upgrade() {
+                    Some(node) if !node.is_stopped() => node,
+                    _ => break,
+                };
+
+                if let Err(e) = node.apply_follow_records().await {
+                    warn!(node.ll, "Failed to publish our updated followers: {:?}", e);
+                }
+            }
+        }.instrument(node.span.clone()));
 
         // Update buckets on disconnect (this cannot be done in a method due to borrow checker limitations)
         let node2 = Arc::downgrade(&node);
         let listener = node.on_disconnect.listen().await;
         spawn(async move {
             let node = node2;
-            loop {
-                let _ = listener.recv().await;
-                node.upgrade().unwrap().connections.refresh_buckets().await;
+            while listener.recv().await.is_ok() {
+                match node.upgrade() {
+                    Some(node) if !node.is_stopped() => node.connections.refresh_buckets().await,
+                    _ => break,
+                }
             }
-        });
+        }.instrument(node.span.clone()));
 
         node
     }
 
+    /// Simulates a crash of the node.
+    /// All connections are abruptly closed, without sending any quit packet.
+    #[cfg(feature = "test")]
+    pub async fn kill(&self) {
+        self.connections.drop_all().await;
+    }
+
     async fn bootstrap_peers(&self) {
         let node_count = unsafe {crate::NODE_COUNT.load(std::sync::atomic::Ordering::Relaxed)};
 
         for _ in 0..node_count {    
-            let n = rand::thread_rng().gen_range(0..node_count);
+            let n = rng().gen_range(0..node_count);
             let addr = format!("local-{}", n);
-            if let Some(s) = connect(addr).await {
+            if let Some(s) = connect(&self.addr, addr).await {
                 self.on_connection(s).await;
             }
 
@@ -185,65 +294,183 @@ impl Node {
         }
     }
 
-    pub async fn on_command(&self, c: Command) {
+    pub async fn on_command(&self, c: Command) -> CommandOutput {
         match c {
-            Command::Conns => {
-                log::info!("{} connections", self.connections.len().await);
-            }
-            Command::Buckets => {
-                self.connections.debug_buckets().await;
-            }
+            Command::Conns => CommandOutput::Conns { count: self.connections.len().await },
+            Command::Buckets => CommandOutput::Buckets(self.connections.buckets().await),
             Command::RefreshBuckets => {
                 self.connections.refresh_buckets().await;
-                log::info!("Buckets refreshed");
+                CommandOutput::Done
             }
             Command::Ping { node_id } => {
-                // Send ping
                 let ping_id = self.ping_id_counter.next();
-                let start = Instant::now();
-                self.connections.send_packet(&node_id, Packet::Ping(PingPacket { ping_id })).await;
-
-                // Receive pong
-                let pong_receiver = self.on_pong_packet.listen().await;
-                let result = timeout(Duration::from_secs(15), async move {
-                    loop {
-                        let (n, pong) = pong_receiver.recv().await.unwrap();
-                        if pong.ping_id == ping_id && n == node_id {
-                            break Instant::now().duration_since(start);
+                let span = tracing::error_span!("request", remote = %node_id, request_id = ping_id);
+                async move {
+                    // Send ping
+                    let pong_receiver = self.on_pong_packet.listen().await;
+                    let start = Instant::now();
+                    self.connections.send_packet(&node_id, Packet::Ping(PingPacket { ping_id })).await;
+
+                    // Receive pong
+                    let result = timeout(Duration::from_secs(15), async move {
+                        loop {
+                            let (n, pong) = pong_receiver.recv().await.unwrap();
+                            if pong.ping_id == ping_id && n == node_id {
+                                break Instant::now().duration_since(start);
+                            }
                         }
-                    }
-                }).await;
+                    }).await;
 
-                // Display result
-                match result {
-                    Ok(d) => log::info!("Ping is {} ms", d.as_millis()),
-                    Err(_) => log::info!("Timed out"),
-                }
+                    CommandOutput::Ping(result.ok())
+                }.instrument(span).await
             }
             Command::SetLogLevel { level } => {
                 self.ll.set(level);
+                CommandOutput::Done
+            }
+            Command::Id => CommandOutput::Id(self.peer_id.clone()),
+            Command::Find { key } => CommandOutput::Find(self.dht_lookup(key).await),
+            Command::Connect { addr } => {
+                if self.is_stopped() {
+                    return CommandOutput::Error(String::from("Node is stopped"));
+                }
+                let (r, w) = match connect(&self.addr, addr.clone()).await {
+                    Some(s) => s.into_split(),
+                    None => return CommandOutput::Error(format!("Could not connect to {addr}")),
+                };
+                match timeout(HANDSHAKE_TIMEOUT, self.handshake(r, w, None)).await {
+                    Ok(Ok(peer_id)) => CommandOutput::Connected(peer_id),
+                    Ok(Err(e)) => CommandOutput::Error(format!("Handshake failed: {:?}", e)),
+                    Err(_) => {
+                        self.metrics.handshake_failed("Timeout");
+                        CommandOutput::Error(String::from("Handshake timed out"))
+                    }
+                }
+            }
+            Command::Disconnect { peer_id } => {
+                if !self.connections.contains(&peer_id).await {
+                    return CommandOutput::Error(format!("Not connected to {peer_id}"));
+                }
+                let quit_packet = QuitPacket {
+                    reason_code: String::from("Requested"),
+                    message: None,
+                    report_fault: false,
+                };
+                // Otherwise bucket refreshes would connect to it again right away
+                self.connections.avoid(peer_id.clone(), REQUESTED_DISCONNECT_COOLDOWN).await;
+                self.connections.disconnect(peer_id, quit_packet).await;
+                CommandOutput::Done
+            }
+            Command::Store { key } => {
+                let timestamp = unix_timestamp();
+                let value = DhtValue {
+                    provider: self.peer_id.clone(),
+                    cached_addr: Some(self.addr.clone()),
+                    account_snapshot_desc: match (AccountSnapshotDescriptor { timestamp, hash: [0; 32], backup_peer_id: self.peer_id.clone(), size: 0 }).sign(&self.rsa_public_key, &self.rsa_private_key) {
+                        Ok(desc) => desc,
+                        Err(e) => return CommandOutput::Error(format!("Could not sign value: {e}")),
+                    },
+                    migration: None,
+                    interaction: None,
+                };
+                self.dht.set(key, value).await;
+                CommandOutput::Done
+            }
+            Command::FindPeer { peer_id } => CommandOutput::FindPeer(self.find_peer(peer_id).await),
+            Command::DhtDump => CommandOutput::DhtDump(self.dht.entries().await),
+            Command::Metrics => CommandOutput::Metrics(Box::new(self.metrics_snapshot().await)),
+            Command::Timeline { cursor, limit } => CommandOutput::Timeline(self.timeline(cursor, limit).await),
+            Command::Peers { verbose } => {
+                let peers = self.connections.peers_with_pings().await;
+                CommandOutput::Peers(peers.into_iter().map(|(peer_id, addr, ping_nanos)| PeerInfo {
+                    bucket: peer_id.bucket(&self.peer_id).filter(|_| verbose),
+                    addr: Some(addr).filter(|_| verbose),
+                    ping: ping_nanos.map(|p| Duration::from_nanos(p as u64)).filter(|_| verbose),
+                    peer_id,
+                }).collect())
             }
-            Command::Id => {
-                log::info!("{}", self.peer_id);
+            Command::Discover { bucket_level, bucket_id } => {
+                if bucket_level >= 128 || bucket_id >= 3 {
+                    return CommandOutput::Error(String::from("Buckets range from 0-A to 127-C"));
+                }
+                self.discover_peers_in_bucket(bucket_level, bucket_id).await;
+                let peers = self.connections.peers_on_bucket(bucket_level, bucket_id).await;
+                CommandOutput::Buckets(vec![(bucket_level, bucket_id, peers)])
             }
-            Command::Find { key } => {
-                self.dht_lookup(key).await;
+            Command::Publish { username } => {
+                let account = match self.account.lock().await.as_ref() {
+                    Some((_, account)) => AccountData { username, ..account.clone() },
+                    None => AccountData {
+                        username,
+                        followers: SegmentedArray::from(Vec::new()),
+                        follower_count: 0,
+                        following: SegmentedArray::from(Vec::new()),
+                        following_count: 0,
+                        backup_peer_id: self.peer_id.clone(),
+                        props: BTreeMap::new(),
+                        posts: SegmentedArray::from(Vec::new()),
+                        interactions: SegmentedArray::from(Vec::new()),
+                    },
+                };
+                match self.publish_account(account).await {
+                    Ok(desc) => CommandOutput::Published(desc),
+                    Err(e) => CommandOutput::Error(format!("Could not sign account: {e}")),
+                }
+            }
+            Command::Post { text } => match self.post(Post::new(text)).await {
+                Ok(reference) => CommandOutput::Posted(reference),
+                Err(e) => CommandOutput::Error(format!("Could not post: {:?}", e)),
+            },
+            Command::Follow { peer_id } => match self.follow(peer_id).await {
+                Ok(desc) => CommandOutput::Published(desc),
+                Err(e) => CommandOutput::Error(format!("Could not follow: {:?}", e)),
+            },
+            Command::FetchAccount { peer_id } => match self.fetch_account(peer_id).await {
+                Ok((descriptor, snapshot)) => CommandOutput::Account(Box::new(AccountInfo {
+                    descriptor,
+                    username: snapshot.username,
+                    follower_count: snapshot.follower_count,
+                    following_count: snapshot.following_count,
+                    backup_peer_id: snapshot.backup_peer_id,
+                    posts: Vec::from(snapshot.posts).into_iter().filter_map(|post| post.into_verified().ok().map(|(_, post)| post)).collect(),
+                })),
+                Err(e) => CommandOutput::Error(format!("Could not fetch account: {e}")),
+            },
+            Command::Quit => {
+                self.stopped.store(true, std::sync::atomic::Ordering::Relaxed);
+                for peer_id in self.connections.peers().await {
+                    let quit_packet = QuitPacket {
+                        reason_code: String::from("Quit"),
+                        message: None,
+                        report_fault: false,
+                    };
+                    self.connections.disconnect(peer_id, quit_packet).await;
+                }
+                CommandOutput::Done
             }
-            c => log::info!("{:?}", c),
         }
     }
 
+    /// Returns true once the node was stopped by [`Command::Quit`].
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
     pub async fn on_connection(&self, s: TcpStream) {
         trace!(self.ll, "New connection");
+        if self.is_stopped() {
+            return;
+        }
         
         let (r, w) = s.into_split();
-        let peer_id = match timeout(Duration::from_secs(40), self.handshake(r, w, None)).await {
+        let peer_id = match timeout(HANDSHAKE_TIMEOUT, self.handshake(r, w, None)).await {
             Ok(Ok(result)) => result,
             Ok(Err(e)) => {
                 warn!(self.ll, "Handshake failed: {:?}", e);
                 return;
             }
             Err(_) => {
+                self.metrics.handshake_failed("Timeout");
                 warn!(self.ll, "Handshake timed out");
                 return;
             }
@@ -257,8 +484,21 @@ impl Node {
     /// This method will be called concurrently, but only for different nodes.
     /// Meaning packets from the same node will be handled serially.
     pub async fn on_packet(&self, n: PeerID, p: Packet) {
+        let span = tracing::error_span!("packet", kind = p.kind(), request_id = Empty);
+        if let Some(request_id) = p.request_id() {
+            span.record("request_id", request_id);
+        }
+        self.handle_packet(n, p).instrument(span).await
+    }
+
+    async fn handle_packet(&self, n: PeerID, p: Packet) {
         trace!(self.ll, "Received packet {:?}", p);
 
+        #[cfg(feature = "test")]
+        if self.misbehave(&n, &p).await {
+            return;
+        }
+
         match p {
             // Peer discovery
             Packet::DiscoverPeers(p) => {
@@ -286,7 +526,7 @@ impl Node {
             
             // Kademlia DHT
             Packet::FindDhtValue(p) => {
-                log::debug!("FindDhtValue in {}", self.peer_id);
+                debug!(self.ll, "Looking up {} for {}", p.key, n);
 
                 let result = match self.dht.get(&p.key).await {
                     Some(mut values) => {
@@ -350,10 +590,33 @@ impl Node {
                 self.on_find_peer_resp_packet.event((n, p)).await;
             }
             Packet::StoreDhtValue(p) => {
-                // TODO [#37]: store value
+                self.on_store_dht_value(&n, &p).await;
 
                 self.on_store_dht_value_packet.event((n, p)).await;
             }
+            Packet::WithdrawDhtValue(p) => {
+                self.on_withdraw_dht_value(&n, &p).await;
+
+                self.on_withdraw_dht_value_packet.event((n, p)).await;
+            }
+
+            // Account snapshots
+            Packet::FetchSnapshot(p) => {
+                let response = self.prepare_fetch_snapshot_response(&p).await;
+                self.connections.send_packet(&n, Packet::FetchSnapshotResp(response)).await;
+
+                self.on_fetch_snapshot_packet.event((n, p)).await;
+            }
+            Packet::FetchSnapshotResp(p) => {
+                self.on_fetch_snapshot_resp_packet.event((n, p)).await;
+            }
+
+            // Social graph
+            Packet::Follow(p) => {
+                self.on_follow(&p).await;
+
+                self.on_follow_packet.event((n, p)).await;
+            }
 
             // Utility packets
             Packet::Ping(p) => {
@@ -0,0 +1,332 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// For when a post cannot be published.
+#[derive(Debug)]
+pub enum PostError {
+    /// Posts are stored in our account, which has to be published first
+    NoAccount,
+    /// The end of the posts or interactions of our account is unknown, so nothing can be appended to them
+    Pruned,
+    Rsa(rsa::errors::Error),
+}
+
+impl From<rsa::errors::Error> for PostError {
+    fn from(e: rsa::errors::Error) -> Self {
+        PostError::Rsa(e)
+    }
+}
+
+impl Node {
+    /// Puts a version of our account on the network.
+    ///
+    /// We provide the snapshot ourselves and announce it under our [`PeerID`] in our DHT store and in the stores of the closest peers we are connected to.
+    /// Calling this again replaces the previous version, which we stop serving.
+    pub async fn publish_account(&self, account: AccountData) -> Result<AccountSnapshotDescriptor, rsa::errors::Error> {
+        // Holding the lock makes sure concurrent publications get different timestamps
+        let mut published = self.account.lock().await;
+        self.publish_locked(&mut published, account).await
+    }
+
+    /// The accounts in the following list of our published account.
+    pub async fn following(&self) -> BTreeSet<PeerID> {
+        match self.account.lock().await.as_ref() {
+            Some((_, account)) => Vec::from(account.following.clone()).into_iter().map(|mention| mention.peer_id).collect(),
+            None => BTreeSet::new(),
+        }
+    }
+
+    /// Signs a post, appends it to the post log of our account and publishes the new version.
+    /// Replies are also recorded as [interactions](Node::interact).
+    pub async fn post(&self, post: Post) -> Result<PostRef, PostError> {
+        let mut published = self.account.lock().await;
+        let mut account = published.as_ref().ok_or(PostError::NoAccount)?.1.clone();
+        let reference = post.reference(self.peer_id.clone());
+        let reply = post.reply_to.clone().map(|target| Interaction::new(InteractionKind::Reply { hash: reference.hash }, target));
+        account.add_post(post.sign(&self.rsa_public_key, &self.rsa_private_key)?).map_err(|_| PostError::Pruned)?;
+        let announcement = match reply {
+            Some(reply) => Some(self.interact_locked(&mut published, account, reply).await?),
+            None => {
+                self.publish_locked(&mut published, account).await?;
+                None
+            }
+        };
+        drop(published);
+
+        if let Some((key, value)) = announcement {
+            self.announce(key, value).await?;
+        }
+        Ok(reference)
+    }
+
+    /// Signs an interaction with a post, adds it to our account and publishes the new version.
+    /// We then announce ourselves under the [key of the post](PostRef::interactions_key).
+    pub async fn interact(&self, kind: InteractionKind, target: PostRef) -> Result<Interaction, PostError> {
+        let mut published = self.account.lock().await;
+        let account = published.as_ref().ok_or(PostError::NoAccount)?.1.clone();
+        let interaction = Interaction::new(kind, target);
+        let (key, value) = self.interact_locked(&mut published, account, interaction.clone()).await?;
+        drop(published);
+
+        self.announce(key, value).await?;
+        Ok(interaction)
+    }
+
+    /// Publishes our account with an interaction, and returns the value to [announce](Node::announce) once our account is unlocked.
+    async fn interact_locked(&self, published: &mut Option<(AccountSnapshotDescriptor, AccountData)>, mut account: AccountData, interaction: Interaction) -> Result<(KeyID, DhtValue), PostError> {
+        let key = interaction.target.interactions_key();
+        let interaction = interaction.sign(&self.rsa_public_key, &self.rsa_private_key)?;
+        account.add_interaction(interaction.clone()).map_err(|_| PostError::Pruned)?;
+        let desc = self.publish_locked(published, account).await?;
+
+        let value = DhtValue {
+            provider: self.peer_id.clone(),
+            cached_addr: Some(self.addr.clone()),
+            account_snapshot_desc: desc.sign(&self.rsa_public_key, &self.rsa_private_key)?,
+            migration: None,
+            interaction: Some(interaction),
+        };
+        Ok((key, value))
+    }
+
+    /// Stores a value about our own account under a key that is not ours, on the peers the closest to that key.
+    /// Unlike [`Node::provide`], we might not be connected to these peers, so this must not be called while holding the lock on our account.
+    pub(super) async fn announce(&self, key: KeyID, value: DhtValue) -> Result<(), rsa::errors::Error> {
+        let packet = Packet::StoreDhtValue(StoreDhtValuePacket {
+            key_id: key.clone(),
+            value: value.clone().sign(&self.rsa_public_key, &self.rsa_private_key)?,
+        });
+        self.dht.set(key.clone(), value).await;
+        let peers = self.closest_peers(&key).await;
+        futures::future::join_all(peers.into_iter().map(|provider| async {
+            // Reaching the peer times out by itself, and the connection must be released even if sending does not complete
+            match self.reach_provider(provider).await {
+                Ok((peer_id, temporary)) => {
+                    if timeout(Duration::from_secs(10), self.connections.send_packet(&peer_id, packet.clone())).await.is_err() {
+                        debug!(self.ll, "Timed out storing a value on {}", peer_id);
+                    }
+                    self.release_provider(peer_id, temporary).await;
+                }
+                Err(e) => debug!(self.ll, "Could not reach a peer close to {}: {:?}", key, e),
+            }
+        })).await;
+        Ok(())
+    }
+
+    /// Finds the interactions with a post, from the accounts that announced them.
+    /// Only accounts we [trust](Node::trust_graph) are fetched.
+    pub async fn interactions(&self, target: &PostRef) -> Vec<(PeerID, Interaction)> {
+        let mut interactions = Vec::new();
+        for (actor, snapshot) in self.interacting_accounts(target).await {
+            for interaction in Vec::from(snapshot.interactions) {
+                match interaction.into_verified() {
+                    Ok((signer, interaction)) if signer == actor && &interaction.target == target => interactions.push((actor.clone(), interaction)),
+                    _ => (),
+                }
+            }
+        }
+        interactions
+    }
+
+    /// Finds the replies to a post, from the trusted accounts that announced them.
+    pub async fn replies(&self, target: &PostRef) -> Vec<(PeerID, Post)> {
+        let mut replies = Vec::new();
+        for (actor, snapshot) in self.interacting_accounts(target).await {
+            for post in Vec::from(snapshot.posts) {
+                match post.into_verified() {
+                    Ok((signer, post)) if signer == actor && post.reply_to.as_ref() == Some(target) => replies.push((actor.clone(), post)),
+                    _ => (),
+                }
+            }
+        }
+        replies
+    }
+
+    /// Downloads the accounts that announced an interaction with a post, leaving out those we don't trust.
+    async fn interacting_accounts(&self, target: &PostRef) -> Vec<(PeerID, AccountDataSnapshot)> {
+        let trust = self.trust_graph().await;
+        let values = self.dht_lookup_announcements(target.interactions_key()).await;
+        let actors: BTreeSet<PeerID> = values.into_iter().map(|value| value.provider).filter(|actor| trust.shows(actor)).collect();
+
+        let mut accounts = Vec::new();
+        for actor in actors {
+            match self.fetch_account(actor.clone()).await {
+                Ok((_, snapshot)) => accounts.push((actor, snapshot)),
+                Err(e) => warn!(self.ll, "Failed to fetch interactions of {}: {}", actor, e),
+            }
+        }
+        accounts
+    }
+
+    pub(super) async fn publish_locked(&self, published: &mut Option<(AccountSnapshotDescriptor, AccountData)>, account: AccountData) -> Result<AccountSnapshotDescriptor, rsa::errors::Error> {
+        let (desc, signed_desc) = self.store_version(published, account).await?;
+        self.provide(self.peer_id.clone(), signed_desc).await?;
+        Ok(desc)
+    }
+
+    /// Serves a new version of our account in place of the previous one.
+    async fn store_version(&self, published: &mut Option<(AccountSnapshotDescriptor, AccountData)>, account: AccountData) -> Result<(AccountSnapshotDescriptor, SignedData<AccountSnapshotDescriptor>), rsa::errors::Error> {
+        // Descriptors with the same timestamp would not replace each other
+        let now = unix_timestamp();
+        let timestamp = match published.as_ref() {
+            Some((desc, _)) => now.max(desc.timestamp + 1),
+            None => now,
+        };
+        let snapshot = account.snapshot();
+        let desc = AccountSnapshotDescriptor::new(timestamp, &snapshot);
+        let signed_desc = desc.clone().sign(&self.rsa_public_key, &self.rsa_private_key)?;
+
+        self.snapshots.insert(snapshot).await;
+        self.dht.remember_backup(self.peer_id.clone(), desc.backup_peer_id.clone()).await;
+        if let Some((old_desc, _)) = published.replace((desc.clone(), account)) {
+            if old_desc.hash != desc.hash {
+                self.snapshots.remove(&old_desc.hash).await;
+            }
+        }
+        debug!(self.ll, "Published account version {}", timestamp);
+
+        Ok((desc, signed_desc))
+    }
+
+    /// Announces ourselves as a provider of an account version, in our DHT store and in the stores of the peers the closest to the account.
+    pub(super) async fn provide(&self, key: KeyID, desc: SignedData<AccountSnapshotDescriptor>) -> Result<(), rsa::errors::Error> {
+        let (value, packet) = self.provider_value(key.clone(), desc)?;
+        self.dht.set(key.clone(), value).await;
+        self.send_to_closest(&key, packet).await;
+        Ok(())
+    }
+
+    /// Our provider record for an account version, and the packet storing it.
+    fn provider_value(&self, key: KeyID, desc: SignedData<AccountSnapshotDescriptor>) -> Result<(DhtValue, Packet), rsa::errors::Error> {
+        let value = DhtValue {
+            provider: self.peer_id.clone(),
+            cached_addr: Some(self.addr.clone()),
+            account_snapshot_desc: desc,
+            migration: None,
+            interaction: None,
+        };
+        let packet = Packet::StoreDhtValue(StoreDhtValuePacket {
+            key_id: key,
+            value: value.clone().sign(&self.rsa_public_key, &self.rsa_private_key)?,
+        });
+        Ok((value, packet))
+    }
+
+    /// Stops announcing ourselves as a provider of an account, on the same peers [`Node::provide`] did.
+    pub(super) async fn unprovide(&self, key: KeyID, timestamp: u64) -> Result<(), rsa::errors::Error> {
+        let withdrawal = DhtWithdrawal { key_id: key.clone(), timestamp };
+        let packet = Packet::WithdrawDhtValue(WithdrawDhtValuePacket {
+            withdrawal: withdrawal.sign(&self.rsa_public_key, &self.rsa_private_key)?,
+        });
+        self.dht.withdraw(&key, &self.peer_id, timestamp).await;
+        self.send_to_closest(&key, packet).await;
+        Ok(())
+    }
+
+    /// Sends a packet to the peers we are connected to that are the closest to a key.
+    /// The owner of the key is reached even if we are not connected to it, as lookups end on it and it relays the packet to its own closest peers.
+    async fn send_to_closest(&self, key: &KeyID, packet: Packet) {
+        if key == &self.peer_id {
+            self.send_own_value(None, packet).await;
+            return;
+        }
+        let peers = self.send_to_closest_connected(key, None, packet.clone()).await;
+        if peers.contains(key) {
+            return;
+        }
+
+        let addr = match self.find_peer(key.clone()).await {
+            Some(addr) => addr,
+            None => {
+                debug!(self.ll, "Could not find {} to send it {}", key, packet.kind());
+                return;
+            }
+        };
+        match self.reach_provider((key.clone(), addr)).await {
+            Ok((peer_id, temporary)) => {
+                self.connections.send_packet(&peer_id, packet).await;
+                self.release_provider(peer_id, temporary).await;
+            }
+            Err(e) => debug!(self.ll, "Could not reach {}: {:?}", key, e),
+        }
+    }
+
+    /// Sends a packet about our account to our closest peers.
+    /// Peers we sent previous values to also get it, as they would otherwise keep serving outdated versions once other peers become closer.
+    async fn send_own_value(&self, except: Option<&PeerID>, packet: Packet) {
+        let peers = self.send_to_closest_connected(&self.peer_id, except, packet.clone()).await;
+        let connected = self.connections.peers().await;
+        let mut stores = self.own_value_stores.lock().await;
+        for peer_id in stores.iter().filter(|peer_id| !peers.contains(peer_id) && connected.contains(peer_id) && Some(*peer_id) != except) {
+            self.connections.send_packet(peer_id, packet.clone()).await;
+        }
+        stores.extend(peers);
+    }
+
+    /// Returns the peers the packet was sent to.
+    async fn send_to_closest_connected(&self, key: &KeyID, except: Option<&PeerID>, packet: Packet) -> Vec<PeerID> {
+        let mut peers = self.connections.peers().await;
+        peers.retain(|peer_id| Some(peer_id) != except);
+        peers.sort_by_key(|peer_id| peer_id.distance(key));
+        peers.truncate(self.config.bucket_size);
+        for peer_id in &peers {
+            self.connections.send_packet(peer_id, packet.clone()).await;
+        }
+        peers
+    }
+
+    /// Stores a value a peer announced itself as the provider of.
+    /// The descriptor must be signed by the owner of the key, or by the provider itself.
+    /// See [`Node::check_account_value`] for the rules of account recovery.
+    pub(super) async fn on_store_dht_value(&self, n: &PeerID, p: &StoreDhtValuePacket) {
+        let value = match p.value.clone().into_verified() {
+            Ok((signer, value)) if &signer == n && &value.provider == n => value,
+            // Relayed by the owner of the key
+            Ok((signer, value)) if n == &p.key_id && signer == value.provider => value,
+            _ => {
+                warn!(self.ll, "Refusing to store a value that was not signed by its provider");
+                return;
+            }
+        };
+        // Providers can also announce their own account under keys of migrated accounts and of posts they interacted with
+        let desc_signer = match value.account_snapshot_desc.verify() {
+            Ok(signer) if signer == p.key_id => signer,
+            Ok(signer) if signer == value.provider && (value.migration.is_some() || value.proves_interaction(&p.key_id)) => signer,
+            _ => {
+                warn!(self.ll, "Refusing to store a descriptor that does not belong under {}", p.key_id);
+                return;
+            }
+        };
+        if !self.check_account_value(&p.key_id, &value, &desc_signer).await {
+            return;
+        }
+        if !self.dht.set(p.key_id.clone(), value).await {
+            trace!(self.ll, "Ignored outdated value for {}", p.key_id);
+            return;
+        }
+
+        // Values about our account are relayed to the peers that store our own values
+        if p.key_id == self.peer_id {
+            self.send_own_value(Some(n), Packet::StoreDhtValue(p.clone())).await;
+        }
+    }
+
+    /// Forgets a value on behalf of its provider.
+    /// Peers that had the value relay the withdrawal to all their peers, as stores drift with connections and withdrawals are signed anyway.
+    pub(super) async fn on_withdraw_dht_value(&self, n: &PeerID, p: &WithdrawDhtValuePacket) {
+        let (provider, withdrawal) = match p.withdrawal.clone().into_verified() {
+            Ok(verified) => verified,
+            Err(_) => {
+                warn!(self.ll, "Refusing a withdrawal with an invalid signature");
+                return;
+            }
+        };
+        if self.dht.withdraw(&withdrawal.key_id, &provider, withdrawal.timestamp).await {
+            for peer_id in self.connections.peers().await.into_iter().filter(|peer_id| peer_id != n && peer_id != &provider) {
+                self.connections.send_packet(&peer_id, Packet::WithdrawDhtValue(p.clone())).await;
+            }
+        }
+    }
+}
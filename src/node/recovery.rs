@@ -0,0 +1,169 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use super::dht::merge_announcements;
+
+/// For when we cannot take over an account.
+#[derive(Debug)]
+pub enum MigrationError {
+    InvalidSignature,
+    /// The migration moves the account to another key
+    WrongTarget,
+    /// The new account must keep the backup that signed the migration
+    BackupMismatch,
+    Rsa(rsa::errors::Error),
+}
+
+impl From<rsa::errors::Error> for MigrationError {
+    fn from(e: rsa::errors::Error) -> Self {
+        MigrationError::Rsa(e)
+    }
+}
+
+/// The backup of an account, if all the descriptors its owner signed agree on it.
+fn agreed_backup(key: &KeyID, values: &[DhtValue]) -> Option<PeerID> {
+    let mut backups = values.iter()
+        .filter(|value| value.migration.is_none())
+        .filter_map(|value| value.account_snapshot_desc.clone().into_verified().ok())
+        .filter(|(signer, _)| signer == key)
+        .map(|(_, desc)| desc.backup_peer_id);
+    let backup = backups.next()?;
+    match backups.all(|other| other == backup) {
+        true => Some(backup),
+        false => None,
+    }
+}
+
+/// The backup most stores agree on, if more than half of the stores that know the account do.
+/// Stores keep the first backup they saw, so a compromised primary key can only mislead stores that had not seen the account yet.
+fn quorum_backup(key: &KeyID, stores: &[Vec<DhtValue>]) -> Option<PeerID> {
+    let opinions: Vec<PeerID> = stores.iter().filter_map(|values| agreed_backup(key, values)).collect();
+    let mut counts: BTreeMap<&PeerID, usize> = BTreeMap::new();
+    for backup in &opinions {
+        *counts.entry(backup).or_default() += 1;
+    }
+    let (backup, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    match count * 2 > opinions.len() {
+        true => Some(backup.clone()),
+        false => None,
+    }
+}
+
+/// What we found out about the migrations of an account.
+#[derive(Debug, Clone)]
+pub enum ResolvedAccount {
+    /// Migrations are final, so this is never looked up again
+    Migrated(PeerID),
+    /// Looked up again after [`MIGRATION_CHECK_INTERVAL`]
+    NotMigrated { checked_at: Instant },
+}
+
+/// Returns the migration of a value if it moves the account of the key to the provider, and was signed by the backup.
+fn valid_migration(key: &KeyID, value: &DhtValue, backup: Option<&PeerID>) -> Option<Migration> {
+    let (signer, migration) = value.migration.clone()?.into_verified().ok()?;
+    let desc_signer = value.account_snapshot_desc.verify().ok()?;
+    match Some(&signer) == backup && &migration.from == key && migration.to == value.provider && desc_signer == value.provider {
+        true => Some(migration),
+        false => None,
+    }
+}
+
+impl Node {
+    /// Signs a migration as the backup of an account.
+    pub fn sign_migration(&self, from: PeerID, to: PeerID) -> Result<SignedData<Migration>, rsa::errors::Error> {
+        Migration::new(from, to).sign(&self.rsa_public_key, &self.rsa_private_key)
+    }
+
+    /// Publishes an account as the new primary key of a migrated account, and announces the migration under the previous key.
+    pub async fn migrate_account(&self, migration: SignedData<Migration>, account: AccountData) -> Result<AccountSnapshotDescriptor, MigrationError> {
+        let (signer, data) = migration.clone().into_verified().map_err(|_| MigrationError::InvalidSignature)?;
+        if data.to != self.peer_id {
+            return Err(MigrationError::WrongTarget);
+        }
+        if account.backup_peer_id != signer {
+            return Err(MigrationError::BackupMismatch);
+        }
+
+        let desc = self.publish_account(account).await?;
+        let value = DhtValue {
+            provider: self.peer_id.clone(),
+            cached_addr: Some(self.addr.clone()),
+            account_snapshot_desc: desc.clone().sign(&self.rsa_public_key, &self.rsa_private_key)?,
+            migration: Some(migration),
+            interaction: None,
+        };
+        self.announce(data.from, value).await?;
+        Ok(desc)
+    }
+
+    /// Follows the migrations of an account to its current primary key.
+    /// Migrations must be signed by the backup of the account, as we know it or as agreed on by most of the peers storing it.
+    /// Results are [cached](Node::resolved_accounts), so that fetching an account doesn't always look up its migrations.
+    pub async fn resolve_account(&self, peer_id: PeerID) -> PeerID {
+        let mut current = peer_id;
+        for _ in 0..MAX_MIGRATION_HOPS {
+            match self.resolved_accounts.lock().await.get(&current) {
+                Some(ResolvedAccount::Migrated(to)) => {
+                    current = to.clone();
+                    continue;
+                }
+                Some(ResolvedAccount::NotMigrated { checked_at }) if checked_at.elapsed() < MIGRATION_CHECK_INTERVAL => break,
+                _ => (),
+            }
+
+            let stores = self.dht_lookup_stores(&current).await;
+            let backup = match self.known_backup(&current).await {
+                Some(backup) => Some(backup),
+                None => quorum_backup(&current, &stores),
+            };
+            let values = merge_announcements(&current, stores);
+            let migration = values.iter().filter_map(|value| valid_migration(&current, value, backup.as_ref())).max_by_key(|migration| migration.timestamp);
+            match migration {
+                Some(migration) => {
+                    debug!(self.ll, "Account {} migrated to {}", current, migration.to);
+                    self.resolved_accounts.lock().await.insert(current, ResolvedAccount::Migrated(migration.to.clone()));
+                    current = migration.to;
+                }
+                None => {
+                    self.resolved_accounts.lock().await.insert(current.clone(), ResolvedAccount::NotMigrated { checked_at: Instant::now() });
+                    break;
+                }
+            }
+        }
+        current
+    }
+
+    /// The backup of an account whose updates we already validated.
+    async fn known_backup(&self, peer_id: &PeerID) -> Option<PeerID> {
+        if let Some(cached) = self.timeline_cache.lock().await.get(peer_id) {
+            return Some(cached.desc.backup_peer_id.clone());
+        }
+        self.mirrors.lock().await.get(peer_id).map(|mirror| mirror.desc.backup_peer_id.clone())
+    }
+
+    /// Enforces account recovery on a value we are asked to store.
+    /// Descriptors of the owner must keep the [first backup we saw](DhtStore::backup), and migrations must be signed by it.
+    /// Once an account migrated, its owner can no longer update it, as its key might be compromised.
+    pub(super) async fn check_account_value(&self, key: &KeyID, value: &DhtValue, desc_signer: &PeerID) -> bool {
+        let stored = self.dht.get(key).await.unwrap_or_default();
+        let backup = self.dht.backup(key).await;
+        if value.migration.is_some() {
+            if valid_migration(key, value, backup.as_ref()).is_none() {
+                warn!(self.ll, "Refusing a migration of {} that was not signed by its backup", key);
+                return false;
+            }
+        } else if desc_signer == key {
+            if matches!(&backup, Some(backup) if backup != &value.account_snapshot_desc.data_unverified().backup_peer_id) {
+                warn!(self.ll, "Refusing an update of {} that changes its backup", key);
+                return false;
+            }
+            if stored.iter().any(|value| value.migration.is_some()) {
+                warn!(self.ll, "Refusing an update of {} as it migrated", key);
+                return false;
+            }
+            self.dht.remember_backup(key.clone(), value.account_snapshot_desc.data_unverified().backup_peer_id.clone()).await;
+        }
+        true
+    }
+}
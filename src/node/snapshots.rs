@@ -0,0 +1,171 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use tracing::Instrument;
+
+/// Account snapshots we provide to other peers, by root hash.
+#[derive(Default)]
+pub struct SnapshotStore {
+    snapshots: Mutex<BTreeMap<[u8; 32], AccountDataSnapshot>>,
+}
+
+impl SnapshotStore {
+    /// Returns the root hash of the snapshot.
+    pub async fn insert(&self, snapshot: AccountDataSnapshot) -> [u8; 32] {
+        let hash = *Hash::hash(&snapshot);
+        self.snapshots.lock().await.insert(hash, snapshot);
+        hash
+    }
+
+    pub async fn get(&self, hash: &[u8; 32]) -> Option<AccountDataSnapshot> {
+        self.snapshots.lock().await.get(hash).cloned()
+    }
+
+    pub async fn remove(&self, hash: &[u8; 32]) -> Option<AccountDataSnapshot> {
+        self.snapshots.lock().await.remove(hash)
+    }
+}
+
+/// For when a snapshot cannot be downloaded from a provider.
+#[derive(Debug)]
+pub enum FetchSnapshotError {
+    /// We could not connect to the provider
+    Unreachable,
+    Timeout,
+    /// The provider does not have the snapshot
+    Unavailable,
+    /// The snapshot does not match its descriptor
+    HashMismatch,
+}
+
+/// For when no valid snapshot of an account could be downloaded.
+#[derive(Debug)]
+pub enum FetchAccountError {
+    /// No descriptor was found in the DHT
+    NotFound,
+    /// No provider gave us a valid snapshot
+    NoProvider,
+}
+
+impl std::fmt::Display for FetchAccountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchAccountError::NotFound => write!(f, "No descriptor found in the DHT"),
+            FetchAccountError::NoProvider => write!(f, "No provider gave a valid snapshot"),
+        }
+    }
+}
+
+impl Node {
+    /// Answers a [`FetchSnapshotPacket`] from the snapshots we provide.
+    pub async fn prepare_fetch_snapshot_response(&self, p: &FetchSnapshotPacket) -> FetchSnapshotRespPacket {
+        let snapshot = self.snapshots.get(&p.hash).await.map(|mut snapshot| {
+            snapshot.followers = snapshot.followers.select(&p.followers);
+            snapshot.following = snapshot.following.select(&p.following);
+            snapshot.posts = snapshot.posts.select(&p.posts);
+            snapshot.interactions = snapshot.interactions.select(&p.interactions);
+            snapshot
+        });
+        FetchSnapshotRespPacket {
+            request_id: p.request_id,
+            snapshot,
+        }
+    }
+
+    /// Downloads a snapshot from a provider and checks it matches the descriptor.
+    /// The address of the provider is looked up if not given.
+    /// Snapshots we provide ourselves are read from our [`SnapshotStore`].
+    pub async fn fetch_snapshot(
+        &self,
+        (provider, addr): (PeerID, Option<String>),
+        desc: &AccountSnapshotDescriptor,
+        followers: SegmentSelection,
+        following: SegmentSelection,
+        posts: SegmentSelection,
+        interactions: SegmentSelection,
+    ) -> Result<AccountDataSnapshot, FetchSnapshotError> {
+        use FetchSnapshotError::*;
+
+        debug!(self.ll, "Fetching snapshot from {}", provider);
+        let request_id = self.snapshot_req_counter.next();
+        let request = FetchSnapshotPacket {
+            request_id,
+            hash: desc.hash,
+            followers,
+            following,
+            posts,
+            interactions,
+        };
+
+        let resp = if provider == self.peer_id {
+            Ok(self.prepare_fetch_snapshot_response(&request).await)
+        } else {
+            let addr = match addr {
+                Some(addr) => addr,
+                None => self.find_peer(provider.clone()).await.ok_or(Unreachable)?,
+            };
+            let (peer_id, temporary) = self.reach_provider((provider, addr)).await.map_err(|e| {
+                warn!(self.ll, "Could not reach snapshot provider: {:?}", e);
+                Unreachable
+            })?;
+
+            let resp = async {
+                let resp_receiver = self.on_fetch_snapshot_resp_packet.listen().await;
+                self.connections.send_packet(&peer_id, Packet::FetchSnapshot(request)).await;
+                loop {
+                    let (n, p) = resp_receiver.recv().await.unwrap();
+                    if p.request_id == request_id && n == peer_id {
+                        trace!(self.ll, "Got response");
+                        break p;
+                    }
+                }
+            }.instrument(tracing::error_span!("request", remote = %peer_id, request_id));
+            let resp = timeout(Duration::from_secs(10), resp).await;
+            self.release_provider(peer_id, temporary).await;
+            resp
+        };
+
+        let snapshot = resp.map_err(|_| Timeout)?.snapshot.ok_or(Unavailable)?;
+        if !desc.matches(&snapshot) {
+            return Err(HashMismatch);
+        }
+        Ok(snapshot)
+    }
+
+    /// Finds the latest descriptor of an account in the DHT and downloads the whole snapshot from one of its providers.
+    /// [Migrations](Node::resolve_account) are followed, so the account might belong to another key.
+    pub async fn fetch_account(&self, peer_id: PeerID) -> Result<(AccountSnapshotDescriptor, AccountDataSnapshot), FetchAccountError> {
+        let peer_id = self.resolve_account(peer_id).await;
+        let values = self.account_descriptors(peer_id).await?;
+        if values.is_empty() {
+            return Err(FetchAccountError::NotFound);
+        }
+        let (desc, snapshot) = self.fetch_described_snapshot(values).await?;
+        // The signature was checked during the lookup
+        Ok((desc.data_unverified().clone(), snapshot))
+    }
+
+    /// Looks up the descriptors of an account in the DHT, newest first, along with the values announcing them.
+    pub(super) async fn account_descriptors(&self, peer_id: PeerID) -> Result<Vec<(AccountSnapshotDescriptor, DhtValue)>, FetchAccountError> {
+        // Values returned by lookups are signed by the owner of the account
+        let values = self.dht_lookup(peer_id).await.ok_or(FetchAccountError::NotFound)?;
+        let mut values: Vec<(AccountSnapshotDescriptor, DhtValue)> = values.into_iter().filter_map(|value| {
+            let (_, desc) = value.account_snapshot_desc.clone().into_verified().ok()?;
+            Some((desc, value))
+        }).collect();
+        values.sort_by_key(|(desc, _)| std::cmp::Reverse(desc.timestamp));
+        Ok(values)
+    }
+
+    /// Downloads the first snapshot a provider gives us, trying descriptors in order.
+    pub(super) async fn fetch_described_snapshot(&self, values: Vec<(AccountSnapshotDescriptor, DhtValue)>) -> Result<(SignedData<AccountSnapshotDescriptor>, AccountDataSnapshot), FetchAccountError> {
+        for (desc, value) in values {
+            match self.fetch_snapshot((value.provider, value.cached_addr), &desc, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All).await {
+                Ok(snapshot) => return Ok((value.account_snapshot_desc, snapshot)),
+                Err(e) => warn!(self.ll, "Failed to fetch snapshot: {:?}", e),
+            }
+        }
+        Err(FetchAccountError::NoProvider)
+    }
+}
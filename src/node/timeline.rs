@@ -0,0 +1,222 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// The verified content of an account that appears in our timeline, as of a snapshot.
+#[derive(Debug, Clone)]
+pub struct CachedAccount {
+    /// Owner of the account, which is not the key of the cache if the account [migrated](Node::resolve_account)
+    pub peer_id: PeerID,
+    pub desc: AccountSnapshotDescriptor,
+    /// Used to build the [trust graph](Node::trust_graph)
+    pub following: Vec<PeerID>,
+    pub posts: Vec<Post>,
+    pub interactions: Vec<Interaction>,
+}
+
+/// Why a post is in the timeline.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "type", content = "by", rename_all = "kebab-case")]
+pub enum TimelineReason {
+    /// Posted by a followed account
+    Posted,
+    Liked(PeerID),
+    Replied(PeerID),
+    Reshared(PeerID),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TimelineEntry {
+    /// Date of the post, or of the interaction that brought it
+    pub timestamp: u64,
+    pub reference: PostRef,
+    pub post: Post,
+    pub reason: TimelineReason,
+}
+
+/// Position in the timeline, which is ordered from the newest entry to the oldest.
+/// Formatted as `timestamp-author-hash`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimelineCursor {
+    pub timestamp: u64,
+    pub author: PeerID,
+    pub hash: [u8; 32],
+}
+
+/// A page of the timeline, with the cursor to pass to get the next one.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TimelinePage {
+    pub entries: Vec<TimelineEntry>,
+    /// `None` if this is the last page
+    pub next: Option<TimelineCursor>,
+}
+
+impl TimelineEntry {
+    pub fn cursor(&self) -> TimelineCursor {
+        TimelineCursor {
+            timestamp: self.timestamp,
+            author: self.reference.author.clone(),
+            hash: self.reference.hash,
+        }
+    }
+}
+
+impl std::fmt::Display for TimelineCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.timestamp, self.author, PeerID::from(self.hash))
+    }
+}
+
+impl std::str::FromStr for TimelineCursor {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('-');
+        let (timestamp, author, hash) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(timestamp), Some(author), Some(hash), None) => (timestamp, author, hash),
+            _ => return Err("Cursor should be formatted as timestamp-author-hash"),
+        };
+        Ok(TimelineCursor {
+            timestamp: timestamp.parse().map_err(|_| "Invalid cursor timestamp")?,
+            author: author.parse().map_err(|_| "Invalid cursor author")?,
+            hash: *hash.parse::<PeerID>().map_err(|_| "Invalid cursor hash")?.bytes(),
+        })
+    }
+}
+
+impl serde::Serialize for TimelineCursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TimelineCursor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Node {
+    /// Returns up to `limit` entries of our home timeline, starting after `cursor`.
+    ///
+    /// The timeline merges the posts of the accounts we follow with the posts they liked, replied to or reshared.
+    /// Posts of accounts outside our [trust graph](Node::trust_graph), blocked or muted are left out.
+    /// Accounts are refreshed when the first page is requested, and only downloaded again if their hash changed.
+    pub async fn timeline(&self, cursor: Option<TimelineCursor>, limit: usize) -> TimelinePage {
+        if cursor.is_none() || self.timeline_cache.lock().await.is_empty() {
+            self.refresh_timeline().await;
+        }
+
+        let trust = self.trust_graph().await;
+        let mut entries = self.timeline_entries(&trust).await;
+        if let Some(cursor) = &cursor {
+            entries.retain(|entry| &entry.cursor() < cursor);
+        }
+        entries.truncate(limit);
+        let next = match entries.len() == limit {
+            true => entries.last().map(|entry| entry.cursor()),
+            false => None,
+        };
+        TimelinePage { entries, next }
+    }
+
+    /// Updates the accounts we follow, then the authors of the posts they interacted with, if we trust them.
+    /// Accounts that no longer appear in the timeline are forgotten.
+    pub async fn refresh_timeline(&self) {
+        let blocked = self.moderation.lock().await.blocked.clone();
+        let following: BTreeSet<PeerID> = self.following().await.into_iter().filter(|peer_id| !blocked.contains(peer_id)).collect();
+        futures::future::join_all(following.iter().map(|peer_id| self.refresh_cached_account(peer_id.clone()))).await;
+
+        let trust = self.trust_graph().await;
+        let mut authors = BTreeSet::new();
+        for (_, cached) in self.timeline_cache.lock().await.iter().filter(|(peer_id, _)| trust.shows(peer_id)) {
+            authors.extend(cached.interactions.iter().map(|interaction| interaction.target.author.clone()));
+        }
+        let authors: Vec<PeerID> = authors.into_iter().filter(|author| !following.contains(author) && trust.shows(author)).collect();
+        futures::future::join_all(authors.iter().map(|peer_id| self.refresh_cached_account(peer_id.clone()))).await;
+
+        self.timeline_cache.lock().await.retain(|peer_id, _| following.contains(peer_id) || authors.contains(peer_id));
+    }
+
+    /// Downloads an account into the timeline cache, unless the latest snapshot has the hash of the cached one.
+    /// Migrations are followed, but updates changing the backup of the account are refused.
+    /// On failure, the cached version is kept.
+    pub(super) async fn refresh_cached_account(&self, peer_id: PeerID) {
+        let owner = self.resolve_account(peer_id.clone()).await;
+        let values = match self.account_descriptors(owner.clone()).await {
+            Ok(values) if !values.is_empty() => values,
+            Ok(_) | Err(_) => {
+                warn!(self.ll, "No descriptor found for {} while refreshing the timeline", peer_id);
+                return;
+            }
+        };
+        let latest = values[0].0.clone();
+        if let Some(cached) = self.timeline_cache.lock().await.get_mut(&peer_id) {
+            if cached.desc.backup_peer_id != latest.backup_peer_id {
+                warn!(self.ll, "Refusing an update of {} that changes its backup", peer_id);
+                return;
+            }
+            if cached.peer_id == owner && cached.desc.hash == latest.hash {
+                trace!(self.ll, "Timeline account {} is unchanged", peer_id);
+                cached.desc = latest;
+                return;
+            }
+        }
+
+        let (desc, snapshot) = match self.fetch_described_snapshot(values).await {
+            Ok(update) => update,
+            Err(e) => {
+                warn!(self.ll, "Failed to refresh {} for the timeline: {}", peer_id, e);
+                return;
+            }
+        };
+        let signed_by_owner = |signer: &PeerID| signer == &owner;
+        let posts = Vec::from(snapshot.posts).into_iter().filter_map(|post| post.into_verified().ok().filter(|(signer, _)| signed_by_owner(signer)).map(|(_, post)| post)).collect();
+        let interactions = Vec::from(snapshot.interactions).into_iter().filter_map(|interaction| interaction.into_verified().ok().filter(|(signer, _)| signed_by_owner(signer)).map(|(_, interaction)| interaction)).collect();
+        let following = Vec::from(snapshot.following).into_iter().map(|mention| mention.peer_id).collect();
+        let cached = CachedAccount { peer_id: owner.clone(), desc: desc.data_unverified().clone(), following, posts, interactions };
+        self.timeline_cache.lock().await.insert(peer_id, cached);
+    }
+
+    /// Builds the whole timeline from the cache, newest first.
+    /// A post appears only once, with its most recent reason.
+    async fn timeline_entries(&self, trust: &TrustGraph) -> Vec<TimelineEntry> {
+        let cache = self.timeline_cache.lock().await;
+        let find_post = |target: &PostRef| {
+            cache.get(&target.author)?.posts.iter().find(|post| *Hash::hash(*post) == target.hash).cloned()
+        };
+
+        let mut entries: BTreeMap<PostRef, TimelineEntry> = BTreeMap::new();
+        let mut add = |entry: TimelineEntry| match entries.get(&entry.reference) {
+            Some(existing) if existing.timestamp >= entry.timestamp => (),
+            _ => { entries.insert(entry.reference.clone(), entry); },
+        };
+        for peer_id in trust.direct.iter().filter(|peer_id| trust.shows(peer_id)) {
+            let cached = match cache.get(peer_id) {
+                Some(cached) => cached,
+                None => continue,
+            };
+            for post in &cached.posts {
+                add(TimelineEntry { timestamp: post.timestamp, reference: post.reference(cached.peer_id.clone()), post: post.clone(), reason: TimelineReason::Posted });
+            }
+            for interaction in cached.interactions.iter().filter(|interaction| trust.shows(&interaction.target.author)) {
+                let post = match find_post(&interaction.target) {
+                    Some(post) => post,
+                    None => continue,
+                };
+                let reason = match interaction.kind {
+                    InteractionKind::Like => TimelineReason::Liked(peer_id.clone()),
+                    InteractionKind::Reply { .. } => TimelineReason::Replied(peer_id.clone()),
+                    InteractionKind::Reshare => TimelineReason::Reshared(peer_id.clone()),
+                };
+                add(TimelineEntry { timestamp: interaction.timestamp, reference: interaction.target.clone(), post, reason });
+            }
+        }
+
+        let mut entries: Vec<TimelineEntry> = entries.into_values().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.cursor()));
+        entries
+    }
+}
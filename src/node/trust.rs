@@ -0,0 +1,95 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+
+/// Local moderation choices, which override the trust graph.
+#[derive(Debug, Clone, Default)]
+pub struct ModerationLists {
+    /// Accounts we never fetch, show, or trust the follows of
+    pub blocked: BTreeSet<PeerID>,
+    /// Accounts we don't show, but still trust the follows of
+    pub muted: BTreeSet<PeerID>,
+}
+
+/// Who we trust: the accounts we follow, and the accounts they follow.
+/// Content from outside this radius is neither fetched nor shown.
+#[derive(Debug, Clone)]
+pub struct TrustGraph {
+    pub me: PeerID,
+    /// Accounts we follow
+    pub direct: BTreeSet<PeerID>,
+    /// Accounts followed by accounts we follow, but not by us
+    pub indirect: BTreeSet<PeerID>,
+    pub lists: ModerationLists,
+}
+
+impl TrustGraph {
+    /// Returns 0 for ourselves, 1 for accounts we follow and 2 for the accounts they follow.
+    pub fn depth(&self, peer_id: &PeerID) -> Option<u8> {
+        if peer_id == &self.me {
+            Some(0)
+        } else if self.lists.blocked.contains(peer_id) {
+            None
+        } else if self.direct.contains(peer_id) {
+            Some(1)
+        } else if self.indirect.contains(peer_id) {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the account is within our trust radius, even if muted.
+    pub fn trusts(&self, peer_id: &PeerID) -> bool {
+        self.depth(peer_id).is_some()
+    }
+
+    /// Whether content of the account may be fetched and shown.
+    pub fn shows(&self, peer_id: &PeerID) -> bool {
+        self.trusts(peer_id) && !self.lists.muted.contains(peer_id)
+    }
+}
+
+impl Node {
+    /// Builds our trust graph from our following list and the following lists of the accounts we follow.
+    /// Lists of followed accounts are read from the [timeline cache](Node::timeline_cache), and downloaded if missing.
+    pub async fn trust_graph(&self) -> TrustGraph {
+        let lists = self.moderation.lock().await.clone();
+        let direct: BTreeSet<PeerID> = self.following().await.into_iter().filter(|peer_id| !lists.blocked.contains(peer_id)).collect();
+
+        let cached: BTreeSet<PeerID> = self.timeline_cache.lock().await.keys().cloned().collect();
+        futures::future::join_all(direct.difference(&cached).map(|peer_id| self.refresh_cached_account(peer_id.clone()))).await;
+
+        let mut indirect = BTreeSet::new();
+        let cache = self.timeline_cache.lock().await;
+        for peer_id in &direct {
+            if let Some(cached) = cache.get(peer_id) {
+                indirect.extend(cached.following.iter().filter(|followed| !direct.contains(followed)).cloned());
+            }
+        }
+        indirect.remove(&self.peer_id);
+
+        TrustGraph { me: self.peer_id.clone(), direct, indirect, lists }
+    }
+
+    /// Hides an account and stops trusting the accounts it follows.
+    pub async fn block(&self, peer_id: PeerID) {
+        let mut lists = self.moderation.lock().await;
+        lists.muted.remove(&peer_id);
+        lists.blocked.insert(peer_id);
+    }
+
+    pub async fn unblock(&self, peer_id: &PeerID) -> bool {
+        self.moderation.lock().await.blocked.remove(peer_id)
+    }
+
+    /// Hides an account, but keeps trusting the accounts it follows.
+    pub async fn mute(&self, peer_id: PeerID) {
+        self.moderation.lock().await.muted.insert(peer_id);
+    }
+
+    pub async fn unmute(&self, peer_id: &PeerID) -> bool {
+        self.moderation.lock().await.muted.remove(peer_id)
+    }
+}
@@ -21,6 +21,14 @@ pub enum Packet {
     FindPeer(FindPeerPacket),
     FindPeerResp(FindPeerRespPacket),
     StoreDhtValue(StoreDhtValuePacket),
+    WithdrawDhtValue(WithdrawDhtValuePacket),
+
+    // Account snapshots
+    FetchSnapshot(FetchSnapshotPacket),
+    FetchSnapshotResp(FetchSnapshotRespPacket),
+
+    // Social graph
+    Follow(FollowPacket),
 
     // Utility packets
     Ping(PingPacket),
@@ -28,6 +36,49 @@ pub enum Packet {
     Quit(QuitPacket),
 }
 
+impl Packet {
+    /// Name of the variant, for logs and metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Packet::ProtocolVersion(_) => "ProtocolVersion",
+            Packet::InitRsa(_) => "InitRsa",
+            Packet::InitAes(_) => "InitAes",
+            Packet::Ehlo(_) => "Ehlo",
+            Packet::DiscoverPeers(_) => "DiscoverPeers",
+            Packet::DiscoverPeersResp(_) => "DiscoverPeersResp",
+            Packet::FindDhtValue(_) => "FindDhtValue",
+            Packet::FindDhtValueResp(_) => "FindDhtValueResp",
+            Packet::FindPeer(_) => "FindPeer",
+            Packet::FindPeerResp(_) => "FindPeerResp",
+            Packet::StoreDhtValue(_) => "StoreDhtValue",
+            Packet::WithdrawDhtValue(_) => "WithdrawDhtValue",
+            Packet::FetchSnapshot(_) => "FetchSnapshot",
+            Packet::FetchSnapshotResp(_) => "FetchSnapshotResp",
+            Packet::Follow(_) => "Follow",
+            Packet::Ping(_) => "Ping",
+            Packet::Pong(_) => "Pong",
+            Packet::Quit(_) => "Quit",
+        }
+    }
+
+    /// The id matching this packet with its request or response, if any.
+    /// Ping ids are considered request ids.
+    pub fn request_id(&self) -> Option<u32> {
+        match self {
+            Packet::DiscoverPeers(p) => Some(p.request_id),
+            Packet::DiscoverPeersResp(p) => Some(p.request_id),
+            Packet::FindDhtValue(p) => Some(p.request_id),
+            Packet::FindDhtValueResp(p) => Some(p.request_id),
+            Packet::FindPeer(p) => Some(p.request_id),
+            Packet::FindPeerResp(p) => Some(p.request_id),
+            Packet::FetchSnapshot(p) => Some(p.request_id),
+            Packet::FetchSnapshotResp(p) => Some(p.request_id),
+            Packet::Ping(p) | Packet::Pong(p) => Some(p.ping_id),
+            Packet::ProtocolVersion(_) | Packet::InitRsa(_) | Packet::InitAes(_) | Packet::Ehlo(_) | Packet::StoreDhtValue(_) | Packet::WithdrawDhtValue(_) | Packet::Follow(_) | Packet::Quit(_) => None,
+        }
+    }
+}
+
 /// The protocol version packet.
 /// This is the first packet ever sent by clients.
 /// 
@@ -168,6 +219,47 @@ pub struct StoreDhtValuePacket {
     pub value: SignedData<DhtValue>,
 }
 
+/// Asks to forget a value previously stored with [`StoreDhtValuePacket`].
+#[derive(Protocol, Debug, Clone)]
+pub struct WithdrawDhtValuePacket {
+    /// Signed by the provider of the value.
+    pub withdrawal: SignedData<DhtWithdrawal>,
+}
+
+/// *Request for [`FetchSnapshotRespPacket`]*
+#[derive(Protocol, Debug, Clone)]
+pub struct FetchSnapshotPacket {
+    /// Unique request id used to match the response to the request.
+    pub request_id: u32,
+    /// Root hash of the snapshot, as found in its [`AccountSnapshotDescriptor`].
+    pub hash: [u8; 32],
+    /// Parts of the followers list to include.
+    pub followers: SegmentSelection,
+    /// Parts of the following list to include.
+    pub following: SegmentSelection,
+    /// Parts of the post log to include.
+    pub posts: SegmentSelection,
+    /// Parts of the interactions to include.
+    pub interactions: SegmentSelection,
+}
+
+/// *Response to [`FetchSnapshotPacket`]*
+#[derive(Protocol, Debug, Clone)]
+pub struct FetchSnapshotRespPacket {
+    /// Unique request id used to match the response to the request.
+    pub request_id: u32,
+    /// The snapshot, with unselected segments replaced by their hashes.
+    /// `None` if we don't provide that snapshot.
+    pub snapshot: Option<AccountDataSnapshot>,
+}
+
+/// Tells an account that we started or stopped following it.
+#[derive(Protocol, Debug, Clone)]
+pub struct FollowPacket {
+    /// Signed by the follower.
+    pub record: SignedData<FollowRecord>,
+}
+
 #[derive(Protocol, Debug, Clone, Copy)]
 pub struct PingPacket {
     pub ping_id: u32,
@@ -148,6 +148,12 @@ impl From<&rsa::RsaPublicKey> for PeerID {
     }
 }
 
+impl From<[u8; 32]> for PeerID {
+    fn from(bytes: [u8; 32]) -> Self {
+        PeerID { bytes: Box::new(bytes) }
+    }
+}
+
 use std::cmp::{Ord, PartialOrd, Ordering};
 impl PartialOrd<PeerID> for PeerID {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -183,6 +189,19 @@ impl std::str::FromStr for PeerID {
     }
 }
 
+impl serde::Serialize for PeerID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PeerID {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl protocol::Parcel for PeerID {
     const TYPE_NAME: &'static str = "PeerID";
 
@@ -14,6 +14,8 @@ pub use {
         segmented_array::*,
         util::*,
         hash::*,
+        random::{rng, NodeRng},
+        clock::unix_timestamp,
         error, warn, info, debug, trace, logging::LogLevel,
         connect,
     },
@@ -21,7 +23,7 @@ pub use {
         sync::{Arc, Weak},
         collections::{BTreeMap, BTreeSet},
         cell::UnsafeCell,
-        time::{Duration, Instant},
+        time::Duration,
         default::Default,
         task::{Waker, Poll},
         hint::unreachable_unchecked,
@@ -29,7 +31,7 @@ pub use {
     },
     tokio::{
         io::{AsyncWriteExt, AsyncReadExt},
-        time::{sleep, timeout},
+        time::{sleep, timeout, Instant},
         spawn,
     },
     futures::{future::BoxFuture, FutureExt},
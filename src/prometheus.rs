@@ -0,0 +1,63 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! HTTP endpoint exposing the node metrics to [Prometheus](https://prometheus.io).
+//!
+//! Serves `GET /metrics` with the output of [`Command::Metrics`] in the text exposition format.
+//! Like the [control API](crate::rpc), it only listens on loopback addresses.
+
+use crate::commands::*;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Requests with larger heads are rejected
+const MAX_HEAD_LINES: usize = 100;
+
+async fn handle_connection(stream: impl AsyncRead + AsyncWrite + Unpin, commands: CommandSender) -> std::io::Result<()> {
+    let (r, mut w) = tokio::io::split(stream);
+    let mut lines = BufReader::new(r).lines();
+
+    // Only the request line matters, headers are skipped
+    let request_line = lines.next_line().await?.unwrap_or_default();
+    let mut head_lines = 1;
+    while let Some(line) = lines.next_line().await? {
+        head_lines += 1;
+        if line.is_empty() || head_lines > MAX_HEAD_LINES {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        _ if head_lines > MAX_HEAD_LINES => ("431 Request Header Fields Too Large", String::new()),
+        (Some("GET"), Some("/metrics")) => match commands.run(Command::Metrics).await {
+            Some(CommandOutput::Metrics(metrics)) => ("200 OK", metrics.to_prometheus()),
+            _ => ("503 Service Unavailable", String::from("The node stopped before replying\n")),
+        },
+        (Some("GET"), Some(_)) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    w.write_all(response.as_bytes()).await?;
+    w.shutdown().await
+}
+
+/// Serves the metrics endpoint until an IO error occurs.
+/// Metrics are requested from the node through `commands`.
+pub async fn serve(addr: SocketAddr, commands: CommandSender) -> std::io::Result<()> {
+    if !addr.ip().is_loopback() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the metrics endpoint only listens on loopback addresses"));
+    }
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let commands = commands.clone();
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, commands).await;
+        });
+    }
+}
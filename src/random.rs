@@ -0,0 +1,81 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+#[cfg(not(feature = "test"))]
+pub type NodeRng = rand::rngs::OsRng;
+#[cfg(feature = "test")]
+pub type NodeRng = testing::SimulationRng;
+
+/// Returns the source of randomness nodes should use for everything (keys, nonces, choices...).
+///
+/// This is [`OsRng`](rand::rngs::OsRng), except while testing, where a seeded generator is used so that simulations can be replayed.
+pub fn rng() -> NodeRng {
+    NodeRng::default()
+}
+
+/// While testing, all randomness derives from a single global seed.
+/// Combined with a single-threaded runtime and a paused clock, this makes simulations deterministic.
+#[cfg(feature = "test")]
+pub mod testing {
+    use rand::{RngCore, CryptoRng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use std::sync::Mutex;
+
+    lazy_static::lazy_static!(
+        static ref SIMULATION_RNG: Mutex<ChaCha8Rng> = Mutex::new(ChaCha8Rng::seed_from_u64(0));
+    );
+
+    /// Resets the global generator.
+    /// Should be called before any node is created.
+    pub fn set_seed(seed: u64) {
+        *SIMULATION_RNG.lock().unwrap() = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    /// Reads the seed from the `TEWTA_SEED` environment variable, or picks a random one.
+    /// Set that variable to the seed of a failed run in order to replay it.
+    pub fn seed_from_env() -> u64 {
+        match std::env::var("TEWTA_SEED") {
+            Ok(seed) => seed.parse().expect("TEWTA_SEED should be an unsigned integer"),
+            Err(_) => rand::rngs::OsRng.next_u64(),
+        }
+    }
+
+    /// A handle to the global seeded generator.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct SimulationRng;
+
+    impl RngCore for SimulationRng {
+        fn next_u32(&mut self) -> u32 {
+            SIMULATION_RNG.lock().unwrap().next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            SIMULATION_RNG.lock().unwrap().next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            SIMULATION_RNG.lock().unwrap().fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            SIMULATION_RNG.lock().unwrap().try_fill_bytes(dest)
+        }
+    }
+
+    impl CryptoRng for SimulationRng {}
+}
+
+#[cfg(all(test, feature = "test"))]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn replay_from_seed() {
+        testing::set_seed(42);
+        let first: Vec<u64> = (0..16).map(|_| rng().gen()).collect();
+        testing::set_seed(42);
+        let second: Vec<u64> = (0..16).map(|_| rng().gen()).collect();
+        assert_eq!(first, second);
+    }
+}
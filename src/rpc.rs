@@ -0,0 +1,264 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! Local control API exposing the [`Command`] set over [JSON-RPC 2.0](https://www.jsonrpc.org/specification).
+//!
+//! Messages are newline-delimited JSON objects. Methods are command names in kebab-case, and params are their named arguments:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "method": "peers", "params": {"verbose": true}, "id": 1}
+//! <-- {"jsonrpc": "2.0", "result": {"type": "peers", "value": [...]}, "id": 1}
+//! ```
+//!
+//! Only local endpoints are supported since there is no authentication: anyone who can reach the endpoint controls the node.
+
+use crate::commands::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{net::SocketAddr, path::PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Where the control API listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcEndpoint {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Must be a loopback address
+    Tcp(SocketAddr),
+}
+
+impl Default for RpcEndpoint {
+    #[cfg(unix)]
+    fn default() -> Self {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+        RpcEndpoint::Unix(dir.join("tewta.sock"))
+    }
+
+    #[cfg(not(unix))]
+    fn default() -> Self {
+        RpcEndpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], 7423)))
+    }
+}
+
+impl std::str::FromStr for RpcEndpoint {
+    type Err = String;
+
+    /// Socket addresses are parsed as TCP endpoints, anything else as a Unix socket path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<SocketAddr>() {
+            Ok(addr) if addr.ip().is_loopback() => Ok(RpcEndpoint::Tcp(addr)),
+            Ok(addr) => Err(format!("{addr} is not a loopback address")),
+            #[cfg(unix)]
+            Err(_) => Ok(RpcEndpoint::Unix(PathBuf::from(s))),
+            #[cfg(not(unix))]
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            #[cfg(unix)]
+            RpcEndpoint::Unix(path) => write!(f, "{}", path.display()),
+            RpcEndpoint::Tcp(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    /// Absent for notifications, which get no response
+    id: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+/// For when a call to the control API fails.
+#[derive(Debug)]
+pub enum RpcClientError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// The node replied with an error
+    Rpc(RpcError),
+    /// The connection was closed before we got a response
+    NoResponse,
+}
+
+impl From<std::io::Error> for RpcClientError {
+    fn from(e: std::io::Error) -> Self {
+        RpcClientError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RpcClientError {
+    fn from(e: serde_json::Error) -> Self {
+        RpcClientError::Json(e)
+    }
+}
+
+impl std::fmt::Display for RpcClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RpcClientError::Io(e) => write!(f, "{e}"),
+            RpcClientError::Json(e) => write!(f, "Invalid response: {e}"),
+            RpcClientError::Rpc(e) => write!(f, "{e}"),
+            RpcClientError::NoResponse => write!(f, "Connection closed without response"),
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: impl Into<String>) -> Response {
+    Response {
+        jsonrpc: String::from("2.0"),
+        result: None,
+        error: Some(RpcError { code, message: message.into() }),
+        id,
+    }
+}
+
+/// Returns `None` for notifications.
+async fn handle_request(line: &str, commands: &CommandSender) -> Option<Response> {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) if e.is_data() => return Some(error_response(Value::Null, INVALID_REQUEST, e.to_string())),
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let id = request.id.clone().unwrap_or(Value::Null);
+    if request.jsonrpc != "2.0" {
+        return Some(error_response(id, INVALID_REQUEST, "Only JSON-RPC 2.0 is supported"));
+    }
+
+    let mut command = serde_json::Map::new();
+    command.insert(String::from("method"), Value::String(request.method));
+    if let Some(params) = request.params {
+        command.insert(String::from("params"), params);
+    }
+    let command: Command = match serde_json::from_value(Value::Object(command)) {
+        Ok(command) => command,
+        Err(e) if e.to_string().starts_with("unknown variant") => return Some(error_response(id, METHOD_NOT_FOUND, e.to_string())),
+        Err(e) => return Some(error_response(id, INVALID_PARAMS, e.to_string())),
+    };
+
+    let output = commands.run(command).await;
+    request.id.as_ref()?;
+    let response = match output.map(serde_json::to_value) {
+        Some(Ok(result)) => Response { jsonrpc: String::from("2.0"), result: Some(result), error: None, id },
+        Some(Err(e)) => error_response(id, INTERNAL_ERROR, e.to_string()),
+        None => error_response(id, INTERNAL_ERROR, "The node stopped before replying"),
+    };
+    Some(response)
+}
+
+async fn handle_connection(stream: impl AsyncRead + AsyncWrite + Unpin, commands: CommandSender) -> std::io::Result<()> {
+    let (r, mut w) = tokio::io::split(stream);
+    let mut lines = BufReader::new(r).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_request(&line, &commands).await {
+            let mut response = serde_json::to_vec(&response).expect("responses are always serializable");
+            response.push(b'\n');
+            w.write_all(&response).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Serves the control API until an IO error occurs.
+/// Commands are forwarded to the node through `commands`.
+pub async fn serve(endpoint: &RpcEndpoint, commands: CommandSender) -> std::io::Result<()> {
+    match endpoint {
+        #[cfg(unix)]
+        RpcEndpoint::Unix(path) => {
+            // A previous instance might have left its socket behind, but anything else at that path is not ours to remove
+            use std::os::unix::fs::FileTypeExt;
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                Ok(_) => return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display()))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+            let listener = tokio::net::UnixListener::bind(path)?;
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let commands = commands.clone();
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, commands).await;
+                });
+            }
+        }
+        RpcEndpoint::Tcp(addr) => {
+            if !addr.ip().is_loopback() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the control API only listens on loopback addresses"));
+            }
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let commands = commands.clone();
+                tokio::spawn(async move {
+                    let _ = handle_connection(stream, commands).await;
+                });
+            }
+        }
+    }
+}
+
+async fn call_on_stream(stream: impl AsyncRead + AsyncWrite + Unpin, command: &Command) -> Result<Value, RpcClientError> {
+    let (r, mut w) = tokio::io::split(stream);
+    let mut request = serde_json::to_value(command)?;
+    let request = request.as_object_mut().expect("commands are serialized as objects");
+    request.insert(String::from("jsonrpc"), Value::String(String::from("2.0")));
+    request.insert(String::from("id"), Value::from(1));
+    let mut request = serde_json::to_vec(request)?;
+    request.push(b'\n');
+    w.write_all(&request).await?;
+
+    let line = BufReader::new(r).lines().next_line().await?.ok_or(RpcClientError::NoResponse)?;
+    let response: Response = serde_json::from_str(&line)?;
+    match (response.result, response.error) {
+        (_, Some(error)) => Err(RpcClientError::Rpc(error)),
+        (Some(result), None) => Ok(result),
+        (None, None) => Err(RpcClientError::NoResponse),
+    }
+}
+
+/// Runs a command on the node serving the control API at `endpoint` and returns its JSON output.
+pub async fn call(endpoint: &RpcEndpoint, command: &Command) -> Result<Value, RpcClientError> {
+    match endpoint {
+        #[cfg(unix)]
+        RpcEndpoint::Unix(path) => call_on_stream(tokio::net::UnixStream::connect(path).await?, command).await,
+        RpcEndpoint::Tcp(addr) => call_on_stream(tokio::net::TcpStream::connect(addr).await?, command).await,
+    }
+}
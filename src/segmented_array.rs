@@ -3,8 +3,9 @@
 
 use crate::prelude::*;
 use sha2::{Sha256, Digest};
+use std::sync::OnceLock;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Segment<T: Hash + std::fmt::Debug, const N: usize> {
     Item(T),
     SegmentedArray(SegmentedArray<T, N>),
@@ -13,14 +14,67 @@ pub enum Segment<T: Hash + std::fmt::Debug, const N: usize> {
 
 /// An array data type whose items can be removed without affecting its hash.
 /// Note: order of items must not change.
-#[derive(Debug, Clone)]
+///
+/// Items are grouped by `N` from the left, like [`SegmentedArray::from`] does, so that all the segments but the last ones are full.
+/// Hashes of arrays are cached, and changes only clear the caches on their path.
+#[derive(Clone)]
 pub struct SegmentedArray<T: Hash + std::fmt::Debug, const N: usize> {
-    pub segments: Vec<Segment<T, N>>,
+    segments: Vec<Segment<T, N>>,
+    hash: OnceLock<[u8; 32]>,
+}
+
+impl<T: Hash + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    fn with_segments(segments: Vec<Segment<T, N>>) -> Self {
+        SegmentedArray { segments, hash: OnceLock::new() }
+    }
+
+    /// Segments can only be changed through the methods of the array, which keep its cached hash right.
+    pub fn segments(&self) -> &[Segment<T, N>] {
+        &self.segments
+    }
+
+    /// Clears the cached hash, as any change to the segments might change it.
+    fn segments_mut(&mut self) -> &mut Vec<Segment<T, N>> {
+        self.hash.take();
+        &mut self.segments
+    }
+}
+
+/// The cached hash is left out
+impl<T: Hash + std::fmt::Debug, const N: usize> std::fmt::Debug for SegmentedArray<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentedArray").field("segments", &self.segments).finish()
+    }
+}
+
+impl<T: Hash + std::fmt::Debug + PartialEq, const N: usize> PartialEq for SegmentedArray<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.segments == other.segments
+    }
 }
 
 impl<T: Hash + Clone + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    /// Replaces a segment by its hash, which leaves the hash of the array unchanged.
     pub fn remove_segment(&mut self, i: usize) {
-        self.segments[i] = Segment::Unknown(self.segments[i].hash());
+        let hash = self.segments[i].hash();
+        self.segments_mut()[i] = Segment::Unknown(hash);
+    }
+}
+
+impl<T: Hash + std::fmt::Debug, const N: usize> Segment<T, N> {
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Segment::Item(_) => true,
+            Segment::SegmentedArray(segmented_array) => segmented_array.is_complete(),
+            Segment::Unknown(_) => false,
+        }
+    }
+}
+
+impl<T: Hash + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    /// Returns true if no segment was replaced by its hash.
+    pub fn is_complete(&self) -> bool {
+        self.segments.iter().all(|segment| segment.is_complete())
     }
 }
 
@@ -59,7 +113,7 @@ impl<T: Hash + Clone + std::fmt::Debug, const N: usize> From<Vec<T>> for Segment
 
         loop {
             if segments.len() <= N {
-                return SegmentedArray { segments };
+                return SegmentedArray::with_segments(segments);
             }
 
             let mut segments_iter = segments.into_iter();
@@ -74,19 +128,28 @@ impl<T: Hash + Clone + std::fmt::Debug, const N: usize> From<Vec<T>> for Segment
                         None => break,
                     }
                 }
-                new_segments.push(Segment::SegmentedArray(SegmentedArray {
-                    segments: new_segmented_array,
-                }));
+                new_segments.push(Segment::SegmentedArray(SegmentedArray::with_segments(new_segmented_array)));
             }
             segments = new_segments;
         }
     }
 }
 
+/// Hashes of items and of arrays are prefixed differently, so that an array cannot be passed off as an item in a [`Proof`].
+const LEAF_TAG: u8 = 0;
+const INNER_TAG: u8 = 1;
+
+fn leaf_hash<T: Hash>(item: &T) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_TAG]);
+    hasher.update(item.hash().as_slice());
+    hasher.finalize().into()
+}
+
 impl<T: Hash + std::fmt::Debug, const N: usize> Hash for Segment<T, N> {
     fn hash(&self) -> Box<[u8; 32]> {
         match self {
-            Segment::Item(item) => item.hash(),
+            Segment::Item(item) => Box::new(leaf_hash(item)),
             Segment::SegmentedArray(segmented_array) => segmented_array.hash(),
             Segment::Unknown(hash) => hash.clone(),
         }
@@ -95,16 +158,457 @@ impl<T: Hash + std::fmt::Debug, const N: usize> Hash for Segment<T, N> {
 
 impl<T: Hash + std::fmt::Debug, const N: usize> Hash for SegmentedArray<T, N> {
     fn hash(&self) -> Box<[u8; 32]> {
-        let mut hasher = Sha256::new();
-        for segment in self.segments.iter() {
-            hasher.update(segment.hash().as_slice());
+        let hash = self.hash.get_or_init(|| {
+            let mut hasher = Sha256::new();
+            hasher.update([INNER_TAG]);
+            for segment in self.segments.iter() {
+                hasher.update(segment.hash().as_slice());
+            }
+            hasher.finalize().into()
+        });
+        Box::new(*hash)
+    }
+}
+
+/// Parts of a [`SegmentedArray`] to transfer.
+/// Segments that are not selected are replaced by their hash.
+#[derive(Debug, Clone, PartialEq, Protocol)]
+pub enum SegmentSelection {
+    /// Only the hashes of the top-level segments
+    Nothing,
+    All,
+    /// Items whose index is within `start..end`
+    Range { start: u64, end: u64 },
+    /// Top-level segments, by index
+    Segments(Vec<u32>),
+    /// The last `count` items, which can be located even if older segments are unknown
+    Last { count: u64 },
+}
+
+impl<T: Hash + std::fmt::Debug, const N: usize> Segment<T, N> {
+    /// Nests an item in arrays of one segment, so that it can be added to an array of that height.
+    fn wrap(item: T, height: usize) -> Self {
+        let mut segment = Segment::Item(item);
+        for _ in 1..height {
+            segment = Segment::SegmentedArray(SegmentedArray::with_segments(vec![segment]));
+        }
+        segment
+    }
+
+    /// Number of items in the segment, if it is complete.
+    fn len(&self) -> Option<usize> {
+        match self {
+            Segment::Item(_) => Some(1),
+            Segment::SegmentedArray(segmented_array) => segmented_array.segments.iter().map(|segment| segment.len()).sum(),
+            Segment::Unknown(_) => None,
+        }
+    }
+}
+
+impl<T: Hash + Clone + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    /// Copies the selected parts of the array, replacing the other segments by their hashes.
+    /// The hash of the result is the same.
+    pub fn select(&self, selection: &SegmentSelection) -> SegmentedArray<T, N> {
+        match selection {
+            SegmentSelection::All => self.clone(),
+            SegmentSelection::Nothing => self.select_segments(|_| false),
+            SegmentSelection::Segments(indexes) => self.select_segments(|i| indexes.contains(&(i as u32))),
+            SegmentSelection::Range { start, end } => self.select_range(*start as usize, *end as usize),
+            SegmentSelection::Last { count } => {
+                let mut selected = self.clone();
+                selected.retain_last(*count as usize);
+                selected
+            }
         }
+    }
+
+    fn select_segments(&self, selected: impl Fn(usize) -> bool) -> SegmentedArray<T, N> {
+        let segments = self.segments.iter().enumerate().map(|(i, segment)| match selected(i) {
+            true => segment.clone(),
+            false => Segment::Unknown(segment.hash()),
+        }).collect();
+        SegmentedArray { segments, hash: self.hash.clone() }
+    }
 
-        let result = hasher.finalize();
-        let mut hash: Box<[u8; 32]> = Box::new(unsafe { uninit_array() });
-        hash.copy_from_slice(&result);
-        
-        hash
+    /// Items after an unknown segment cannot be located, so they are not selected.
+    fn select_range(&self, start: usize, end: usize) -> SegmentedArray<T, N> {
+        let mut offset = Some(0);
+        let segments = self.segments.iter().map(|segment| {
+            let (segment_start, len) = match (offset, segment.len()) {
+                (Some(segment_start), Some(len)) => (segment_start, len),
+                _ => {
+                    offset = None;
+                    return Segment::Unknown(segment.hash());
+                }
+            };
+            offset = Some(segment_start + len);
+            match segment {
+                _ if segment_start + len <= start || segment_start >= end => Segment::Unknown(segment.hash()),
+                Segment::SegmentedArray(segmented_array) if segment_start < start || segment_start + len > end => {
+                    Segment::SegmentedArray(segmented_array.select_range(start.saturating_sub(segment_start), end - segment_start))
+                }
+                segment => segment.clone(),
+            }
+        }).collect();
+        SegmentedArray { segments, hash: self.hash.clone() }
+    }
+}
+
+impl<T: Hash + Clone + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    /// Appends an item, giving the array the same layout as if it had been built [from](SegmentedArray::from) all its items.
+    /// Only the segments on the path to the last item are modified, so older segments can be unknown.
+    /// Gives the item back if segments on that path are unknown, as the layout cannot be told then.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.shape_len().is_none() {
+            return Err(item);
+        }
+        let mut height = self.height().unwrap_or(1);
+        if self.is_full() {
+            let old = std::mem::replace(self, SegmentedArray::with_segments(Vec::new()));
+            self.segments_mut().push(Segment::SegmentedArray(old));
+            height += 1;
+        }
+        self.push_not_full(item, height);
+        Ok(())
+    }
+
+    /// Number of levels of arrays down to the items.
+    /// Segments of a same array all have the same height, so any known one tells it.
+    fn height(&self) -> Option<usize> {
+        if self.segments.is_empty() {
+            return Some(1);
+        }
+        self.segments.iter().find_map(|segment| match segment {
+            Segment::Item(_) => Some(1),
+            Segment::SegmentedArray(segmented_array) => segmented_array.height().map(|height| height + 1),
+            Segment::Unknown(_) => None,
+        })
+    }
+
+    /// Only the last segment can be partially filled, so it is the only one to check.
+    /// Other segments are full, even if unknown.
+    fn is_full(&self) -> bool {
+        self.segments.len() >= N && match self.segments.last() {
+            Some(Segment::SegmentedArray(segmented_array)) => segmented_array.is_full(),
+            _ => true,
+        }
+    }
+
+    fn push_not_full(&mut self, item: T, height: usize) {
+        if let Some(Segment::SegmentedArray(last)) = self.segments_mut().last_mut() {
+            if !last.is_full() {
+                return last.push_not_full(item, height - 1);
+            }
+        }
+        self.segments_mut().push(Segment::wrap(item, height));
+    }
+
+    /// Inserts an item at a position, shifting the following ones.
+    /// The array keeps the layout it would have if it had been built [from](SegmentedArray::from) all its items, so all the segments after the position change.
+    /// Gives the item back if the position is past the end, or if segments from the position on are unknown.
+    pub fn insert(&mut self, index: usize, item: T) -> Result<(), T> {
+        match self.shape_len() {
+            Some(len) if index == len => self.push(item),
+            Some(len) if index < len && self.is_complete_from(index) => match self.insert_shifting(index, item) {
+                Some(last) => self.push(last),
+                None => Ok(()),
+            },
+            _ => Err(item),
+        }
+    }
+
+    /// Number of items, deduced from the layout, which only requires the segments on the path to the last item.
+    fn shape_len(&self) -> Option<usize> {
+        let height = self.height()?;
+        let last = match self.segments.last() {
+            Some(Segment::Item(_)) => 1,
+            Some(Segment::SegmentedArray(segmented_array)) => segmented_array.shape_len()?,
+            Some(Segment::Unknown(_)) => return None,
+            None => return Some(0),
+        };
+        Some((self.segments.len() - 1) * N.pow(height as u32 - 1) + last)
+    }
+
+    /// Returns true if all the items from an index on are known.
+    fn is_complete_from(&self, index: usize) -> bool {
+        let capacity = match self.height() {
+            Some(height) => N.pow(height as u32 - 1),
+            None => return false,
+        };
+        let position = index / capacity;
+        let first = match self.segments.get(position) {
+            Some(Segment::Item(_)) => true,
+            Some(Segment::SegmentedArray(segmented_array)) => segmented_array.is_complete_from(index % capacity),
+            Some(Segment::Unknown(_)) | None => false,
+        };
+        first && self.segments[position + 1..].iter().all(|segment| segment.is_complete())
+    }
+
+    /// Inserts an item and moves the following ones by one position.
+    /// Returns the last item if it no longer fits in this array.
+    fn insert_shifting(&mut self, index: usize, item: T) -> Option<T> {
+        let height = self.height().unwrap_or(1);
+        let segments = self.segments_mut();
+        if height == 1 {
+            segments.insert(index, Segment::Item(item));
+            return match segments.len() > N {
+                true => match segments.pop() {
+                    Some(Segment::Item(last)) => Some(last),
+                    _ => None,
+                },
+                false => None,
+            };
+        }
+
+        let capacity = N.pow(height as u32 - 1);
+        let position = index / capacity;
+        let mut carried = Some(item);
+        for (i, segment) in segments.iter_mut().enumerate().skip(position) {
+            let item = match carried.take() {
+                Some(item) => item,
+                None => break,
+            };
+            let index = if i == position { index % capacity } else { 0 };
+            if let Segment::SegmentedArray(segmented_array) = segment {
+                carried = segmented_array.insert_shifting(index, item);
+            }
+        }
+        match carried {
+            Some(item) if segments.len() < N => {
+                segments.push(Segment::wrap(item, height));
+                None
+            }
+            carried => carried,
+        }
+    }
+
+    /// Replaces segments by their hash so that only the last `count` items are kept.
+    /// The hash is unchanged.
+    pub fn retain_last(&mut self, count: usize) {
+        self.retain_last_counting(count);
+    }
+
+    /// Returns the number of items kept.
+    fn retain_last_counting(&mut self, count: usize) -> usize {
+        let mut kept = 0;
+        for segment in self.segments_mut().iter_mut().rev() {
+            if kept >= count {
+                if !matches!(segment, Segment::Unknown(_)) {
+                    *segment = Segment::Unknown(segment.hash());
+                }
+                continue;
+            }
+            match segment {
+                Segment::SegmentedArray(segmented_array) => kept += segmented_array.retain_last_counting(count - kept),
+                Segment::Item(_) => kept += 1,
+                Segment::Unknown(_) => (),
+            }
+        }
+        kept
+    }
+}
+
+/// Nested arrays deeper than that are refused, to protect the stack of the reader
+const MAX_SEGMENT_DEPTH: usize = 32;
+
+/// Segments are encoded as a tag (0 for an item, 1 for a nested array, 2 for an unknown segment) followed by the segment.
+/// Arrays are encoded as a `u32` number of segments followed by the segments.
+impl<T: Hash + Parcel + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    fn read_with_depth(read: &mut dyn std::io::Read, settings: &protocol::Settings, depth: usize) -> Result<Self, protocol::Error> {
+        if depth > MAX_SEGMENT_DEPTH {
+            return Err(protocol::Error::from("Segmented array is too deep"));
+        }
+        let len = u32::read(read, settings)? as usize;
+        if len > N {
+            return Err(protocol::Error::from(format!("Segmented array has {len} segments, more than {N}")));
+        }
+        let mut segments = Vec::with_capacity(len);
+        for _ in 0..len {
+            let segment = match u8::read(read, settings)? {
+                0 => Segment::Item(T::read(read, settings)?),
+                1 => Segment::SegmentedArray(SegmentedArray::read_with_depth(read, settings, depth + 1)?),
+                2 => Segment::Unknown(Box::new(<[u8; 32]>::read(read, settings)?)),
+                tag => return Err(protocol::ErrorKind::UnknownEnumDiscriminator("Segment", tag.to_string()).into()),
+            };
+            segments.push(segment);
+        }
+        Ok(SegmentedArray::with_segments(segments))
+    }
+}
+
+impl<T: Hash + Parcel + std::fmt::Debug, const N: usize> Parcel for SegmentedArray<T, N> {
+    const TYPE_NAME: &'static str = "SegmentedArray";
+
+    fn read_field(read: &mut dyn std::io::Read, settings: &protocol::Settings, _: &mut protocol::hint::Hints) -> Result<Self, protocol::Error> {
+        SegmentedArray::read_with_depth(read, settings, 0)
+    }
+
+    fn write_field(&self, write: &mut dyn std::io::Write, settings: &protocol::Settings, _: &mut protocol::hint::Hints) -> Result<(), protocol::Error> {
+        (self.segments.len() as u32).write(write, settings)?;
+        for segment in &self.segments {
+            match segment {
+                Segment::Item(item) => {
+                    0u8.write(write, settings)?;
+                    item.write(write, settings)?;
+                }
+                Segment::SegmentedArray(segmented_array) => {
+                    1u8.write(write, settings)?;
+                    segmented_array.write(write, settings)?;
+                }
+                Segment::Unknown(hash) => {
+                    2u8.write(write, settings)?;
+                    hash.as_ref().write(write, settings)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Proves that an item belongs to a [`SegmentedArray`] of a known hash, without the rest of the array.
+///
+/// Levels go from the array containing the item up to the root, and hold the hashes of the other segments of each array, in order.
+/// As arrays are filled from the left, the index and length of the array tell the position of the item and the number of siblings at each level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proof<const N: usize> {
+    /// Index of the proven item in the array
+    pub index: u64,
+    /// Number of items in the array
+    pub len: u64,
+    pub levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl<T: Hash + Clone + std::fmt::Debug, const N: usize> SegmentedArray<T, N> {
+    /// Returns the item at an index, if it is known.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.locate(index, &mut Vec::new())
+    }
+
+    /// Builds a proof that the item at an index belongs to the array.
+    /// Returns `None` if the item is not known, which is the case for items after an unknown segment.
+    pub fn prove(&self, index: usize) -> Option<Proof<N>> {
+        let len = self.shape_len()?;
+        let mut levels = Vec::new();
+        self.locate(index, &mut levels)?;
+        levels.reverse();
+        Some(Proof { index: index as u64, len: len as u64, levels })
+    }
+
+    /// Finds an item, pushing proof levels from the root down.
+    fn locate(&self, index: usize, levels: &mut Vec<Vec<[u8; 32]>>) -> Option<&T> {
+        let height = self.height()?;
+        let capacity = N.checked_pow(height as u32 - 1)?;
+        let position = index / capacity;
+        let siblings = self.segments.iter().enumerate()
+            .filter(|(i, _)| *i != position)
+            .map(|(_, segment)| *segment.hash())
+            .collect();
+        levels.push(siblings);
+        match self.segments.get(position)? {
+            Segment::Item(item) => Some(item),
+            Segment::SegmentedArray(segmented_array) => segmented_array.locate(index % capacity, levels),
+            Segment::Unknown(_) => None,
+        }
+    }
+}
+
+impl<const N: usize> Proof<N> {
+    /// Checks that the proof links an item to the hash of an array.
+    /// The proof must have exactly the levels and siblings an array of that length has on the path to that index.
+    pub fn verify<T: Hash>(&self, root: &[u8; 32], item: &T) -> bool {
+        let sibling_counts = match self.sibling_counts() {
+            Some(sibling_counts) => sibling_counts,
+            None => return false,
+        };
+        if sibling_counts.len() != self.levels.len() {
+            return false;
+        }
+
+        let mut hash = leaf_hash(item);
+        let mut index = self.index;
+        for (siblings, count) in self.levels.iter().zip(sibling_counts.iter().rev()) {
+            if siblings.len() != *count {
+                return false;
+            }
+            let position = (index % N as u64) as usize;
+            index /= N as u64;
+            let mut hasher = Sha256::new();
+            hasher.update([INNER_TAG]);
+            for sibling in &siblings[..position] {
+                hasher.update(sibling);
+            }
+            hasher.update(hash);
+            for sibling in &siblings[position..] {
+                hasher.update(sibling);
+            }
+            hash = hasher.finalize().into();
+        }
+        &hash == root
+    }
+
+    /// Number of siblings on each level of the path to the item, from the root down, in an array built [from](SegmentedArray::from) `len` items.
+    fn sibling_counts(&self) -> Option<Vec<usize>> {
+        if self.index >= self.len || N < 2 {
+            return None;
+        }
+        let mut height = 1;
+        let mut capacity = N as u64;
+        while capacity < self.len {
+            capacity = capacity.saturating_mul(N as u64);
+            height += 1;
+        }
+
+        let (mut index, mut len) = (self.index, self.len);
+        let mut sibling_counts = Vec::with_capacity(height);
+        for level in (0..height as u32).rev() {
+            let capacity = (N as u64).checked_pow(level)?;
+            let position = index / capacity;
+            sibling_counts.push((len.div_ceil(capacity) - 1) as usize);
+            len = (len - position * capacity).min(capacity);
+            index %= capacity;
+        }
+        Some(sibling_counts)
+    }
+}
+
+/// Proofs are encoded as the `u64` index and length, followed by a `u32` number of levels and the levels.
+/// Each level is a `u32` number of sibling hashes followed by the hashes.
+impl<const N: usize> Parcel for Proof<N> {
+    const TYPE_NAME: &'static str = "Proof";
+
+    fn read_field(read: &mut dyn std::io::Read, settings: &protocol::Settings, _: &mut protocol::hint::Hints) -> Result<Self, protocol::Error> {
+        let index = u64::read(read, settings)?;
+        let len = u64::read(read, settings)?;
+        let height = u32::read(read, settings)? as usize;
+        if height > MAX_SEGMENT_DEPTH + 1 {
+            return Err(protocol::Error::from("Proof is too deep"));
+        }
+        let mut levels = Vec::with_capacity(height);
+        for _ in 0..height {
+            let count = u32::read(read, settings)? as usize;
+            if count >= N {
+                return Err(protocol::Error::from(format!("Proof level has {count} siblings, more than {}", N - 1)));
+            }
+            let mut siblings = Vec::with_capacity(count);
+            for _ in 0..count {
+                siblings.push(<[u8; 32]>::read(read, settings)?);
+            }
+            levels.push(siblings);
+        }
+        Ok(Proof { index, len, levels })
+    }
+
+    fn write_field(&self, write: &mut dyn std::io::Write, settings: &protocol::Settings, _: &mut protocol::hint::Hints) -> Result<(), protocol::Error> {
+        self.index.write(write, settings)?;
+        self.len.write(write, settings)?;
+        (self.levels.len() as u32).write(write, settings)?;
+        for siblings in &self.levels {
+            (siblings.len() as u32).write(write, settings)?;
+            for sibling in siblings {
+                sibling.write(write, settings)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -136,23 +640,24 @@ mod tests {
         let seg_array2 = seg_array.clone();
 
         seg_array.remove_segment(7);
-        if let Some(Segment::SegmentedArray(array)) = seg_array.segments.get_mut(6) {
+        if let Some(Segment::SegmentedArray(array)) = seg_array.segments_mut().get_mut(6) {
             array.remove_segment(5);
         }
 
         assert_eq!(seg_array.hash(), seg_array2.hash());
     }
 
-    #[test]
-    fn segment_sizes() {
-        fn check_segment_size<T: Hash + std::fmt::Debug, const N: usize>(segment: &Segment<T, N>) {
-            if let Segment::SegmentedArray(array) = segment {
-                assert!(array.segments.len() <= N);
-                for segment in &array.segments {
-                    check_segment_size(segment);
-                }
+    fn check_segment_size<T: Hash + std::fmt::Debug, const N: usize>(segment: &Segment<T, N>) {
+        if let Segment::SegmentedArray(array) = segment {
+            assert!(array.segments.len() <= N);
+            for segment in &array.segments {
+                check_segment_size(segment);
             }
         }
+    }
+
+    #[test]
+    fn segment_sizes() {
 
         let mut array: Vec<u16> = Vec::new();
         for i in 0..51215 {
@@ -165,4 +670,189 @@ mod tests {
             check_segment_size(segment);
         }
     }
+
+    #[test]
+    fn push_and_retain_last() {
+        let mut seg_array: SegmentedArray<u16, 4> = SegmentedArray::from(Vec::new());
+        for i in 0..200 {
+            seg_array.push(i).unwrap();
+            let expected: SegmentedArray<u16, 4> = SegmentedArray::from((0..=i).collect::<Vec<_>>());
+            assert_eq!(seg_array, expected);
+        }
+
+        // Old items can be pruned, and new ones still appended
+        let hash = seg_array.hash();
+        seg_array.retain_last(10);
+        assert_eq!(seg_array.hash(), hash);
+        let items = Vec::from(seg_array.clone());
+        assert_eq!(items.len(), 10);
+        assert_eq!(items.last(), Some(&199));
+        for i in 200..300 {
+            seg_array.push(i).unwrap();
+        }
+        let expected: SegmentedArray<u16, 4> = SegmentedArray::from((0..300).collect::<Vec<_>>());
+        assert_eq!(seg_array.hash(), expected.hash());
+        seg_array.retain_last(0);
+        assert_eq!(seg_array.hash(), expected.hash());
+        assert!(Vec::from(seg_array.clone()).is_empty());
+
+        // Without the last item, the layout is unknown, so nothing can be appended
+        assert_eq!(seg_array.push(300), Err(300));
+        assert_eq!(seg_array.hash(), expected.hash());
+        let mut selected = expected.select(&SegmentSelection::Nothing);
+        assert_eq!(selected.push(300), Err(300));
+        let mut selected = expected.select(&SegmentSelection::Last { count: 1 });
+        selected.push(300).unwrap();
+        assert_eq!(selected.hash(), SegmentedArray::<u16, 4>::from((0..301).collect::<Vec<_>>()).hash());
+    }
+
+    #[test]
+    fn wire_format() {
+        let array: Vec<u16> = (0..5000).collect();
+        let mut seg_array: SegmentedArray<u16, 16> = SegmentedArray::from(array);
+        if let Some(Segment::SegmentedArray(array)) = seg_array.segments_mut().get_mut(0) {
+            array.remove_segment(3);
+        }
+        assert!(!seg_array.is_complete());
+
+        let bytes = seg_array.raw_bytes(&PROTOCOL_SETTINGS).unwrap();
+        let seg_array2 = SegmentedArray::<u16, 16>::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).unwrap();
+        assert_eq!(seg_array, seg_array2);
+        assert_eq!(seg_array.hash(), seg_array2.hash());
+
+        // Arrays with more than N segments are refused
+        assert!(SegmentedArray::<u16, 4>::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).is_err());
+    }
+
+    #[test]
+    fn select() {
+        let array: Vec<u16> = (0..5000).collect();
+        let seg_array: SegmentedArray<u16, 16> = SegmentedArray::from(array);
+
+        for selection in [SegmentSelection::Nothing, SegmentSelection::All, SegmentSelection::Segments(vec![1]), SegmentSelection::Range { start: 250, end: 300 }, SegmentSelection::Last { count: 10 }] {
+            let selected = seg_array.select(&selection);
+            assert_eq!(selected.hash(), seg_array.hash());
+            let items = Vec::from(selected);
+            match selection {
+                SegmentSelection::Nothing => assert!(items.is_empty()),
+                SegmentSelection::All => assert_eq!(items.len(), 5000),
+                SegmentSelection::Segments(_) => assert_eq!(items, (4096..5000).collect::<Vec<_>>()),
+                SegmentSelection::Range { .. } => assert_eq!(items, (250..300).collect::<Vec<_>>()),
+                SegmentSelection::Last { .. } => assert_eq!(items, (4990..5000).collect::<Vec<_>>()),
+            }
+        }
+    }
+
+    #[test]
+    fn proofs() {
+        let array: Vec<u16> = (0..5000).collect();
+        let mut seg_array: SegmentedArray<u16, 16> = SegmentedArray::from(array);
+        let root = *seg_array.hash();
+
+        for index in [0, 15, 16, 255, 256, 4095, 4096, 4999] {
+            let proof = seg_array.prove(index).unwrap();
+            assert_eq!(proof.index, index as u64);
+            assert!(proof.verify(&root, &(index as u16)));
+            assert!(!proof.verify(&root, &(index as u16 + 1)));
+            assert_eq!(seg_array.get(index), Some(&(index as u16)));
+
+            let bytes = proof.raw_bytes(&PROTOCOL_SETTINGS).unwrap();
+            let proof2 = Proof::<16>::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).unwrap();
+            assert_eq!(proof, proof2);
+            assert!(Proof::<8>::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).is_err());
+        }
+        assert!(seg_array.prove(5000).is_none());
+
+        // Proofs can still be built from a pruned array, for the items it kept
+        seg_array.retain_last(10);
+        assert!(seg_array.prove(4989).is_none());
+        let proof = seg_array.prove(4990).unwrap();
+        assert!(proof.verify(&root, &4990u16));
+
+        // Tampered proofs are refused
+        let mut tampered = proof.clone();
+        tampered.index += 1;
+        assert!(!tampered.verify(&root, &4990u16));
+        let mut tampered = proof.clone();
+        tampered.levels[1][0][0] ^= 1;
+        assert!(!tampered.verify(&root, &4990u16));
+        let mut tampered = proof.clone();
+        tampered.levels[1].swap(0, 1);
+        assert!(!tampered.verify(&root, &4990u16));
+
+        // Truncated proofs are refused, even when the hashes they skip are right
+        let mut truncated = proof.clone();
+        truncated.levels.pop();
+        assert!(!truncated.verify(&root, &4990u16));
+        let mut truncated = proof.clone();
+        truncated.levels[0].pop();
+        assert!(!truncated.verify(&root, &4990u16));
+        let mut truncated = proof.clone();
+        truncated.len = 4096;
+        assert!(!truncated.verify(&root, &4990u16));
+
+        // The array containing an item cannot be passed off as an item of the array one level up
+        let inner: SegmentedArray<u16, 16> = SegmentedArray::from((4976..4992).collect::<Vec<_>>());
+        let forged = Proof::<16> { index: proof.index / 16, len: proof.len.div_ceil(16), levels: proof.levels[1..].to_vec() };
+        assert_eq!(forged.sibling_counts().unwrap().len(), forged.levels.len());
+        assert!(!forged.verify(&root, &inner));
+    }
+
+    #[test]
+    fn insert() {
+        let mut items: Vec<u16> = Vec::new();
+        let mut seg_array: SegmentedArray<u16, 4> = SegmentedArray::from(Vec::new());
+        for i in 0..300 {
+            let index = (i as usize * 37) % (items.len() + 1);
+            items.insert(index, i);
+            seg_array.insert(index, i).unwrap();
+            let expected: SegmentedArray<u16, 4> = SegmentedArray::from(items.clone());
+            assert_eq!(seg_array, expected);
+            assert_eq!(seg_array.hash(), expected.hash());
+            check_segment_size(&Segment::SegmentedArray(seg_array.clone()));
+        }
+        assert!(seg_array.insert(301, 0).is_err());
+
+        // Only known items can be shifted
+        seg_array.retain_last(10);
+        assert!(seg_array.insert(0, 0).is_err());
+        assert!(seg_array.insert(289, 0).is_err());
+        seg_array.insert(295, 1000).unwrap();
+        seg_array.insert(301, 1001).unwrap();
+        items.insert(295, 1000);
+        items.insert(301, 1001);
+        let expected: SegmentedArray<u16, 4> = SegmentedArray::from(items);
+        assert_eq!(seg_array.hash(), expected.hash());
+    }
+
+    #[test]
+    fn cached_hashes() {
+        let mut seg_array: SegmentedArray<u16, 16> = SegmentedArray::from((0..5000).collect::<Vec<_>>());
+        seg_array.hash();
+        assert!(seg_array.segments.iter().all(|segment| matches!(segment, Segment::SegmentedArray(array) if array.hash.get().is_some())));
+
+        // Only the path to the new item is cleared
+        seg_array.push(5000).unwrap();
+        assert!(seg_array.hash.get().is_none());
+        assert!(matches!(&seg_array.segments[0], Segment::SegmentedArray(array) if array.hash.get().is_some()));
+        assert!(matches!(&seg_array.segments[1], Segment::SegmentedArray(array) if array.hash.get().is_none()));
+        let expected: SegmentedArray<u16, 16> = SegmentedArray::from((0..5001).collect::<Vec<_>>());
+        assert_eq!(seg_array.hash(), expected.hash());
+
+        seg_array.insert(4500, 0).unwrap();
+        assert!(matches!(&seg_array.segments[0], Segment::SegmentedArray(array) if array.hash.get().is_some()));
+        let mut items: Vec<u16> = (0..5001).collect();
+        items.insert(4500, 0);
+        assert_eq!(seg_array.hash(), SegmentedArray::<u16, 16>::from(items.clone()).hash());
+
+        // Pruning and decoding leave no stale hash behind
+        seg_array.retain_last(10);
+        assert!(seg_array.hash.get().is_none());
+        assert!(matches!(&seg_array.segments[1], Segment::SegmentedArray(array) if array.hash.get().is_none()));
+        assert_eq!(seg_array.hash(), SegmentedArray::<u16, 16>::from(items).hash());
+        let bytes = seg_array.raw_bytes(&PROTOCOL_SETTINGS).unwrap();
+        let decoded = SegmentedArray::<u16, 16>::from_raw_bytes(&bytes, &PROTOCOL_SETTINGS).unwrap();
+        assert!(decoded.hash.get().is_none());
+        assert_eq!(decoded.hash(), seg_array.hash());
+    }
 }
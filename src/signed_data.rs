@@ -5,7 +5,7 @@ use sha2::{Digest, Sha256};
 use rsa::{RsaPrivateKey, PaddingScheme, RsaPublicKey, PublicKeyParts, PublicKey};
 use crate::prelude::*;
 
-#[derive(Debug, Clone, protocol_derive::Protocol)]
+#[derive(Debug, Clone, PartialEq, protocol_derive::Protocol, serde::Serialize)]
 pub struct SignedData<T: Parcel> {
     data: T,
     rsa_public_key_exponent: Vec<u8>,
@@ -25,15 +25,36 @@ impl<T: Parcel> SignedData<T> {
         hasher.update(bytes);
         let hash = hasher.finalize();
 
-        rsa_public_key.verify(PaddingScheme::new_pss::<Sha256, OsRng>(OsRng), &hash, &self.signature)?;
+        rsa_public_key.verify(PaddingScheme::new_pss::<Sha256, NodeRng>(rng()), &hash, &self.signature)?;
 
         Ok(PeerID::from(&rsa_public_key))
     }
 
+    /// Gives access to the data without checking the signature.
+    pub fn data_unverified(&self) -> &T {
+        &self.data
+    }
+
     pub fn into_verified(self) -> Result<(PeerID, T), rsa::errors::Error> {
         let peer_id = self.verify()?;
         Ok((peer_id, self.data))
     }
+
+    /// Gives access to the data without updating the signature, to simulate forgeries.
+    #[cfg(feature = "test")]
+    pub fn tamper(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+/// Covers the signature too, so that lists of signed data can't have their signatures swapped without changing their hash.
+impl<T: Parcel + Hashable> Hashable for SignedData<T> {
+    fn update_hasher(&self, hasher: &mut impl Digest) {
+        self.data.update_hasher(hasher);
+        self.rsa_public_key_exponent.update_hasher(hasher);
+        self.rsa_public_key_modulus.update_hasher(hasher);
+        self.signature.update_hasher(hasher);
+    }
 }
 
 pub trait Signable: Parcel {
@@ -50,7 +71,7 @@ impl<T> Signable for T where T: Parcel {
 
         // TODO [#65]: Investigate security implications of the PSS padding scheme
         // Can we just ignore the salt lenght?
-        let signature = rsa_private_key.sign(PaddingScheme::new_pss::<Sha256, OsRng>(OsRng), hash.as_slice())?;
+        let signature = rsa_private_key.sign(PaddingScheme::new_pss::<Sha256, NodeRng>(rng()), hash.as_slice())?;
 
         Ok(SignedData {
             data: self,
@@ -0,0 +1,168 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::{prelude::*, local_addr_index};
+use std::sync::RwLock;
+use tracing::Instrument;
+
+/// A way for a simulated node to deviate from the protocol.
+/// Misbehaving nodes still complete handshakes, so that honest nodes keep them in their buckets.
+#[derive(Debug, Clone)]
+pub enum Misbehavior {
+    /// Answers lookups and discovery requests with peers that do not exist, crafted to look close to the target.
+    BogusPeers,
+    /// Never answers any request, not even pings.
+    Silent,
+    /// Answers with lists larger than the protocol allows.
+    OversizedLists,
+    /// Answers DHT lookups with values whose signature does not match the key.
+    ForgedValues,
+    /// Hides `target` from the network by answering lookups for it with nothing but other eclipsing nodes.
+    Eclipse { target: KeyID },
+    /// Floods peers with discovery requests every `interval`.
+    SpamDiscovery { interval: Duration },
+}
+
+lazy_static::lazy_static!(
+    /// Misbehavior of each node, by index.
+    static ref MISBEHAVIORS: RwLock<BTreeMap<usize, Misbehavior>> = RwLock::new(BTreeMap::new());
+    /// Nodes taking part in eclipse attacks, by target.
+    static ref ECLIPSERS: RwLock<BTreeMap<KeyID, Vec<(PeerID, String)>>> = RwLock::new(BTreeMap::new());
+);
+
+/// Makes the node at index `i` misbehave, or behave again if `misbehavior` is `None`.
+/// Must be called before the node is created to take full effect.
+pub fn set_misbehavior(i: usize, misbehavior: Option<Misbehavior>) {
+    let mut misbehaviors = MISBEHAVIORS.write().unwrap();
+    match misbehavior {
+        Some(misbehavior) => misbehaviors.insert(i, misbehavior),
+        None => misbehaviors.remove(&i),
+    };
+}
+
+pub fn misbehavior(i: usize) -> Option<Misbehavior> {
+    MISBEHAVIORS.read().unwrap().get(&i).cloned()
+}
+
+/// Peers with IDs very close to `near`, at addresses nobody listens on.
+fn bogus_peers(near: &PeerID, count: usize) -> Vec<(PeerID, String)> {
+    (0..count).map(|i| {
+        let peer_id = near.generate_in_bucket(rng().gen_range(64..128), rng().gen_range(0..3));
+        (peer_id, format!("local-{}", usize::MAX - i))
+    }).collect()
+}
+
+impl Node {
+    /// Called when the node is created.
+    pub(crate) fn start_misbehaving(self: &Arc<Self>) {
+        match misbehavior(local_addr_index(&self.addr)) {
+            Some(Misbehavior::Eclipse { target }) => {
+                let mut eclipsers = ECLIPSERS.write().unwrap();
+                let eclipsers = eclipsers.entry(target).or_default();
+                eclipsers.retain(|(peer_id, _)| peer_id != &self.peer_id);
+                eclipsers.push((self.peer_id.clone(), self.addr.clone()));
+            }
+            Some(Misbehavior::SpamDiscovery { interval }) => {
+                let node = Arc::downgrade(self);
+                spawn(async move {
+                    loop {
+                        sleep(interval).await;
+                        let node = match node.upgrade() {
+                            Some(node) => node,
+                            None => break,
+                        };
+                        for peer_id in node.connections.peers().await {
+                            let target = node.peer_id.generate_in_bucket(rng().gen_range(0..128), rng().gen_range(0..3));
+                            node.connections.send_packet(&peer_id, Packet::DiscoverPeers(DiscoverPeersPacket {
+                                request_id: node.discover_peer_req_counter.next(),
+                                target,
+                                mask: vec![0xFF; 32],
+                                limit: MAX_DISCOVERY_PEERS_RETURNED,
+                            })).await;
+                        }
+                    }
+                }.instrument(self.span.clone()));
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles a packet the way our [`Misbehavior`] dictates.
+    /// Returns `false` if the packet should be handled honestly.
+    pub(crate) async fn misbehave(&self, n: &PeerID, p: &Packet) -> bool {
+        let misbehavior = match misbehavior(local_addr_index(&self.addr)) {
+            Some(misbehavior) => misbehavior,
+            None => return false,
+        };
+
+        let response = match (misbehavior, p) {
+            (Misbehavior::Silent, Packet::DiscoverPeers(_) | Packet::FindDhtValue(_) | Packet::FindPeer(_) | Packet::Ping(_)) => None,
+            (Misbehavior::BogusPeers, Packet::DiscoverPeers(p)) => Some(Packet::DiscoverPeersResp(DiscoverPeersRespPacket {
+                request_id: p.request_id,
+                peers: bogus_peers(&p.target, p.limit.min(MAX_DISCOVERY_PEERS_RETURNED) as usize),
+            })),
+            (Misbehavior::BogusPeers, Packet::FindDhtValue(p)) => Some(Packet::FindDhtValueResp(FindDhtValueRespPacket {
+                request_id: p.request_id,
+                result: DhtLookupResult::NotFound(bogus_peers(&p.key, p.limit_peers.min(MAX_DHT_PEERS_RETURNED) as usize)),
+            })),
+            (Misbehavior::BogusPeers, Packet::FindPeer(p)) => Some(Packet::FindPeerResp(FindPeerRespPacket {
+                request_id: p.request_id,
+                peers: bogus_peers(&p.peer_id, p.limit.min(MAX_DHT_PEERS_RETURNED) as usize),
+            })),
+            (Misbehavior::OversizedLists, Packet::DiscoverPeers(p)) => Some(Packet::DiscoverPeersResp(DiscoverPeersRespPacket {
+                request_id: p.request_id,
+                peers: bogus_peers(&p.target, MAX_DISCOVERY_PEERS_RETURNED as usize * 10),
+            })),
+            (Misbehavior::OversizedLists, Packet::FindDhtValue(p)) => Some(Packet::FindDhtValueResp(FindDhtValueRespPacket {
+                request_id: p.request_id,
+                result: DhtLookupResult::NotFound(bogus_peers(&p.key, MAX_DHT_PEERS_RETURNED as usize * 10)),
+            })),
+            (Misbehavior::OversizedLists, Packet::FindPeer(p)) => Some(Packet::FindPeerResp(FindPeerRespPacket {
+                request_id: p.request_id,
+                peers: bogus_peers(&p.peer_id, MAX_DHT_PEERS_RETURNED as usize * 10),
+            })),
+            (Misbehavior::ForgedValues, Packet::FindDhtValue(p)) => {
+                let desc = AccountSnapshotDescriptor { timestamp: u64::MAX, hash: [0; 32], backup_peer_id: p.key.clone(), size: 0 };
+
+                // A valid signature, but from the wrong key
+                let impersonated = desc.clone().sign(&self.rsa_public_key, &self.rsa_private_key).unwrap();
+                // The signature of an honest value, applied to different data
+                let mut tampered = AccountSnapshotDescriptor { timestamp: 0, hash: [0; 32], backup_peer_id: p.key.clone(), size: 0 }.sign(&self.rsa_public_key, &self.rsa_private_key).unwrap();
+                *tampered.tamper() = desc;
+
+                Some(Packet::FindDhtValueResp(FindDhtValueRespPacket {
+                    request_id: p.request_id,
+                    result: DhtLookupResult::Found(vec![
+                        DhtValue { provider: self.peer_id.clone(), cached_addr: Some(self.addr.clone()), account_snapshot_desc: impersonated, migration: None, interaction: None },
+                        DhtValue { provider: self.peer_id.clone(), cached_addr: Some(self.addr.clone()), account_snapshot_desc: tampered, migration: None, interaction: None },
+                    ]),
+                }))
+            }
+            (Misbehavior::Eclipse { target }, Packet::FindDhtValue(p)) if p.key == target => {
+                let mut peers = ECLIPSERS.read().unwrap().get(&target).cloned().unwrap_or_default();
+                peers.retain(|(peer_id, _)| peer_id != &self.peer_id);
+                peers.truncate(p.limit_peers.min(MAX_DHT_PEERS_RETURNED) as usize);
+                Some(Packet::FindDhtValueResp(FindDhtValueRespPacket {
+                    request_id: p.request_id,
+                    result: DhtLookupResult::NotFound(peers),
+                }))
+            }
+            (Misbehavior::Eclipse { target }, Packet::FindPeer(p)) if p.peer_id == target => {
+                let mut peers = ECLIPSERS.read().unwrap().get(&target).cloned().unwrap_or_default();
+                peers.retain(|(peer_id, _)| peer_id != &self.peer_id);
+                peers.truncate(p.limit.min(MAX_DHT_PEERS_RETURNED) as usize);
+                Some(Packet::FindPeerResp(FindPeerRespPacket {
+                    request_id: p.request_id,
+                    peers,
+                }))
+            }
+            _ => return false,
+        };
+
+        trace!(self.ll, "Misbehaving on {:?}", p);
+        if let Some(response) = response {
+            self.connections.send_packet(n, response).await;
+        }
+        true
+    }
+}
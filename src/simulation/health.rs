@@ -0,0 +1,141 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use std::collections::VecDeque;
+
+/// Overview of the state of a simulated network, compared to what an ideal network would look like.
+///
+/// Nodes are referred to by their index in the slice given to [`inspect`].
+#[derive(Debug, Clone)]
+pub struct NetworkHealth {
+    pub node_count: usize,
+    /// Average fill ratio of buckets, by level.
+    /// A bucket is considered full when it has [`KADEMLIA_BUCKET_SIZE`] peers, or all the peers that exist in that bucket.
+    /// Stops at the first level where no node could have any peer.
+    pub bucket_fill: Vec<f64>,
+    /// Average ratio of the [`KADEMLIA_BUCKET_SIZE`] closest nodes to a node that it is connected to.
+    pub closest_k_accuracy: f64,
+    /// Number of connected components of the connection graph.
+    pub components: usize,
+    /// Longest shortest path between two nodes of the connection graph.
+    /// `None` if the graph is not connected.
+    pub diameter: Option<usize>,
+    /// Nodes that have no connection at all.
+    pub isolated: Vec<usize>,
+}
+
+/// Computes the [`NetworkHealth`] of a set of nodes.
+/// Connections to nodes outside of that set are ignored.
+pub async fn inspect(nodes: &[Arc<Node>]) -> NetworkHealth {
+    let indexes: BTreeMap<&PeerID, usize> = nodes.iter().enumerate().map(|(i, n)| (&n.peer_id, i)).collect();
+    let mut graph: Vec<Vec<usize>> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let peers = node.connections.peers().await;
+        graph.push(peers.iter().filter_map(|p| indexes.get(p).copied()).collect());
+    }
+
+    // Bucket fill
+    let mut bucket_fill = Vec::new();
+    for bucket_level in 0..128 {
+        let mut fill = 0.0;
+        let mut buckets = 0;
+        for (i, node) in nodes.iter().enumerate() {
+            for bucket_id in 0..3 {
+                let in_bucket = |peer_id: &PeerID| peer_id.bucket(&node.peer_id) == Some((bucket_level, bucket_id));
+                let existing = nodes.iter().filter(|n| in_bucket(&n.peer_id)).count();
+                if existing == 0 {
+                    continue;
+                }
+                let connected = graph[i].iter().filter(|j| in_bucket(&nodes[**j].peer_id)).count();
+                fill += connected.min(KADEMLIA_BUCKET_SIZE) as f64 / existing.min(KADEMLIA_BUCKET_SIZE) as f64;
+                buckets += 1;
+            }
+        }
+        if buckets == 0 {
+            break;
+        }
+        bucket_fill.push(fill / buckets as f64);
+    }
+
+    // Closest nodes
+    let mut closest_k_accuracy = 0.0;
+    for (i, node) in nodes.iter().enumerate() {
+        let mut others: Vec<usize> = (0..nodes.len()).filter(|j| *j != i).collect();
+        others.sort_by_key(|j| nodes[*j].peer_id.distance(&node.peer_id));
+        others.truncate(KADEMLIA_BUCKET_SIZE);
+        if others.is_empty() {
+            continue;
+        }
+        let found = others.iter().filter(|j| graph[i].contains(j)).count();
+        closest_k_accuracy += found as f64 / others.len() as f64;
+    }
+    if !nodes.is_empty() {
+        closest_k_accuracy /= nodes.len() as f64;
+    }
+
+    // Connection graph
+    let mut undirected = vec![BTreeSet::new(); nodes.len()];
+    for (i, peers) in graph.iter().enumerate() {
+        for j in peers {
+            undirected[i].insert(*j);
+            undirected[*j].insert(i);
+        }
+    }
+    let isolated = (0..nodes.len()).filter(|i| undirected[*i].is_empty()).collect();
+    let mut component = vec![None; nodes.len()];
+    let mut components = 0;
+    let mut diameter = 0;
+    for start in 0..nodes.len() {
+        // Breadth-first search
+        let mut distances = vec![None; nodes.len()];
+        distances[start] = Some(0);
+        let mut queue = VecDeque::from([start]);
+        while let Some(i) = queue.pop_front() {
+            let distance = distances[i].unwrap();
+            diameter = diameter.max(distance);
+            for j in &undirected[i] {
+                if distances[*j].is_none() {
+                    distances[*j] = Some(distance + 1);
+                    queue.push_back(*j);
+                }
+            }
+        }
+
+        if component[start].is_none() {
+            for (i, distance) in distances.iter().enumerate() {
+                if distance.is_some() {
+                    component[i] = Some(components);
+                }
+            }
+            components += 1;
+        }
+    }
+
+    NetworkHealth {
+        node_count: nodes.len(),
+        bucket_fill,
+        closest_k_accuracy,
+        components,
+        diameter: if components <= 1 { Some(diameter) } else { None },
+        isolated,
+    }
+}
+
+impl std::fmt::Display for NetworkHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Nodes: {}", self.node_count)?;
+        write!(f, "Bucket fill:")?;
+        for (level, fill) in self.bucket_fill.iter().enumerate() {
+            write!(f, " {level}:{:.0}%", fill * 100.0)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Closest {} accuracy: {:.0}%", KADEMLIA_BUCKET_SIZE, self.closest_k_accuracy * 100.0)?;
+        writeln!(f, "Components: {}", self.components)?;
+        match self.diameter {
+            Some(diameter) => writeln!(f, "Diameter: {diameter}")?,
+            None => writeln!(f, "Diameter: infinite")?,
+        }
+        write!(f, "Isolated nodes: {:?}", self.isolated)
+    }
+}
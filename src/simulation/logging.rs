@@ -0,0 +1,179 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! Logging setup for simulations, configured from the environment:
+//!
+//! - `RUST_LOG` filters events (see [`crate::logging`]),
+//! - `TEWTA_LOG_FORMAT` is `text` (default) or `json`,
+//! - `TEWTA_LOG_DIR`, if set, is a directory where each node gets its own `<addr>.log` file, on top of the shared stderr output.
+
+use crate::logging::{self, LogFormat};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fmt::Write as _, fs::File, io::Write as _, path::PathBuf, sync::Mutex};
+use tokio::time::Instant;
+use tracing::{field::{Field, Visit}, span, Event, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Installs the global subscriber as configured by the environment.
+/// Does nothing if a subscriber is already installed.
+pub fn init_logging_from_env() {
+    let format = match std::env::var("TEWTA_LOG_FORMAT") {
+        Ok(format) => format.parse().unwrap_or_else(|e| panic!("Invalid TEWTA_LOG_FORMAT: {e}")),
+        Err(_) => LogFormat::default(),
+    };
+    let dir = std::env::var_os("TEWTA_LOG_DIR").map(PathBuf::from);
+    init_logging(format, dir);
+}
+
+/// Installs the global subscriber, writing to stderr and to per-node files in `dir`.
+pub fn init_logging(format: LogFormat, dir: Option<PathBuf>) {
+    let files = dir.map(|dir| {
+        std::fs::create_dir_all(&dir).unwrap_or_else(|e| panic!("Could not create log directory {}: {e}", dir.display()));
+        NodeLogFiles::new(dir, format).boxed()
+    });
+    logging::init_with(format, files);
+}
+
+/// Fields recorded on a span or event.
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), Value::String(format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::String(value.to_string()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+}
+
+/// Writes the events of each node to its own file, named after the `addr` field of its `node` span.
+/// Events happening outside of a node are ignored.
+pub struct NodeLogFiles {
+    dir: PathBuf,
+    format: LogFormat,
+    start: Instant,
+    files: Mutex<BTreeMap<String, File>>,
+}
+
+impl NodeLogFiles {
+    pub fn new(dir: PathBuf, format: LogFormat) -> NodeLogFiles {
+        NodeLogFiles {
+            dir,
+            format,
+            start: Instant::now(),
+            files: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn format_line(&self, event: &Event<'_>, spans: Vec<(&'static str, Map<String, Value>)>) -> String {
+        let time = Instant::now().duration_since(self.start).as_secs_f64();
+        let metadata = event.metadata();
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        match self.format {
+            LogFormat::Json => {
+                let spans: Vec<Value> = spans.into_iter().map(|(name, mut fields)| {
+                    fields.insert(String::from("name"), Value::from(name));
+                    Value::Object(fields)
+                }).collect();
+                let line = serde_json::json!({
+                    "time": time,
+                    "level": metadata.level().as_str(),
+                    "target": metadata.target(),
+                    "fields": fields.0,
+                    "spans": spans,
+                });
+                line.to_string()
+            }
+            LogFormat::Text => {
+                let mut line = format!("[{time:>10.3}s] {:>5} ", metadata.level().as_str());
+                for (name, fields) in spans {
+                    line.push_str(name);
+                    if !fields.is_empty() {
+                        let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{k}={}", v.as_str().map(String::from).unwrap_or_else(|| v.to_string()))).collect();
+                        let _ = write!(line, "{{{}}}", fields.join(" "));
+                    }
+                    line.push(':');
+                }
+                let _ = write!(line, " {}:", metadata.target());
+                if let Some(Value::String(message)) = fields.0.remove("message") {
+                    let _ = write!(line, " {message}");
+                }
+                for (k, v) in fields.0 {
+                    let _ = write!(line, " {k}={v}");
+                }
+                line
+            }
+        }
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for NodeLogFiles {
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let scope = match ctx.event_scope(event) {
+            Some(scope) => scope,
+            None => return,
+        };
+        let mut addr = None;
+        let mut spans = Vec::new();
+        for span in scope.from_root() {
+            let extensions = span.extensions();
+            let fields = extensions.get::<Fields>().map(|f| f.0.clone()).unwrap_or_default();
+            if span.name() == "node" {
+                addr = fields.get("addr").and_then(|addr| addr.as_str()).map(String::from);
+            }
+            spans.push((span.name(), fields));
+        }
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        let mut line = self.format_line(event, spans);
+        line.push('\n');
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(&addr) {
+            let path = self.dir.join(format!("{addr}.log"));
+            match File::create(&path) {
+                Ok(file) => files.insert(addr.clone(), file),
+                Err(e) => {
+                    eprintln!("Could not create log file {}: {e}", path.display());
+                    return;
+                }
+            };
+        }
+        let _ = files.get_mut(&addr).unwrap().write_all(line.as_bytes());
+    }
+}
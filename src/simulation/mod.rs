@@ -0,0 +1,17 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! Tools to control the simulated network used for [testing](crate::stream::testing).
+
+mod partitions;
+pub use partitions::*;
+mod health;
+pub use health::*;
+mod topology;
+pub use topology::*;
+mod byzantine;
+pub use byzantine::*;
+mod scenario;
+pub use scenario::*;
+mod logging;
+pub use logging::*;
@@ -0,0 +1,36 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use std::sync::RwLock;
+
+lazy_static::lazy_static!(
+    /// Partition of each node, by index. Nodes that are not listed are in partition 0.
+    static ref PARTITIONS: RwLock<Vec<usize>> = RwLock::new(Vec::new());
+);
+
+/// Splits the network so that nodes can only reach nodes of their own group.
+/// Nodes that are part of no group form an additional group.
+/// 
+/// New connections across groups are refused, and existing ones break on their next write.
+pub fn partition(groups: &[Vec<usize>]) {
+    let mut partitions = PARTITIONS.write().unwrap();
+    partitions.clear();
+    for (i, group) in groups.iter().enumerate() {
+        for node in group {
+            if partitions.len() <= *node {
+                partitions.resize(node + 1, 0);
+            }
+            partitions[*node] = i + 1;
+        }
+    }
+}
+
+/// Removes all partitions.
+pub fn heal() {
+    PARTITIONS.write().unwrap().clear();
+}
+
+pub fn are_partitioned(a: usize, b: usize) -> bool {
+    let partitions = PARTITIONS.read().unwrap();
+    partitions.get(a).unwrap_or(&0) != partitions.get(b).unwrap_or(&0)
+}
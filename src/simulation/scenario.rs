@@ -0,0 +1,371 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+//! Scenario files describe a whole simulation so that it can be replayed without interaction.
+//!
+//! They are made of one directive per line. Empty lines and everything after a `#` are ignored.
+//!
+//! ```text
+//! nodes 60                      # Number of nodes to boot
+//! seeds 1 2 3                   # Run the scenario once per seed (optional)
+//! links wan                     # `perfect`, `wan`, or a list of `latency=20ms..150ms bandwidth=1000000 drop=0.0001 stall=0.0005 stall-duration=45s`
+//! adversaries 0-5 silent        # `bogus-peers`, `silent`, `oversized-lists`, `forged-values` or `spam-discovery [interval]`
+//! config 0-9 bucket-size=4      # Overrides `NodeConfig` fields of some nodes
+//!
+//! at 20s 0-59 refresh-buckets   # Node commands, using the same syntax as the interactive simulation
+//! at 40s partition 0-29 30-59   # Simulation-wide actions
+//! at 60s heal
+//! at 90s expect components = 1  # Expected outcomes
+//! ```
+//!
+//! Simulation-wide actions are `health`, `topology <json|dot> <path>`, `kill <nodes>`, `restart <nodes>`, `add <count>`, `partition <nodes> <nodes>...`, `heal` and `expect <metric> <op> <value>`.
+//! Metrics are `alive`, `components`, `isolated`, `diameter`, `closest`, `fill <level>` and `lookups <count>` (success rate of random lookups).
+
+use super::Misbehavior;
+use crate::{prelude::*, stream::testing::{LinkConditions, Latency}};
+
+/// A simulation described by a scenario file.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub node_count: usize,
+    /// Seeds to run the scenario with. Empty if the seed should be picked at runtime.
+    pub seeds: Vec<u64>,
+    pub links: LinkConditions,
+    pub adversaries: Vec<(Vec<usize>, Misbehavior)>,
+    /// `NodeConfig` overrides as `(nodes, key, value)`, applied in order
+    pub configs: Vec<(Vec<usize>, String, String)>,
+    /// Configuration the overrides apply to, the default one unless changed after parsing
+    pub base_config: NodeConfig,
+    /// Actions to run, sorted by the time they should run at, relative to the launch of the network.
+    pub steps: Vec<(Duration, Action)>,
+}
+
+/// Something that can happen during a simulation.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Sends a command to some nodes.
+    Command(Vec<usize>, Command),
+    Health,
+    Topology { format: TopologyFormat, path: String },
+    Kill(Vec<usize>),
+    Restart(Vec<usize>),
+    Add(usize),
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    Expect(Expectation),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopologyFormat {
+    Json,
+    Dot,
+}
+
+/// A condition the network must satisfy at some point of the simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Number of running nodes
+    Alive,
+    /// See [`NetworkHealth::components`]
+    Components,
+    /// Number of [`NetworkHealth::isolated`] nodes
+    Isolated,
+    /// See [`NetworkHealth::diameter`]. Infinite if the network is not connected.
+    Diameter,
+    /// See [`NetworkHealth::closest_k_accuracy`]
+    Closest,
+    /// See [`NetworkHealth::bucket_fill`]
+    Fill(usize),
+    /// Ratio of successful lookups among that many random ones
+    Lookups(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Lower,
+    LowerOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// For when a scenario file is invalid.
+#[derive(Debug)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(input: Option<&str>) -> Result<T, &'static str> {
+    input.ok_or("Missing number")?.parse().map_err(|_| "Invalid number")
+}
+
+fn parse_links(words: &[&str]) -> Result<LinkConditions, &'static str> {
+    match words {
+        ["perfect"] => return Ok(LinkConditions::default()),
+        ["wan"] => return Ok(LinkConditions::wan()),
+        _ => (),
+    }
+
+    let mut links = LinkConditions::default();
+    for word in words {
+        let (key, value) = word.split_once('=').ok_or("Expected `perfect`, `wan` or `key=value` pairs")?;
+        match key {
+            "latency" => links.latency = match value.split_once("..") {
+                Some((min, max)) => Latency::Uniform(parse_duration(min)?, parse_duration(max)?),
+                None => Latency::Constant(parse_duration(value)?),
+            },
+            "bandwidth" => links.bandwidth = Some(parse_number(Some(value))?),
+            "drop" => links.drop_probability = parse_number(Some(value))?,
+            "stall" => links.stall_probability = parse_number(Some(value))?,
+            "stall-duration" => links.stall_duration = parse_duration(value)?,
+            _ => return Err("Unknown link property"),
+        }
+    }
+    Ok(links)
+}
+
+fn parse_misbehavior(words: &[&str]) -> Result<Misbehavior, &'static str> {
+    match words {
+        ["bogus-peers"] => Ok(Misbehavior::BogusPeers),
+        ["silent"] => Ok(Misbehavior::Silent),
+        ["oversized-lists"] => Ok(Misbehavior::OversizedLists),
+        ["forged-values"] => Ok(Misbehavior::ForgedValues),
+        ["spam-discovery"] => Ok(Misbehavior::SpamDiscovery { interval: Duration::from_secs(1) }),
+        ["spam-discovery", interval] => Ok(Misbehavior::SpamDiscovery { interval: parse_duration(interval)? }),
+        _ => Err("Unknown misbehavior"),
+    }
+}
+
+impl Action {
+    /// Parses a simulation-wide action, or a command prefixed by its destinators.
+    pub fn parse(input: &str) -> Result<Action, CommandParsingError> {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let action = match words.as_slice() {
+            ["health"] => Action::Health,
+            ["topology", "json", path] => Action::Topology { format: TopologyFormat::Json, path: path.to_string() },
+            ["topology", "dot", path] => Action::Topology { format: TopologyFormat::Dot, path: path.to_string() },
+            ["kill", nodes] => Action::Kill(parse_destinators(nodes)?),
+            ["restart", nodes] => Action::Restart(parse_destinators(nodes)?),
+            ["add", count] => Action::Add(parse_number(Some(count))?),
+            ["partition", groups @ ..] if !groups.is_empty() => {
+                Action::Partition(groups.iter().map(|g| parse_destinators(g)).collect::<Result<_, _>>()?)
+            }
+            ["heal"] => Action::Heal,
+            ["expect", expectation @ ..] => Action::Expect(Expectation::parse(expectation)?),
+            _ => {
+                let (destinators, command) = Command::parse(input)?;
+                Action::Command(destinators, command)
+            }
+        };
+        Ok(action)
+    }
+}
+
+impl Expectation {
+    fn parse(words: &[&str]) -> Result<Expectation, &'static str> {
+        let (metric, words) = match words {
+            ["alive", words @ ..] => (Metric::Alive, words),
+            ["components", words @ ..] => (Metric::Components, words),
+            ["isolated", words @ ..] => (Metric::Isolated, words),
+            ["diameter", words @ ..] => (Metric::Diameter, words),
+            ["closest", words @ ..] => (Metric::Closest, words),
+            ["fill", level, words @ ..] => (Metric::Fill(parse_number(Some(level))?), words),
+            ["lookups", count, words @ ..] => (Metric::Lookups(parse_number(Some(count))?), words),
+            _ => return Err("Unknown metric"),
+        };
+        let (comparison, value) = match words {
+            [comparison, value] => (*comparison, parse_number(Some(value))?),
+            _ => return Err("Expected a comparison and a value after the metric"),
+        };
+        let comparison = match comparison {
+            "=" | "==" => Comparison::Equal,
+            "<" => Comparison::Lower,
+            "<=" => Comparison::LowerOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err("Unknown comparison"),
+        };
+        Ok(Expectation { metric, comparison, value })
+    }
+
+    /// Checks whether a measured value of the metric is satisfying.
+    pub fn holds(&self, measured: f64) -> bool {
+        match self.comparison {
+            Comparison::Equal => measured == self.value,
+            Comparison::Lower => measured < self.value,
+            Comparison::LowerOrEqual => measured <= self.value,
+            Comparison::Greater => measured > self.value,
+            Comparison::GreaterOrEqual => measured >= self.value,
+        }
+    }
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.metric {
+            Metric::Alive => write!(f, "alive")?,
+            Metric::Components => write!(f, "components")?,
+            Metric::Isolated => write!(f, "isolated")?,
+            Metric::Diameter => write!(f, "diameter")?,
+            Metric::Closest => write!(f, "closest")?,
+            Metric::Fill(level) => write!(f, "fill {level}")?,
+            Metric::Lookups(count) => write!(f, "lookups {count}")?,
+        }
+        let comparison = match self.comparison {
+            Comparison::Equal => "=",
+            Comparison::Lower => "<",
+            Comparison::LowerOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        };
+        write!(f, " {comparison} {}", self.value)
+    }
+}
+
+impl Scenario {
+    pub fn parse(input: &str) -> Result<Scenario, ScenarioError> {
+        let mut node_count = None;
+        let mut scenario = Scenario {
+            node_count: 0,
+            seeds: Vec::new(),
+            links: LinkConditions::default(),
+            adversaries: Vec::new(),
+            configs: Vec::new(),
+            base_config: NodeConfig::default(),
+            steps: Vec::new(),
+        };
+
+        for (i, line) in input.lines().enumerate() {
+            let error = |message: String| ScenarioError { line: i + 1, message };
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                ["nodes", count] => node_count = Some(parse_number(Some(count)).map_err(|e| error(e.to_string()))?),
+                ["seed" | "seeds", seeds @ ..] => for seed in seeds {
+                    scenario.seeds.push(parse_number(Some(seed)).map_err(|e| error(e.to_string()))?);
+                },
+                ["links", words @ ..] => scenario.links = parse_links(words).map_err(|e| error(e.to_string()))?,
+                ["adversaries", nodes, misbehavior @ ..] => {
+                    let nodes = parse_destinators(nodes).map_err(|e| error(e.to_string()))?;
+                    let misbehavior = parse_misbehavior(misbehavior).map_err(|e| error(e.to_string()))?;
+                    scenario.adversaries.push((nodes, misbehavior));
+                }
+                ["config", nodes, overrides @ ..] if !overrides.is_empty() => {
+                    let nodes = parse_destinators(nodes).map_err(|e| error(e.to_string()))?;
+                    let mut config = NodeConfig::default();
+                    for word in overrides {
+                        let (key, value) = word.split_once('=').ok_or_else(|| error(format!("Expected key=value, got {word}")))?;
+                        config.set(key, value).map_err(|e| error(e.to_string()))?;
+                        scenario.configs.push((nodes.clone(), key.to_string(), value.to_string()));
+                    }
+                }
+                ["at", time, ..] => {
+                    let time = parse_duration(time).map_err(|e| error(e.to_string()))?;
+                    let action = line.splitn(3, char::is_whitespace).nth(2).ok_or_else(|| error(String::from("Missing action")))?;
+                    let action = Action::parse(action.trim()).map_err(|e| error(e.to_string()))?;
+                    scenario.steps.push((time, action));
+                }
+                _ => return Err(error(format!("Unknown directive: {line}"))),
+            }
+        }
+
+        scenario.node_count = node_count.ok_or(ScenarioError { line: 0, message: String::from("Missing node count") })?;
+        scenario.steps.sort_by_key(|(time, _)| *time);
+        Ok(scenario)
+    }
+
+    /// The misbehavior assigned to a node, if any.
+    pub fn misbehavior(&self, i: usize) -> Option<Misbehavior> {
+        self.adversaries.iter().find(|(nodes, _)| nodes.contains(&i)).map(|(_, m)| m.clone())
+    }
+
+    /// The configuration of a node, with all the overrides that apply to it.
+    pub fn config(&self, i: usize) -> NodeConfig {
+        let mut config = self.base_config.clone();
+        for (_, key, value) in self.configs.iter().filter(|(nodes, _, _)| nodes.contains(&i)) {
+            config.set(key, value).expect("overrides are validated when parsing");
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let scenario = Scenario::parse("
+            # Partition and heal
+            nodes 60
+            seeds 1 2
+            links latency=20ms..150ms drop=0.001
+            adversaries 0-2,5 spam-discovery 500ms
+
+            at 1m expect components = 1
+            at 20s 0-59 refresh-buckets
+            at 40s partition 0-29 30-59 # comment
+            at 1m30s heal
+        ");
+        let error = scenario.unwrap_err();
+        assert_eq!(error.line, 11);
+
+        let mut scenario = Scenario::parse("
+            nodes 60
+            seeds 1 2
+            links latency=20ms..150ms drop=0.001
+            adversaries 0-2,5 spam-discovery 500ms
+            config 0-9 bucket-size=4 ping-interval=10s
+            config 5 bucket_size=6
+
+            at 60s expect fill 0 >= 0.9
+            at 20s 0-59 refresh-buckets
+            at 40s partition 0-29 30-59 # comment
+        ").unwrap();
+        assert_eq!(scenario.node_count, 60);
+        assert_eq!(scenario.seeds, vec![1, 2]);
+        assert!(matches!(scenario.links.latency, Latency::Uniform(min, _) if min == Duration::from_millis(20)));
+        assert_eq!(scenario.links.drop_probability, 0.001);
+        assert!(matches!(scenario.misbehavior(5), Some(Misbehavior::SpamDiscovery { interval }) if interval == Duration::from_millis(500)));
+        assert!(scenario.misbehavior(3).is_none());
+        assert_eq!(scenario.config(5).bucket_size, 6);
+        assert_eq!(scenario.config(5).ping_interval, Duration::from_secs(10));
+        assert_eq!(scenario.config(3).bucket_size, 4);
+        assert_eq!(scenario.config(10), NodeConfig::default());
+        scenario.base_config.bucket_size = 5;
+        scenario.base_config.alpha = 1;
+        assert_eq!(scenario.config(5).bucket_size, 6);
+        assert_eq!(scenario.config(5).alpha, 1);
+        assert_eq!(scenario.config(10).bucket_size, 5);
+
+        let times: Vec<u64> = scenario.steps.iter().map(|(time, _)| time.as_secs()).collect();
+        assert_eq!(times, vec![20, 40, 60]);
+        assert!(matches!(&scenario.steps[0].1, Action::Command(nodes, Command::RefreshBuckets) if nodes.len() == 60));
+        assert!(matches!(&scenario.steps[1].1, Action::Partition(groups) if groups.len() == 2 && groups[1][0] == 30));
+        match &scenario.steps[2].1 {
+            Action::Expect(expectation) => {
+                assert_eq!(expectation.metric, Metric::Fill(0));
+                assert!(expectation.holds(0.95) && !expectation.holds(0.85));
+            }
+            _ => panic!("Expected an expectation"),
+        }
+
+        assert!(Scenario::parse("at 10s health").is_err());
+        assert!(Scenario::parse("nodes 10\nconfig 0 alpha=0").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use crate::prelude::*;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Colors of the regions of the ID space, selected by the first two bits of IDs.
+const REGION_COLORS: [&str; 4] = ["#fbb4ae", "#b3cde3", "#ccebc5", "#decbe4"];
+/// Colors of buckets A, B and C.
+const BUCKET_COLORS: [&str; 3] = ["#e41a1c", "#377eb8", "#4daf4a"];
+
+/// A snapshot of all the connections of a simulated network.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkTopology {
+    pub nodes: Vec<NodeTopology>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeTopology {
+    pub peer_id: String,
+    pub addr: String,
+    pub connections: Vec<ConnectionTopology>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionTopology {
+    pub peer_id: String,
+    pub addr: String,
+    pub bucket_level: usize,
+    /// `A`, `B` or `C`
+    pub bucket_id: char,
+    pub ping_ms: Option<f64>,
+}
+
+/// Takes a snapshot of the connections of a set of nodes.
+pub async fn topology(nodes: &[Arc<Node>]) -> NetworkTopology {
+    let mut topology = NetworkTopology { nodes: Vec::with_capacity(nodes.len()) };
+    for node in nodes {
+        let mut connections = Vec::new();
+        for (peer_id, addr, ping_nanos) in node.connections.peers_with_pings().await {
+            let (bucket_level, bucket_id) = match peer_id.bucket(&node.peer_id) {
+                Some(bucket) => bucket,
+                None => continue,
+            };
+            connections.push(ConnectionTopology {
+                peer_id: peer_id.to_string(),
+                addr,
+                bucket_level,
+                bucket_id: ['A', 'B', 'C'][bucket_id],
+                ping_ms: ping_nanos.map(|p| p as f64 / 1_000_000.0),
+            });
+        }
+        topology.nodes.push(NodeTopology {
+            peer_id: node.peer_id.to_string(),
+            addr: node.addr.clone(),
+            connections,
+        });
+    }
+    topology
+}
+
+impl NetworkTopology {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("topology is always serializable")
+    }
+
+    /// Renders the connection graph in the [DOT language](https://graphviz.org/doc/info/lang.html).
+    ///
+    /// Nodes are colored by the region of the ID space they belong to (first two bits of their ID).
+    /// Edges are colored by bucket (A, B or C) and get thinner as the bucket level increases.
+    /// Connections that only one side knows about are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph tewta {\n    node [style=filled];\n");
+        for node in &self.nodes {
+            let region = u8::from_str_radix(&node.peer_id[..1], 16).unwrap_or(0) >> 2;
+            let _ = writeln!(dot, "    \"{}\" [label=\"{}\\n{}\", fillcolor=\"{}\"];", node.addr, node.addr, &node.peer_id[..8], REGION_COLORS[region as usize]);
+        }
+
+        let known: BTreeSet<(&str, &str)> = self.nodes.iter()
+            .flat_map(|n| n.connections.iter().map(move |c| (n.addr.as_str(), c.addr.as_str())))
+            .collect();
+        for node in &self.nodes {
+            for connection in &node.connections {
+                let mutual = known.contains(&(connection.addr.as_str(), node.addr.as_str()));
+                if mutual && connection.addr < node.addr {
+                    continue; // Already written from the other side
+                }
+                let bucket_id = (connection.bucket_id as u8 - b'A') as usize;
+                let width = (4.0 - connection.bucket_level as f64 * 0.5).max(0.5);
+                let mut label = format!("{}{}", connection.bucket_level, connection.bucket_id);
+                if let Some(ping_ms) = connection.ping_ms {
+                    let _ = write!(label, " {:.0}ms", ping_ms);
+                }
+                let _ = writeln!(
+                    dot,
+                    "    \"{}\" -- \"{}\" [color=\"{}\", penwidth={}, label=\"{}\"{}];",
+                    node.addr, connection.addr, BUCKET_COLORS[bucket_id], width, label,
+                    if mutual { "" } else { ", style=dashed" },
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot() {
+        let connection = |peer_id: &str, addr: &str| ConnectionTopology {
+            peer_id: peer_id.to_string(),
+            addr: addr.to_string(),
+            bucket_level: 0,
+            bucket_id: 'C',
+            ping_ms: Some(42.0),
+        };
+        let topology = NetworkTopology {
+            nodes: vec![
+                NodeTopology {
+                    peer_id: "0123456789abcdef".to_string(),
+                    addr: "local-0".to_string(),
+                    connections: vec![connection("f123456789abcdef", "local-1")],
+                },
+                NodeTopology {
+                    peer_id: "f123456789abcdef".to_string(),
+                    addr: "local-1".to_string(),
+                    connections: vec![connection("0123456789abcdef", "local-0")],
+                },
+            ],
+        };
+
+        let dot = topology.to_dot();
+        assert_eq!(dot.matches(" -- ").count(), 1);
+        assert!(dot.contains("\"local-0\" -- \"local-1\" [color=\"#4daf4a\", penwidth=4, label=\"0C 42ms\"];"));
+        assert!(dot.contains(REGION_COLORS[0]) && dot.contains(REGION_COLORS[3]));
+    }
+}
@@ -12,42 +12,134 @@ pub type TcpStream = testing::TestStream;
 
 /// While testing, we build a fake network of thousands of nodes.  
 /// We replace the implementation of TcpStream with a local fake stream that's faster and more scalable.  
+/// 
+/// Links can be degraded with [`LinkConditions`](testing::LinkConditions) in order to simulate real-world networks.
 #[cfg(feature = "test")]
 pub mod testing {
     use super::*;
+    use std::{collections::VecDeque, sync::Mutex as SyncMutex, io::ErrorKind, future::Future};
+
+    /// Distribution of the delay between a write and the moment the bytes become readable.
+    #[derive(Debug, Clone)]
+    pub enum Latency {
+        Constant(Duration),
+        Uniform(Duration, Duration),
+    }
+
+    impl Latency {
+        fn sample(&self) -> Duration {
+            match self {
+                Latency::Constant(d) => *d,
+                Latency::Uniform(min, max) if min >= max => *min,
+                Latency::Uniform(min, max) => rng().gen_range(*min..*max),
+            }
+        }
+    }
+
+    /// Quality of a simulated link between two nodes.
+    /// The default value is a perfect link.
+    #[derive(Debug, Clone)]
+    pub struct LinkConditions {
+        pub latency: Latency,
+        /// Maximum throughput of the link, in bytes per second.
+        pub bandwidth: Option<u64>,
+        /// Probability for each write to break the connection.
+        pub drop_probability: f64,
+        /// Probability for each write to freeze the link for `stall_duration`.
+        pub stall_probability: f64,
+        pub stall_duration: Duration,
+    }
+
+    impl Default for LinkConditions {
+        fn default() -> Self {
+            LinkConditions {
+                latency: Latency::Constant(Duration::ZERO),
+                bandwidth: None,
+                drop_probability: 0.0,
+                stall_probability: 0.0,
+                stall_duration: Duration::ZERO,
+            }
+        }
+    }
+
+    impl LinkConditions {
+        /// Typical conditions between two residential connections.
+        pub fn wan() -> Self {
+            LinkConditions {
+                latency: Latency::Uniform(Duration::from_millis(20), Duration::from_millis(150)),
+                bandwidth: Some(1_000_000),
+                drop_probability: 0.0001,
+                stall_probability: 0.0005,
+                stall_duration: Duration::from_secs(45),
+            }
+        }
+    }
+
+    /// Decides the conditions of the link between two nodes, given their indexes.
+    pub trait LinkModel: Send + Sync {
+        fn conditions(&self, from: usize, to: usize) -> LinkConditions;
+    }
+
+    impl LinkModel for LinkConditions {
+        fn conditions(&self, _from: usize, _to: usize) -> LinkConditions {
+            self.clone()
+        }
+    }
+
+    impl<F: Fn(usize, usize) -> LinkConditions + Send + Sync> LinkModel for F {
+        fn conditions(&self, from: usize, to: usize) -> LinkConditions {
+            self(from, to)
+        }
+    }
+
+    /// One direction of a link
+    #[derive(Default)]
+    struct Pipe {
+        /// Written bytes along with the instant they become readable
+        chunks: VecDeque<(Instant, Vec<u8>)>,
+        /// When the link will be done transmitting the bytes already written
+        free_at: Option<Instant>,
+        waken_on_readable: Option<Waker>,
+        /// The write half has been dropped
+        closed: bool,
+    }
+
+    struct Link {
+        conditions: LinkConditions,
+        /// Indexes of the nodes on both ends
+        endpoints: (usize, usize),
+        /// The connection has been dropped by the network
+        broken: std::sync::atomic::AtomicBool,
+    }
 
     /// A fake [TcpStream] used for [testing].  
     /// Implements [`AsyncRead`] and [`AsyncWrite`].
     pub struct TestStream {
-        inbound: Arc<Mutex<Vec<u8>>>,
-        outbound: Arc<Mutex<Vec<u8>>>,
-        to_wake_on_write: Arc<Mutex<Option<Waker>>>,
-        waken_on_readable: Arc<Mutex<Option<Waker>>>,
+        inbound: Arc<SyncMutex<Pipe>>,
+        outbound: Arc<SyncMutex<Pipe>>,
+        link: Arc<Link>,
         log_id: (usize, bool),
     }
 
     impl TestStream {
-        pub fn new() -> (Self, Self) {
-            let inbound = Arc::new(Mutex::new(Vec::new()));
-            let outbound = Arc::new(Mutex::new(Vec::new()));
-            let to_wake_on_write = Arc::new(Mutex::new(None));
-            let waken_on_readable = Arc::new(Mutex::new(None));
+        pub fn new(conditions: LinkConditions, endpoints: (usize, usize)) -> (Self, Self) {
+            let inbound = Arc::new(SyncMutex::new(Pipe::default()));
+            let outbound = Arc::new(SyncMutex::new(Pipe::default()));
+            let link = Arc::new(Link { conditions, endpoints, broken: Default::default() });
             // generate random log_id
-            let log_id = rand::thread_rng().gen_range(0..1000000);
+            let log_id = rng().gen_range(0..1000000);
     
             (
                 TestStream {
                     inbound: inbound.clone(),
                     outbound: outbound.clone(),
-                    to_wake_on_write: to_wake_on_write.clone(),
-                    waken_on_readable: waken_on_readable.clone(),
+                    link: link.clone(),
                     log_id: (log_id, true),
                 },
                 TestStream {
                     inbound: outbound,
                     outbound: inbound,
-                    to_wake_on_write: waken_on_readable,
-                    waken_on_readable: to_wake_on_write,
+                    link,
                     log_id: (log_id, false),
                 },
             )
@@ -56,19 +148,15 @@ pub mod testing {
         pub fn into_split(self) -> (TestReadHalf, TestWriteHalf) {
             (
                 TestReadHalf {
-                    data: Arc::clone(&self.inbound),
-                    waken_on_readable: Arc::clone(&self.waken_on_readable),
-                    lock_fut: None,
-                    waker_lock_fut: None,
+                    pipe: Arc::clone(&self.inbound),
+                    link: Arc::clone(&self.link),
+                    delay: None,
                     log_id: self.log_id,
                 },
                 TestWriteHalf {
-                    data: Arc::clone(&self.outbound),
-                    to_wake_on_write: Arc::clone(&self.to_wake_on_write),
-                    wrote: false,
-                    woke: false,
-                    lock_fut: None,
-                    waker_lock_fut: None,
+                    pipe: Arc::clone(&self.outbound),
+                    other_pipe: Arc::clone(&self.inbound),
+                    link: Arc::clone(&self.link),
                     log_id: self.log_id,
                 }
             )
@@ -76,31 +164,40 @@ pub mod testing {
     }
 
     pub struct TestReadHalf {
-        data: Arc<Mutex<Vec<u8>>>,
-        waken_on_readable: Arc<Mutex<Option<Waker>>>,
-        lock_fut: Option<BoxFuture<'static, MutexGuardArc<Vec<u8>>>>,
-        waker_lock_fut: Option<BoxFuture<'static, MutexGuardArc<Option<Waker>>>>,
+        pipe: Arc<SyncMutex<Pipe>>,
+        link: Arc<Link>,
+        delay: Option<Pin<Box<tokio::time::Sleep>>>,
         log_id: (usize, bool),
     }
 
     pub struct TestWriteHalf {
-        data: Arc<Mutex<Vec<u8>>>,
-        to_wake_on_write: Arc<Mutex<Option<Waker>>>,
-        wrote: bool,
-        woke: bool,
-        lock_fut: Option<BoxFuture<'static, MutexGuardArc<Vec<u8>>>>,
-        waker_lock_fut: Option<BoxFuture<'static, MutexGuardArc<Option<Waker>>>>,
+        pipe: Arc<SyncMutex<Pipe>>,
+        /// Needed to wake our own read half when the connection breaks
+        other_pipe: Arc<SyncMutex<Pipe>>,
+        link: Arc<Link>,
         log_id: (usize, bool),
     }
 
     impl TestReadHalf {
         pub fn reunite(self, other: TestWriteHalf) -> Result<TestStream, tokio::net::tcp::ReuniteError> {
             assert_eq!(self.log_id, other.log_id);
+
+            // The write half must not be dropped as it would close the pipe
+            let other = std::mem::ManuallyDrop::new(other);
+            // JUSTIFICATION
+            //  Benefit
+            //      We have to move fields out of a type implementing Drop.
+            //  Soundness
+            //      Each field is read exactly once and `other` will never be used nor dropped again.
+            let (outbound, other_pipe, other_link) = unsafe {
+                (std::ptr::read(&other.pipe), std::ptr::read(&other.other_pipe), std::ptr::read(&other.link))
+            };
+            std::mem::drop((other_pipe, other_link));
+
             Ok(TestStream {
-                inbound: self.data,
-                outbound: other.data,
-                to_wake_on_write: other.to_wake_on_write,
-                waken_on_readable: self.waken_on_readable,
+                inbound: self.pipe,
+                outbound,
+                link: self.link,
                 log_id: self.log_id,
             })
         }
@@ -112,96 +209,124 @@ pub mod testing {
             cx: &mut std::task::Context<'_>,
             buf: &mut tokio::io::ReadBuf<'_>,
         ) -> std::task::Poll<std::io::Result<()>> {
-            trace!(LogLevel::from(0), "ReadHalf {}{}: polled", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+            tracing::trace!("ReadHalf {}{}: polled", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
 
-            // Updating waker
-            if self.waker_lock_fut.is_none() {
-                let self_waken_on_readable = Arc::clone(&self.waken_on_readable);
-                self.waker_lock_fut = Some(async move { self_waken_on_readable.lock_arc().await }.boxed());
-            }
-            if let Poll::Ready(mut waker) = self.waker_lock_fut.as_mut().unwrap().as_mut().poll(cx) {
-                self.waker_lock_fut = None;
-                
-                *waker = Some(cx.waker().clone());
-                trace!(LogLevel::from(0), "ReadHalf {}{}: waker updated", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
-            }
-            
-            // Checking readable
-            if self.lock_fut.is_none() {
-                let self_outbound = Arc::clone(&self.data);
-                self.lock_fut = Some(async move { self_outbound.lock_arc().await }.boxed());
-            }
-            if let Poll::Ready(mut data) = self.lock_fut.as_mut().unwrap().as_mut().poll(cx) {
-                self.lock_fut = None;
-                
-                if !data.is_empty() {
-                    trace!(LogLevel::from(0), "ReadHalf {}{}: data ready", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
-                    if buf.remaining() < data.len() {
+            loop {
+                if self.link.broken.load(std::sync::atomic::Ordering::Relaxed) {
+                    return Poll::Ready(Err(ErrorKind::ConnectionReset.into()));
+                }
+
+                let pipe = Arc::clone(&self.pipe);
+                let mut pipe = pipe.lock().unwrap();
+                pipe.waken_on_readable = Some(cx.waker().clone());
+
+                // Read all chunks that already went through the link
+                let now = Instant::now();
+                let mut read = false;
+                while let Some((readable_at, chunk)) = pipe.chunks.front_mut() {
+                    if *readable_at > now || buf.remaining() == 0 {
+                        break;
+                    }
+                    read = true;
+                    if buf.remaining() < chunk.len() {
                         let size = buf.remaining();
-                        buf.put_slice(&data[..size]);
-                        data.drain(..size);
+                        buf.put_slice(&chunk[..size]);
+                        chunk.drain(..size);
                     } else {
-                        buf.put_slice(data.as_slice());
-                        data.clear();
+                        buf.put_slice(chunk);
+                        pipe.chunks.pop_front();
                     }
+                }
+                if read {
+                    tracing::trace!("ReadHalf {}{}: data ready", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                    self.delay = None;
                     return Poll::Ready(Ok(()));
-                } else {
-                    trace!(LogLevel::from(0), "ReadHalf {}{}: not readable", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                }
+
+                // Wait for the next chunk to go through the link
+                match pipe.chunks.front() {
+                    Some((readable_at, _)) => {
+                        let readable_at = *readable_at;
+                        std::mem::drop(pipe);
+                        match self.delay.as_mut() {
+                            Some(delay) => delay.as_mut().reset(readable_at),
+                            None => self.delay = Some(Box::pin(tokio::time::sleep_until(readable_at))),
+                        }
+                        if self.delay.as_mut().unwrap().as_mut().poll(cx).is_pending() {
+                            return Poll::Pending;
+                        }
+                    }
+                    None if pipe.closed => return Poll::Ready(Ok(())), // EOF
+                    None => {
+                        tracing::trace!("ReadHalf {}{}: not readable", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+
+    impl TestWriteHalf {
+        /// Breaks the connection and wakes both read halves so that they notice.
+        fn break_link(&self) {
+            self.link.broken.store(true, std::sync::atomic::Ordering::Relaxed);
+            for pipe in [&self.pipe, &self.other_pipe] {
+                if let Some(waker) = pipe.lock().unwrap().waken_on_readable.take() {
+                    waker.wake();
                 }
             }
-            
-            Poll::Pending
         }
     }
 
     impl AsyncWrite for TestWriteHalf {
         fn poll_write(
-            mut self: Pin<&mut Self>,
-            cx: &mut std::task::Context<'_>,
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
             buf: &[u8],
         ) -> Poll<Result<usize, std::io::Error>> {
-            if !self.wrote {
-                if self.lock_fut.is_none() {
-                    let self_outbound = Arc::clone(&self.data);
-                    self.lock_fut = Some(async move { self_outbound.lock_arc().await }.boxed());
-                }
+            if self.link.broken.load(std::sync::atomic::Ordering::Relaxed) {
+                return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
+            }
 
-                if let Poll::Ready(mut data) = self.lock_fut.as_mut().unwrap().as_mut().poll(cx) {
-                    self.lock_fut = None;
-    
-                    data.extend_from_slice(buf);
-                    self.wrote = true;
-                    trace!(LogLevel::from(0), "WriteHalf {}{}: wrote {} bytes", self.log_id.0, ['A', 'B'][self.log_id.1 as usize], buf.len());
-                }
+            let (from, to) = self.link.endpoints;
+            if crate::simulation::are_partitioned(from, to) {
+                tracing::trace!("WriteHalf {}{}: link crosses a partition", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                self.break_link();
+                return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
             }
-            
-            if self.wrote && !self.woke {
-                if self.waker_lock_fut.is_none() {
-                    let self_to_wake_on_write = Arc::clone(&self.to_wake_on_write);
-                    self.waker_lock_fut = Some(async move { self_to_wake_on_write.lock_arc().await }.boxed());
-                }
 
-                if let Poll::Ready(waker) = self.waker_lock_fut.as_mut().unwrap().as_mut().poll(cx) {
-                    self.waker_lock_fut = None;
-                    
-                    if let Some(waker) = waker.clone() {
-                        waker.wake();
-                        trace!(LogLevel::from(0), "WriteHalf {}{}: woke read half", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
-                    } else {
-                        trace!(LogLevel::from(0), "WriteHalf {}{}: did not wake", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
-                    }
-                    self.woke = true;
-                }
+            let conditions = &self.link.conditions;
+            if conditions.drop_probability > 0.0 && rng().gen_bool(conditions.drop_probability) {
+                tracing::trace!("WriteHalf {}{}: connection dropped", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                self.break_link();
+                return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
             }
-            
-            match self.wrote && self.woke {
-                true => {
-                    self.wrote = false;
-                    self.woke = false;
-                    Poll::Ready(Ok(buf.len()))
-                },
-                false => Poll::Pending,
+
+            let mut pipe = self.pipe.lock().unwrap();
+            let now = Instant::now();
+            let mut free_at = pipe.free_at.unwrap_or(now).max(now);
+            if conditions.stall_probability > 0.0 && rng().gen_bool(conditions.stall_probability) {
+                tracing::trace!("WriteHalf {}{}: link stalled", self.log_id.0, ['A', 'B'][self.log_id.1 as usize]);
+                free_at += conditions.stall_duration;
             }
+            if let Some(bandwidth) = conditions.bandwidth {
+                free_at += Duration::from_secs_f64(buf.len() as f64 / bandwidth as f64);
+            }
+            pipe.free_at = Some(free_at);
+
+            // Bytes can't overtake the ones written before
+            let mut readable_at = free_at + conditions.latency.sample();
+            if let Some((last_readable_at, _)) = pipe.chunks.back() {
+                readable_at = readable_at.max(*last_readable_at);
+            }
+            pipe.chunks.push_back((readable_at, buf.to_vec()));
+            tracing::trace!("WriteHalf {}{}: wrote {} bytes", self.log_id.0, ['A', 'B'][self.log_id.1 as usize], buf.len());
+
+            if let Some(waker) = pipe.waken_on_readable.take() {
+                waker.wake();
+            }
+
+            Poll::Ready(Ok(buf.len()))
         }
     
         fn poll_flush(self: Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), std::io::Error>> {
@@ -212,4 +337,14 @@ pub mod testing {
             unimplemented!("Shutdown on virtual testing streams is not implemented");
         }
     }
+
+    impl Drop for TestWriteHalf {
+        fn drop(&mut self) {
+            let mut pipe = self.pipe.lock().unwrap();
+            pipe.closed = true;
+            if let Some(waker) = pipe.waken_on_readable.take() {
+                waker.wake();
+            }
+        }
+    }
 }
@@ -1,7 +1,7 @@
 // Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
-use std::mem::MaybeUninit;
+use std::{mem::MaybeUninit, time::Duration};
 
 /// # Safety
 /// 
@@ -10,3 +10,17 @@ pub unsafe fn uninit_array() -> [u8; 32] {
     let array: [MaybeUninit<u8>; 32] = MaybeUninit::uninit().assume_init();
     std::mem::transmute(array)
 }
+
+/// Parses durations such as `500ms`, `10s`, `2m` or `1h`.
+pub fn parse_duration(input: &str) -> Result<Duration, &'static str> {
+    let split = input.find(|c: char| !c.is_ascii_digit() && c != '.').ok_or("Missing duration unit")?;
+    let value: f64 = input[..split].parse().map_err(|_| "Invalid duration")?;
+    let unit = match &input[split..] {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err("Unknown duration unit"),
+    };
+    Ok(Duration::from_secs_f64(value * unit))
+}
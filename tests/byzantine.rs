@@ -0,0 +1,51 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_byzantine() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    // One node out of five misbehaves, in all the ways we know of
+    let adversaries = |i: usize| match (i % 5, i / 5 % 5) {
+        (0, 0) => Some(Misbehavior::BogusPeers),
+        (0, 1) => Some(Misbehavior::Silent),
+        (0, 2) => Some(Misbehavior::OversizedLists),
+        (0, 3) => Some(Misbehavior::ForgedValues),
+        (0, _) => Some(Misbehavior::SpamDiscovery { interval: Duration::from_secs(1) }),
+        _ => None,
+    };
+    let network = launch_network(100, seed_from_env(), LinkConditions::default(), adversaries).await;
+
+    // Wait for network to boot
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    publish_values(&network).await;
+    let success_rate = lookup_success_rate(&network, 40).await;
+    eprintln!("Lookup success rate with 20% adversaries: {success_rate}");
+    assert!(success_rate >= 0.9, "Honest nodes failed too many lookups ({success_rate})");
+
+    // Looking up the key of a silent peer ends up asking it, through temporary connections that must be closed once the request timed out.
+    // Bucket refreshes can legitimately connect some nodes to it afterwards, but not all of them.
+    let silent = &network.nodes[5];
+    assert!(matches!(adversaries(5), Some(Misbehavior::Silent)));
+    let mut checked = 0;
+    let mut kept = 0;
+    for i in network.honest() {
+        let node = &network.nodes[i];
+        if checked >= 5 || node.connections.contains(&silent.peer_id).await {
+            continue;
+        }
+        node.dht_lookup(silent.peer_id.clone()).await;
+        if node.connections.contains(&silent.peer_id).await {
+            kept += 1;
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
+    assert!(kept < checked, "Temporary connections to a silent peer were kept ({kept}/{checked})");
+}
@@ -0,0 +1,45 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::simulation::inspect;
+
+#[tokio::test(start_paused = true)]
+async fn test_churn() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let mut network = launch_network(100, seed_from_env(), LinkConditions::default(), honest).await;
+
+    // Wait for network to boot
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    publish_values(&network).await;
+    let initial_success_rate = lookup_success_rate(&network, 30).await;
+    let initial_fill = inspect(&network.alive_nodes()).await.bucket_fill[0];
+    eprintln!("Before churn: lookup success rate {initial_success_rate}, bucket fill {initial_fill}");
+
+    // A third of the network is replaced
+    let churn = Churn {
+        interval: Duration::from_secs(20),
+        departures: 5,
+        arrivals: 5,
+        restart_probability: 0.5,
+    };
+    network.churn(&churn, Duration::from_secs(120)).await;
+    assert_eq!(network.alive().len(), 100);
+
+    // Let the network recover
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    publish_values(&network).await;
+    let success_rate = lookup_success_rate(&network, 30).await;
+    let fill = inspect(&network.alive_nodes()).await.bucket_fill[0];
+    eprintln!("After churn: lookup success rate {success_rate}, bucket fill {fill}");
+
+    assert!(success_rate >= 0.85, "Lookup success rate did not recover ({success_rate})");
+    assert!(fill >= initial_fill * 0.9, "Bucket fill did not recover ({fill} < {initial_fill})");
+}
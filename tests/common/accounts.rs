@@ -0,0 +1,90 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use tewta::prelude::*;
+use super::*;
+
+/// Builds the accounts published by tests.
+/// Accounts are empty and backed up by a peer that doesn't exist unless told otherwise.
+pub struct AccountBuilder {
+    account: AccountData,
+}
+
+impl AccountBuilder {
+    pub fn new(username: &str) -> AccountBuilder {
+        AccountBuilder {
+            account: AccountData {
+                username: username.to_string(),
+                followers: SegmentedArray::from(Vec::new()),
+                follower_count: 0,
+                following: SegmentedArray::from(Vec::new()),
+                following_count: 0,
+                backup_peer_id: format!("{:064x}", 4242).parse().unwrap(),
+                props: BTreeMap::new(),
+                posts: SegmentedArray::from(Vec::new()),
+                interactions: SegmentedArray::from(Vec::new()),
+            },
+        }
+    }
+
+    /// Follows the accounts of these nodes, at their current address
+    pub fn following(mut self, nodes: &[&Arc<Node>]) -> AccountBuilder {
+        let following: Vec<UserMention> = nodes.iter().map(|node| UserMention {
+            username: String::new(),
+            peer_id: node.peer_id.clone(),
+            cached_addr: Some(node.addr.clone()),
+            providers_addrs: Vec::new(),
+            cached_at: 0,
+        }).collect();
+        self.account.following_count = following.len() as u32;
+        self.account.following = SegmentedArray::from(following);
+        self
+    }
+
+    /// Adds `count` followers that don't exist
+    pub fn followers(mut self, count: u32) -> AccountBuilder {
+        let followers: Vec<UserMention> = (0..count).map(|i| UserMention {
+            username: format!("follower{i}"),
+            peer_id: format!("{:064x}", i).parse().unwrap(),
+            cached_addr: None,
+            providers_addrs: Vec::new(),
+            cached_at: 0,
+        }).collect();
+        self.account.follower_count = count;
+        self.account.followers = SegmentedArray::from(followers);
+        self
+    }
+
+    /// Makes the key of this node able to recover the account
+    pub fn backup(mut self, node: &Node) -> AccountBuilder {
+        self.account.backup_peer_id = node.peer_id.clone();
+        self
+    }
+
+    pub fn build(self) -> AccountData {
+        self.account
+    }
+}
+
+/// Shorthand for an account with default options.
+pub fn account(username: &str) -> AccountData {
+    AccountBuilder::new(username).build()
+}
+
+/// Boots the network of 30 honest nodes on which accounts are published, and waits for buckets to fill.
+pub async fn launch_social_network() -> Network {
+    launch_configured_social_network(|_| NodeConfig::default()).await
+}
+
+/// Same as [`launch_social_network`], with nodes configured by `configs` from their index.
+pub async fn launch_configured_social_network(configs: impl Fn(usize) -> NodeConfig + 'static) -> Network {
+    let network = launch_configured_network(30, seed_from_env(), LinkConditions::default(), honest, configs).await;
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 1).await;
+    network
+}
+
+/// Texts of the posts in a timeline, in order.
+pub fn texts(entries: &[TimelineEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.post.text.as_str()).collect()
+}
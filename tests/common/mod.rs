@@ -1,61 +1,255 @@
 // Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
 // Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
 
-use std::{sync::Arc, io::Write};
-use async_channel::{Receiver, Sender};
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use async_channel::Receiver;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use rand::{Rng, seq::{IteratorRandom, SliceRandom}};
+#[allow(unused_imports)]
+use tewta::{stream::*, commands::*, node::*, packets::*, peers::*, util::*, logging::*, account::*, signed_data::*, constants::*, random::rng, *};
+
+mod scenario;
+#[allow(unused_imports)]
+pub use scenario::*;
+mod accounts;
 #[allow(unused_imports)]
-use tewta::{stream::*, commands::*, node::*, packets::*, peers::*, util::*, logging::*, *};
+pub use accounts::*;
 
+#[allow(unused_imports)]
 pub use {
     tokio::time::sleep,
     std::time::Duration,
+    tewta::random::testing::seed_from_env,
+    tewta::stream::testing::{LinkConditions, LinkModel},
+    tewta::simulation::Misbehavior,
 };
 
-pub async fn run_node(addr: String, conn_receiver: Receiver<TcpStream>, command_receiver: CommandReceiver, print_command_input: bool) -> Arc<Node> {
-    let node = Node::new(addr).await;
-
+pub fn run_node(node: Arc<Node>, conn_receiver: Receiver<TcpStream>, command_receiver: CommandReceiver) -> Vec<JoinHandle<()>> {
+    let span = node.span.clone();
     let node2 = Arc::clone(&node);
-    tokio::spawn(async move {
+    let connection_task = tokio::spawn(async move {
         let node = node2;
         loop {
             let stream = conn_receiver.recv().await.unwrap();
             node.on_connection(stream).await;
         }
-    });
+    }.instrument(span.clone()));
 
-    let node2 = Arc::clone(&node);
-    tokio::spawn(async move {
-        let node = node2;
+    let command_task = tokio::spawn(async move {
         loop {
-            let command = command_receiver.wait_command().await;
-            node.on_command(command).await;
-    
-            // Print command input chars if no command is running anymore
-            if unsafe { RUNNING_COMMAND_COUNTER.fetch_sub(1, std::sync::atomic::Ordering::Relaxed) } == 1 && print_command_input {
-                print!("\x1b[32m>>> \x1b[0m");
-                std::io::stdout().flush().unwrap();
-            }
+            let (command, replier) = command_receiver.wait_command().await;
+            replier.reply(node.on_command(command).await);
         }
-    });
+    }.instrument(span));
 
-    node
+    vec![connection_task, command_task]
 }
 
 /// TODO [#49]: Manage command senders somewhere else as only the simulation needs it
 
-pub async fn launch_network(node_count: usize, print_command_input: bool) -> (Vec<Sender<Command>>, Vec<Arc<Node>>) {
-    env_logger::init();
-    unsafe {NODE_COUNT.store(node_count, std::sync::atomic::Ordering::Relaxed)};
+/// A running simulated network.
+/// Nodes are identified by their index, which never changes, even when they are killed or restarted.
+pub struct Network {
+    pub command_senders: Vec<CommandSender>,
+    pub nodes: Vec<Arc<Node>>,
+    tasks: Vec<Vec<JoinHandle<()>>>,
+    alive: Vec<bool>,
+    adversaries: Box<dyn Fn(usize) -> Option<Misbehavior>>,
+    configs: Box<dyn Fn(usize) -> NodeConfig>,
+}
 
-    let mut command_senders = Vec::new();
-    let mut nodes = Vec::new();
-    for i in 0..node_count {
+/// Describes how nodes come and go.
+#[derive(Debug, Clone)]
+pub struct Churn {
+    /// Time between two rounds of churn
+    pub interval: Duration,
+    /// Number of nodes killed at each round
+    pub departures: usize,
+    /// Number of nodes joining at each round
+    pub arrivals: usize,
+    /// Probability for a joining node to be a restarted dead node rather than a new one
+    pub restart_probability: f64,
+}
+
+impl Network {
+    async fn start_node(&mut self, i: usize, node: Arc<Node>) {
         let (command_receiver, command_sender) = CommandReceiver::new();
-        command_senders.push(command_sender);
         let (connection_sender, connection_receiver) = async_channel::unbounded();
-        LISTENERS.lock().await.push(connection_sender);
-        nodes.push(run_node(format!("local-{}", i), connection_receiver, command_receiver, print_command_input).await);
+        let mut listeners = LISTENERS.lock().await;
+        if i < listeners.len() {
+            listeners[i] = connection_sender;
+            self.command_senders[i] = command_sender;
+            self.tasks[i] = run_node(Arc::clone(&node), connection_receiver, command_receiver);
+            self.nodes[i] = node;
+            self.alive[i] = true;
+        } else {
+            listeners.push(connection_sender);
+            self.command_senders.push(command_sender);
+            self.tasks.push(run_node(Arc::clone(&node), connection_receiver, command_receiver));
+            self.nodes.push(node);
+            self.alive.push(true);
+            unsafe { NODE_COUNT.fetch_max(listeners.len(), std::sync::atomic::Ordering::Relaxed) };
+        }
+    }
+
+    /// Adds a brand new node to the network and returns its index.
+    pub async fn add_node(&mut self) -> usize {
+        let i = self.nodes.len();
+        simulation::set_misbehavior(i, (self.adversaries)(i));
+        let node = Node::new(format!("local-{}", i), (self.configs)(i)).await;
+        self.start_node(i, node).await;
+        i
+    }
+
+    /// Simulates a crash of a node.
+    /// Its peers will notice their connections broke, and nobody will be able to connect to it.
+    pub async fn kill(&mut self, i: usize) {
+        if !self.alive[i] {
+            return;
+        }
+        for task in self.tasks[i].drain(..) {
+            task.abort();
+        }
+        self.nodes[i].kill().await;
+        self.alive[i] = false;
+    }
+
+    /// Restarts a dead node with the same identity.
+    /// Everything else (connections, DHT entries) is lost.
+    pub async fn restart(&mut self, i: usize) {
+        assert!(!self.alive[i], "Node {i} is still running");
+        let private_key = self.nodes[i].rsa_private_key.clone();
+        let node = Node::with_private_key(format!("local-{}", i), private_key, (self.configs)(i)).await;
+        self.start_node(i, node).await;
     }
 
-    (command_senders, nodes)
+    pub fn is_alive(&self, i: usize) -> bool {
+        self.alive[i]
+    }
+
+    /// Indexes of running nodes
+    pub fn alive(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|i| self.alive[*i]).collect()
+    }
+
+    pub fn alive_nodes(&self) -> Vec<Arc<Node>> {
+        self.alive().into_iter().map(|i| Arc::clone(&self.nodes[i])).collect()
+    }
+
+    /// Indexes of running nodes that follow the protocol
+    pub fn honest(&self) -> Vec<usize> {
+        self.alive().into_iter().filter(|i| simulation::misbehavior(*i).is_none()).collect()
+    }
+
+    /// Kills and adds nodes at regular intervals for `duration`.
+    pub async fn churn(&mut self, churn: &Churn, duration: Duration) {
+        let rounds = duration.as_nanos() / churn.interval.as_nanos().max(1);
+        for _ in 0..rounds {
+            sleep(churn.interval).await;
+
+            let departing = self.alive().into_iter().choose_multiple(&mut rng(), churn.departures);
+            for i in departing {
+                self.kill(i).await;
+            }
+
+            for _ in 0..churn.arrivals {
+                let dead = (0..self.nodes.len()).filter(|i| !self.alive[*i]).choose(&mut rng());
+                match dead {
+                    Some(i) if rng().gen_bool(churn.restart_probability) => self.restart(i).await,
+                    _ => { self.add_node().await; },
+                }
+            }
+        }
+    }
+}
+
+/// Boots a network of `node_count` nodes whose randomness all derives from `seed`.
+/// Connections between nodes go through links whose conditions are decided by `links`.
+/// Nodes misbehave as decided by `adversaries` from their index (see [`honest`]).
+///
+/// Run under a single-threaded runtime with a paused clock (`#[tokio::test(start_paused = true)]`) to get reproducible results.
+pub async fn launch_network(node_count: usize, seed: u64, links: impl LinkModel + 'static, adversaries: impl Fn(usize) -> Option<Misbehavior> + 'static) -> Network {
+    launch_configured_network(node_count, seed, links, adversaries, |_| NodeConfig::default()).await
+}
+
+/// Same as [`launch_network`], with nodes configured by `configs` from their index.
+pub async fn launch_configured_network(
+    node_count: usize,
+    seed: u64,
+    links: impl LinkModel + 'static,
+    adversaries: impl Fn(usize) -> Option<Misbehavior> + 'static,
+    configs: impl Fn(usize) -> NodeConfig + 'static,
+) -> Network {
+    simulation::init_logging_from_env();
+    eprintln!("Simulation seed: {seed} (set TEWTA_SEED={seed} to replay)");
+    random::testing::set_seed(seed);
+    *LINK_MODEL.write().unwrap() = Box::new(links);
+
+    let mut network = Network {
+        command_senders: Vec::new(),
+        nodes: Vec::new(),
+        tasks: Vec::new(),
+        alive: Vec::new(),
+        adversaries: Box::new(adversaries),
+        configs: Box::new(configs),
+    };
+    for _ in 0..node_count {
+        network.add_node().await;
+    }
+
+    network
+}
+
+/// Adversary model of networks where everyone follows the protocol.
+pub fn honest(_i: usize) -> Option<Misbehavior> {
+    None
+}
+
+/// Makes each running honest node store a DHT value under its own ID.
+pub async fn publish_values(network: &Network) {
+    for i in network.honest() {
+        let node = &network.nodes[i];
+        let key = node.peer_id.to_owned();
+        let value = DhtValue {
+            provider: node.peer_id.clone(),
+            cached_addr: None,
+            account_snapshot_desc: AccountSnapshotDescriptor {
+                timestamp: 0,
+                hash: [0; 32],
+                backup_peer_id: node.peer_id.clone(),
+                size: 0,
+            }.sign(&node.rsa_public_key, &node.rsa_private_key).unwrap(),
+            migration: None,
+            interaction: None,
+        };
+        node.dht.set(key, value).await;
+    }
+}
+
+/// Makes random running honest nodes look up values published by other random running honest nodes.
+/// Returns the ratio of successful lookups.
+pub async fn lookup_success_rate(network: &Network, lookups: usize) -> f64 {
+    let alive = network.honest();
+    let mut successes = 0;
+    for _ in 0..lookups {
+        let from = &network.nodes[*alive.choose(&mut rng()).unwrap()];
+        let target = &network.nodes[*alive.choose(&mut rng()).unwrap()];
+        if let Ok(Some(_)) = tokio::time::timeout(Duration::from_secs(60), from.dht_lookup(target.peer_id.to_owned())).await {
+            successes += 1;
+        }
+    }
+    successes as f64 / lookups as f64
+}
+
+/// Refreshes the buckets of all running nodes and waits for them to fill.
+pub async fn refresh_all_buckets(network: &Network, rounds: usize) {
+    for _ in 0..rounds {
+        for node in network.alive_nodes() {
+            node.connections.refresh_buckets().await; // This will complete immediately so no need to spawn futures
+        }
+        sleep(Duration::from_secs(10)).await;
+    }
 }
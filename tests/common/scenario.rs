@@ -0,0 +1,127 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+use super::*;
+use tewta::simulation::{Action, Expectation, Metric, Scenario, TopologyFormat, inspect, topology, partition, heal};
+use tokio::time::Instant;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+
+impl Network {
+    /// Sends a command to some nodes.
+    /// Replies are yielded as commands complete, tagged with the index of the node.
+    pub fn send_command(&self, destinators: &[usize], command: Command) -> FuturesUnordered<BoxFuture<'static, (usize, Result<CommandOutput, String>)>> {
+        destinators.iter().map(|i| {
+            let i = *i;
+            let sender = self.command_senders.get(i).filter(|_| self.alive[i]).cloned();
+            let command = command.clone();
+            async move {
+                let sender = match sender {
+                    Some(sender) => sender,
+                    None => return (i, Err(format!("Node {i} is not running"))),
+                };
+                (i, sender.run(command).await.ok_or_else(|| format!("Node {i} stopped before replying")))
+            }.boxed()
+        }).collect()
+    }
+
+    /// Measures a metric on running nodes.
+    pub async fn measure(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Alive => self.alive().len() as f64,
+            Metric::Lookups(count) => {
+                publish_values(self).await;
+                lookup_success_rate(self, count).await
+            }
+            metric => {
+                let health = inspect(&self.alive_nodes()).await;
+                match metric {
+                    Metric::Components => health.components as f64,
+                    Metric::Isolated => health.isolated.len() as f64,
+                    Metric::Diameter => health.diameter.map(|d| d as f64).unwrap_or(f64::INFINITY),
+                    Metric::Closest => health.closest_k_accuracy,
+                    Metric::Fill(level) => health.bucket_fill.get(level).copied().unwrap_or(0.0),
+                    Metric::Alive | Metric::Lookups(_) => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Runs an action and returns a message describing why it failed, if it did.
+    pub async fn run_action(&mut self, action: &Action) -> Result<(), String> {
+        match action {
+            Action::Command(destinators, command) => {
+                tokio::spawn(print_replies(self.send_command(destinators, command.clone())));
+            }
+            Action::Health => println!("{}", inspect(&self.alive_nodes()).await),
+            Action::Topology { format, path } => {
+                let topology = topology(&self.alive_nodes()).await;
+                let content = match format {
+                    TopologyFormat::Json => topology.to_json(),
+                    TopologyFormat::Dot => topology.to_dot(),
+                };
+                std::fs::write(path, content).map_err(|e| e.to_string())?;
+                println!("Topology written to {path}");
+            }
+            Action::Kill(nodes) => for i in nodes {
+                if *i >= self.nodes.len() {
+                    return Err(format!("Node {i} does not exist"));
+                }
+                self.kill(*i).await;
+            },
+            Action::Restart(nodes) => for i in nodes {
+                if self.alive.get(*i).copied().unwrap_or(true) {
+                    return Err(format!("Node {i} is not dead"));
+                }
+                self.restart(*i).await;
+            },
+            Action::Add(count) => for _ in 0..*count {
+                self.add_node().await;
+            },
+            Action::Partition(groups) => partition(groups),
+            Action::Heal => heal(),
+            Action::Expect(expectation) => {
+                let measured = self.measure(expectation.metric).await;
+                if !expectation.holds(measured) {
+                    return Err(format!("Expected {expectation}, got {measured}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints replies to commands as they arrive.
+pub async fn print_replies(mut replies: FuturesUnordered<BoxFuture<'static, (usize, Result<CommandOutput, String>)>>) {
+    while let Some((i, reply)) = replies.next().await {
+        match reply {
+            Ok(output) => println!("\x1b[34m[{i}]\x1b[0m {}", output.to_string().replace('\n', &format!("\n\x1b[34m[{i}]\x1b[0m "))),
+            Err(e) => println!("\x1b[34m[{i}]\x1b[0m \x1b[31m{e}\x1b[0m"),
+        }
+    }
+}
+
+/// Runs a scenario with a given seed and returns the expectations that failed.
+///
+/// Run under a single-threaded runtime with a paused clock so that timings are virtual.
+pub async fn run_scenario(scenario: &Scenario, seed: u64) -> Vec<Expectation> {
+    let (adversaries, configs) = (scenario.clone(), scenario.clone());
+    let mut network = launch_configured_network(scenario.node_count, seed, scenario.links.clone(), move |i| adversaries.misbehavior(i), move |i| configs.config(i)).await;
+    let start = Instant::now();
+
+    let mut failures = Vec::new();
+    for (time, action) in &scenario.steps {
+        tokio::time::sleep_until(start + *time).await;
+        let result = network.run_action(action).await;
+        match (action, result) {
+            (Action::Expect(expectation), Ok(())) => println!("[{:>6.1}s] \x1b[32mPASS\x1b[0m {expectation}", time.as_secs_f64()),
+            (Action::Expect(expectation), Err(e)) => {
+                println!("[{:>6.1}s] \x1b[31mFAIL\x1b[0m {e}", time.as_secs_f64());
+                failures.push(expectation.clone());
+            }
+            (_, Err(e)) => println!("[{:>6.1}s] \x1b[33mWARN\x1b[0m {e}", time.as_secs_f64()),
+            (_, Ok(())) => (),
+        }
+    }
+
+    failures
+}
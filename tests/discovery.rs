@@ -3,24 +3,85 @@
 
 mod common;
 use crate::common::*;
+use futures::StreamExt;
+use tewta::{prelude::*, simulation::inspect};
 
-#[tokio::test]
+#[tokio::test(start_paused = true)]
 async fn test_discovery() {
     #[cfg(not(feature = "test"))]
     compile_error!("Test feature required");
 
-    let nodes = launch_network(50, false).await.1;
+    let network = launch_network(50, seed_from_env(), LinkConditions::default(), honest).await;
+    let nodes = &network.nodes;
 
     // Wait for network to boot
     sleep(Duration::from_secs(5)).await;
 
     // Update buckets
-    for node in &nodes {
+    for node in nodes {
         node.connections.refresh_buckets().await; // This will complete immediately so no need to spawn futures
     }
 
     // Wait for buckets to update
     sleep(Duration::from_secs(5)).await;
 
-    // TODO [#50]: Add a few assertions in the discovery test
+    let health = inspect(nodes).await;
+    eprintln!("{health}");
+    assert!(health.isolated.is_empty());
+    assert_eq!(health.components, 1);
+    assert!(health.diameter.unwrap() <= 4);
+    assert!(health.bucket_fill[0] >= 0.6);
+    assert!(health.closest_k_accuracy >= 0.2);
+
+    // Commands report what nodes see
+    let replies: Vec<_> = network.send_command(&[0, 1], Command::Conns).collect().await;
+    for (i, reply) in replies {
+        match reply {
+            Ok(CommandOutput::Conns { count }) => assert_eq!(count, nodes[i].connections.len().await),
+            reply => panic!("Unexpected reply from node {i}: {reply:?}"),
+        }
+    }
+    let peer_id = nodes[1].connections.peers().await.remove(0);
+    match network.command_senders[1].run(Command::Ping { node_id: peer_id.clone() }).await {
+        Some(CommandOutput::Ping(Some(_))) => (),
+        reply => panic!("Unexpected reply to ping: {reply:?}"),
+    }
+
+    // Peers can be found even when we are not connected to them
+    let connected = nodes[1].connections.peers().await;
+    let target = nodes.iter().skip(2).find(|n| !connected.contains(&n.peer_id)).unwrap();
+    match network.command_senders[1].run(Command::FindPeer { peer_id: target.peer_id.clone() }).await {
+        Some(CommandOutput::FindPeer(Some(addr))) => assert_eq!(addr, target.addr),
+        reply => panic!("Unexpected reply to find-peer: {reply:?}"),
+    }
+
+    // Quitting stops the node for good
+    network.command_senders[2].run(Command::Quit).await.unwrap();
+    assert!(nodes[2].is_stopped());
+    match network.command_senders[2].run(Command::Connect { addr: nodes[3].addr.clone() }).await {
+        Some(CommandOutput::Error(_)) => (),
+        reply => panic!("Unexpected reply to connect: {reply:?}"),
+    }
+    sleep(NodeConfig::default().refresh_interval * 2).await;
+    assert_eq!(nodes[2].connections.len().await, 0);
+
+    // Disconnecting removes the peer
+    let sender = &network.command_senders[1];
+    sender.run(Command::Disconnect { peer_id: peer_id.clone() }).await.unwrap();
+    match sender.run(Command::Peers { verbose: true }).await {
+        Some(CommandOutput::Peers(peers)) => {
+            assert!(peers.iter().all(|p| p.peer_id != peer_id));
+            assert!(peers.iter().all(|p| p.addr.is_some() && p.bucket.is_some()));
+        }
+        reply => panic!("Unexpected reply to peers: {reply:?}"),
+    }
+    sleep(Duration::from_secs(10)).await;
+    assert!(!nodes[1].connections.contains(&peer_id).await);
+
+    // Stored values show up in the dump
+    sender.run(Command::Store { key: nodes[1].peer_id.clone() }).await.unwrap();
+    match sender.run(Command::DhtDump).await {
+        Some(CommandOutput::DhtDump(entries)) => assert!(entries.iter().any(|(key, _)| key == &nodes[1].peer_id)),
+        reply => panic!("Unexpected reply to dht-dump: {reply:?}"),
+    }
 }
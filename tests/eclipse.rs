@@ -0,0 +1,40 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::simulation::set_misbehavior;
+
+#[tokio::test(start_paused = true)]
+async fn test_eclipse() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let mut network = launch_network(100, seed_from_env(), LinkConditions::default(), honest).await;
+    let target = network.nodes[0].peer_id.to_owned();
+
+    // One node out of ten colludes to hide node 0.
+    // IDs are only known once nodes exist, so they turn malicious on restart.
+    for i in (5..100).step_by(10) {
+        set_misbehavior(i, Some(Misbehavior::Eclipse { target: target.clone() }));
+        network.kill(i).await;
+        network.restart(i).await;
+    }
+
+    // Wait for network to boot
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    publish_values(&network).await;
+    let honest = network.honest();
+    assert_eq!(honest.len(), 90);
+    let mut successes = 0;
+    for i in honest.iter().skip(1).step_by(4) {
+        if let Ok(Some(_)) = tokio::time::timeout(Duration::from_secs(60), network.nodes[*i].dht_lookup(target.clone())).await {
+            successes += 1;
+        }
+    }
+    let lookups = honest.iter().skip(1).step_by(4).count();
+    eprintln!("{successes}/{lookups} honest nodes found the eclipsed value");
+    assert!(successes as f64 >= lookups as f64 * 0.9, "The eclipse attack succeeded ({successes}/{lookups})");
+}
@@ -0,0 +1,98 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+async fn published(node: &Node) -> AccountData {
+    node.account.lock().await.as_ref().unwrap().1.clone()
+}
+
+fn peer_ids(mentions: SegmentedArray<UserMention, 32>) -> Vec<PeerID> {
+    Vec::from(mentions).into_iter().map(|mention| mention.peer_id).collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_follow() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    let interval = NodeConfig::default().follower_update_interval;
+
+    let (alice, bob, carol) = (&nodes[3], &nodes[4], &nodes[5]);
+    assert!(matches!(alice.follow(bob.peer_id.clone()).await, Err(FollowError::NoAccount)));
+    for (node, username) in [(alice, "alice"), (bob, "bob"), (carol, "carol")] {
+        node.publish_account(account(username)).await.unwrap();
+    }
+
+    // Alice follows Bob, who learns about it
+    alice.follow(bob.peer_id.clone()).await.unwrap();
+    sleep(interval).await;
+    let account = published(alice).await;
+    assert_eq!(account.following_count, 1);
+    let following = Vec::from(account.following);
+    assert_eq!(following.len(), 1);
+    assert_eq!(following[0].username, "bob");
+    assert_eq!(following[0].peer_id, bob.peer_id);
+    assert_eq!(following[0].cached_addr, Some(bob.addr.clone()));
+    let account = published(bob).await;
+    assert_eq!(account.follower_count, 1);
+    let followers = Vec::from(account.followers);
+    assert_eq!(followers[0].username, "alice");
+    assert_eq!(followers[0].peer_id, alice.peer_id);
+    assert!(matches!(alice.follow(bob.peer_id.clone()).await, Err(FollowError::AlreadyFollowing)));
+
+    // Both versions are on the network
+    let (_, snapshot) = nodes[20].fetch_account(alice.peer_id.clone()).await.unwrap();
+    assert_eq!(peer_ids(snapshot.following), vec![bob.peer_id.clone()]);
+    let (_, snapshot) = nodes[20].fetch_account(bob.peer_id.clone()).await.unwrap();
+    assert_eq!(peer_ids(snapshot.followers), vec![alice.peer_id.clone()]);
+
+    // Carol follows Bob too, then Alice unfollows him
+    carol.follow(bob.peer_id.clone()).await.unwrap();
+    alice.unfollow(&bob.peer_id).await.unwrap();
+    assert_eq!(published(bob).await.follower_count, 1);
+    sleep(interval).await;
+    let account = published(alice).await;
+    assert_eq!(account.following_count, 0);
+    assert!(peer_ids(account.following).is_empty());
+    let account = published(bob).await;
+    assert_eq!(account.follower_count, 1);
+    assert_eq!(peer_ids(account.followers), vec![carol.peer_id.clone()]);
+    assert!(matches!(alice.unfollow(&bob.peer_id).await, Err(FollowError::NotFollowing)));
+
+    // Records signed by someone else than the follower, older than the last one, or dated in the future, are ignored
+    let mention = |node: &Node, username: &str| UserMention {
+        username: username.to_string(),
+        peer_id: node.peer_id.clone(),
+        cached_addr: Some(node.addr.clone()),
+        providers_addrs: Vec::new(),
+        cached_at: 0,
+    };
+    let forged = FollowRecord { follower: mention(alice, "alice"), followed: bob.peer_id.clone(), follows: true, timestamp: unix_timestamp() };
+    let replayed = FollowRecord { timestamp: 1, ..forged.clone() };
+    let future = FollowRecord { timestamp: unix_timestamp() + 2 * MAX_CLOCK_DRIFT.as_secs(), ..forged.clone() };
+    let relay = bob.connections.peers().await.into_iter().next().unwrap();
+    let relay = nodes.iter().find(|node| node.peer_id == relay).unwrap();
+    for record in [forged.sign(&carol.rsa_public_key, &carol.rsa_private_key).unwrap(), replayed.sign(&alice.rsa_public_key, &alice.rsa_private_key).unwrap(), future.sign(&alice.rsa_public_key, &alice.rsa_private_key).unwrap()] {
+        relay.connections.send_packet(&bob.peer_id, Packet::Follow(FollowPacket { record })).await;
+    }
+    sleep(interval).await;
+    assert_eq!(peer_ids(published(bob).await.followers), vec![carol.peer_id.clone()]);
+
+    // Stale mentions are looked up again
+    carol.follow(alice.peer_id.clone()).await.unwrap();
+    let mut stale = published(carol).await;
+    stale.following = SegmentedArray::from(Vec::from(stale.following).into_iter().map(|mention| UserMention { cached_addr: None, ..mention }).collect::<Vec<_>>());
+    carol.publish_account(stale).await.unwrap();
+    carol.refresh_mentions(Duration::from_secs(3600)).await.unwrap();
+    assert!(Vec::from(published(carol).await.following).iter().all(|mention| mention.cached_addr.is_none()));
+    carol.refresh_mentions(Duration::ZERO).await.unwrap();
+    let following = Vec::from(published(carol).await.following);
+    assert_eq!(following.len(), 2);
+    assert!(following.iter().all(|mention| mention.cached_addr.is_some()));
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_interactions() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    for (i, node) in nodes.iter().enumerate().take(10) {
+        node.publish_account(account(&format!("user{i}"))).await.unwrap();
+    }
+    let post = nodes[3].post(Post::new("Hello")).await.unwrap();
+    let other = nodes[3].post(Post::new("Unrelated")).await.unwrap();
+
+    // Several accounts interact with the post
+    nodes[4].interact(InteractionKind::Like, post.clone()).await.unwrap();
+    nodes[5].interact(InteractionKind::Like, post.clone()).await.unwrap();
+    nodes[5].interact(InteractionKind::Reshare, post.clone()).await.unwrap();
+    nodes[5].interact(InteractionKind::Like, other.clone()).await.unwrap();
+    let reply = nodes[6].post(Post { reply_to: Some(post.clone()), ..Post::new("Hi") }).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+
+    // They can be found from the post by anyone who trusts them
+    let reader = AccountBuilder::new("reader").following(&nodes[4..=8].iter().collect::<Vec<_>>()).build();
+    nodes[20].publish_account(reader).await.unwrap();
+    let mut found: Vec<(PeerID, InteractionKind)> = nodes[20].interactions(&post).await.into_iter().map(|(actor, i)| (actor, i.kind)).collect();
+    found.sort_by_key(|(actor, _)| actor.clone());
+    let mut expected = vec![
+        (nodes[4].peer_id.clone(), InteractionKind::Like),
+        (nodes[5].peer_id.clone(), InteractionKind::Like),
+        (nodes[5].peer_id.clone(), InteractionKind::Reshare),
+        (nodes[6].peer_id.clone(), InteractionKind::Reply { hash: reply.hash }),
+    ];
+    expected.sort_by_key(|(actor, _)| actor.clone());
+    assert_eq!(found, expected);
+    let found = nodes[20].interactions(&other).await;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, nodes[5].peer_id);
+
+    // Interactions signed by someone else than the account are ignored
+    nodes[7].interact(InteractionKind::Like, post.clone()).await.unwrap();
+    let mut forged = nodes[7].account.lock().await.as_ref().unwrap().1.clone();
+    let interaction = Interaction::new(InteractionKind::Reshare, post.clone());
+    forged.add_interaction(interaction.sign(&nodes[8].rsa_public_key, &nodes[8].rsa_private_key).unwrap()).unwrap();
+    nodes[7].publish_account(forged).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    let found = nodes[20].interactions(&post).await;
+    let by_forger: Vec<_> = found.iter().filter(|(actor, _)| actor == &nodes[7].peer_id).collect();
+    assert_eq!(by_forger.len(), 1);
+    assert_eq!(by_forger[0].1.kind, InteractionKind::Like);
+    assert!(found.iter().all(|(actor, _)| actor != &nodes[8].peer_id));
+
+    // Accounts can only be announced under the key of a post they interacted with
+    let key = post.interactions_key();
+    let desc = nodes[9].account.lock().await.as_ref().unwrap().0.clone();
+    let value = |interaction: Option<Interaction>| DhtValue {
+        provider: nodes[9].peer_id.clone(),
+        cached_addr: Some(nodes[9].addr.clone()),
+        account_snapshot_desc: desc.clone().sign(&nodes[9].rsa_public_key, &nodes[9].rsa_private_key).unwrap(),
+        migration: None,
+        interaction: interaction.map(|i| i.sign(&nodes[9].rsa_public_key, &nodes[9].rsa_private_key).unwrap()),
+    };
+    let unproven = [
+        value(None),
+        value(Some(Interaction::new(InteractionKind::Like, other.clone()))),
+    ];
+    let victim = nodes[9].connections.peers().await.into_iter().next().unwrap();
+    for value in unproven {
+        let value = value.sign(&nodes[9].rsa_public_key, &nodes[9].rsa_private_key).unwrap();
+        nodes[9].connections.send_packet(&victim, Packet::StoreDhtValue(StoreDhtValuePacket { key_id: key.clone(), value })).await;
+    }
+    sleep(Duration::from_secs(1)).await;
+    let victim = nodes.iter().find(|node| node.peer_id == victim).unwrap();
+    let stored = victim.dht.get(&key).await.unwrap_or_default();
+    assert!(stored.iter().all(|value| value.provider != nodes[9].peer_id));
+    let value = value(Some(Interaction::new(InteractionKind::Like, post.clone()))).sign(&nodes[9].rsa_public_key, &nodes[9].rsa_private_key).unwrap();
+    nodes[9].connections.send_packet(&victim.peer_id, Packet::StoreDhtValue(StoreDhtValuePacket { key_id: key.clone(), value })).await;
+    sleep(Duration::from_secs(1)).await;
+    let stored = victim.dht.get(&key).await.unwrap_or_default();
+    assert!(stored.iter().any(|value| value.provider == nodes[9].peer_id));
+}
@@ -0,0 +1,40 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::{commands::*, logging::LogFormat, simulation::NodeLogFiles};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+use serde_json::Value;
+
+#[tokio::test(start_paused = true)]
+async fn test_logging() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    // The runtime is single-threaded, so every node logs through this subscriber regardless of RUST_LOG
+    let dir = std::env::temp_dir().join(format!("tewta-logging-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(NodeLogFiles::new(dir.clone(), LogFormat::Json)));
+
+    let network = launch_network(5, seed_from_env(), LinkConditions::default(), honest).await;
+    sleep(Duration::from_secs(5)).await;
+    let peer_id = network.nodes[1].connections.peers().await.remove(0);
+    network.command_senders[1].run(Command::Ping { node_id: peer_id.clone() }).await.unwrap();
+
+    // Each node has its own file, and lines carry their context
+    for i in 0..5 {
+        assert!(dir.join(format!("local-{i}.log")).exists());
+    }
+    let lines: Vec<Value> = std::fs::read_to_string(dir.join("local-1.log")).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let spans = |line: &Value| line["spans"].as_array().unwrap().clone();
+    assert!(lines.iter().all(|line| spans(line)[0]["name"] == "node" && spans(line)[0]["addr"] == "local-1"));
+    assert!(lines.iter().any(|line| spans(line).iter().any(|span| span["name"] == "peer" && span["remote"].is_string())));
+    assert!(lines.iter().any(|line| spans(line).iter().any(|span| span["name"] == "packet" && span["request_id"].is_u64())));
+    assert!(lines.iter().any(|line| spans(line).iter().any(|span| span["name"] == "request" && span["remote"] == peer_id.to_string())));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::{commands::*, prometheus};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test(start_paused = true)]
+async fn test_metrics() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_network(10, seed_from_env(), LinkConditions::default(), honest).await;
+    sleep(Duration::from_secs(5)).await;
+
+    // Do a lookup to get lookup metrics
+    let key = network.nodes[9].peer_id.clone();
+    network.command_senders[9].run(Command::Store { key: key.clone() }).await.unwrap();
+    network.command_senders[0].run(Command::Find { key }).await.unwrap();
+
+    let metrics = match network.command_senders[0].run(Command::Metrics).await {
+        Some(CommandOutput::Metrics(metrics)) => metrics,
+        output => panic!("Unexpected output: {output:?}"),
+    };
+    for kind in ["ProtocolVersion", "InitRsa", "InitAes", "Ehlo", "FindDhtValue"] {
+        assert!(metrics.packets_sent.get(kind).copied().unwrap_or(0) > 0, "no {kind} packet sent");
+    }
+    assert!(metrics.packets_received.get("Ehlo").copied().unwrap_or(0) > 0);
+    assert!(metrics.bytes_sent > 0 && metrics.bytes_received > 0);
+    assert_eq!(metrics.lookups.values().sum::<u64>(), 1);
+    assert_eq!(metrics.lookup_hops.count, 1);
+    assert!(metrics.lookup_hops.sum >= 1.0);
+    // Connections come and go, so gauges can only be checked loosely
+    assert!(metrics.connections > 0);
+    assert!(!metrics.buckets.is_empty());
+
+    let prometheus_text = metrics.to_prometheus();
+    assert!(prometheus_text.contains("tewta_packets_sent_total{kind=\"Ehlo\"}"));
+    assert!(prometheus_text.contains("tewta_lookup_hops_bucket{le=\"+Inf\"} 1"));
+
+    // The store size shows on the node that stored the value
+    match network.command_senders[9].run(Command::Metrics).await {
+        Some(CommandOutput::Metrics(metrics)) => assert_eq!((metrics.dht_keys, metrics.dht_values), (1, 1)),
+        output => panic!("Unexpected output: {output:?}"),
+    }
+
+    // The HTTP endpoint
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let commands = network.command_senders[0].clone();
+    tokio::spawn(async move { prometheus::serve(addr, commands).await.unwrap() });
+    let get = |path: &'static str| async move {
+        let mut stream = loop {
+            match tokio::net::TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let response = get("/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(response.contains("# TYPE tewta_packets_received_total counter"));
+    assert!(response.contains("tewta_lookups_total"));
+    assert!(get("/").await.starts_with("HTTP/1.1 404"));
+
+    assert!(prometheus::serve("0.0.0.0:0".parse().unwrap(), network.command_senders[0].clone()).await.is_err());
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+/// Peers distributing an account, according to all DHT stores.
+async fn providers(network: &Network, key: &PeerID) -> BTreeSet<PeerID> {
+    let mut providers = BTreeSet::new();
+    for node in network.alive_nodes() {
+        for value in node.dht.get(key).await.unwrap_or_default() {
+            providers.insert(value.provider);
+        }
+    }
+    providers
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_mirroring() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    // Node 7 has no room for others' accounts
+    let mut network = launch_configured_social_network(|i| NodeConfig {
+        max_mirrored_bytes: if i == 7 { 10 } else { NodeConfig::default().max_mirrored_bytes },
+        ..NodeConfig::default()
+    }).await;
+    let nodes = network.nodes.clone();
+
+    // Nodes 5, 6 and 7 follow node 3
+    let alice = nodes[3].peer_id.clone();
+    let desc1 = nodes[3].publish_account(account("alice")).await.unwrap();
+    for (i, username) in [(5, "bob"), (6, "carol"), (7, "dave")] {
+        nodes[i].publish_account(AccountBuilder::new(username).following(&[&nodes[3]]).build()).await.unwrap();
+        nodes[i].sync_mirrors().await;
+    }
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(nodes[5].mirrors.lock().await.get(&alice).unwrap().desc, desc1);
+    assert!(nodes[5].snapshots.get(&desc1.hash).await.is_some());
+    assert!(matches!(nodes[7].mirror_account(alice.clone()).await, Err(MirrorError::QuotaExceeded)));
+    assert!(nodes[7].mirrors.lock().await.is_empty());
+    // The size in the descriptor was enough to refuse, so the snapshot was never downloaded
+    assert!(!nodes[7].metrics_snapshot().await.packets_sent.contains_key("FetchSnapshot"));
+    let expected: BTreeSet<PeerID> = [3, 5, 6].iter().map(|i| nodes[*i].peer_id.clone()).collect();
+    assert_eq!(providers(&network, &alice).await, expected);
+    for value in nodes[3].dht.get(&alice).await.unwrap() {
+        assert_eq!(value.account_snapshot_desc.data_unverified(), &desc1);
+    }
+
+    // Unfollowing withdraws the provider record
+    nodes[6].publish_account(account("carol")).await.unwrap();
+    nodes[6].sync_mirrors().await;
+    sleep(Duration::from_secs(1)).await;
+    assert!(nodes[6].mirrors.lock().await.is_empty());
+    assert!(nodes[6].snapshots.get(&desc1.hash).await.is_none());
+    assert!(!providers(&network, &alice).await.contains(&nodes[6].peer_id));
+
+    // Followers pick up updates and keep distributing the account when its owner is offline
+    let desc2 = nodes[3].publish_account(account("alice2")).await.unwrap();
+    nodes[5].sync_mirrors().await;
+    assert_eq!(nodes[5].mirrors.lock().await.get(&alice).unwrap().desc, desc2);
+    assert!(nodes[5].snapshots.get(&desc1.hash).await.is_none());
+
+    // Republishing the same content keeps the snapshot served
+    let desc3 = nodes[3].publish_account(account("alice2")).await.unwrap();
+    assert_eq!(desc3.hash, desc2.hash);
+    nodes[5].sync_mirrors().await;
+    assert_eq!(nodes[5].mirrors.lock().await.get(&alice).unwrap().desc, desc3);
+    assert!(nodes[5].snapshots.get(&desc3.hash).await.is_some());
+    sleep(Duration::from_secs(1)).await;
+    network.kill(3).await;
+    sleep(Duration::from_secs(1)).await;
+    let (desc, snapshot) = nodes[20].fetch_account(alice.clone()).await.unwrap();
+    assert_eq!(desc, desc3);
+    assert_eq!(AccountData::try_from(snapshot).unwrap().username, "alice2");
+}
@@ -0,0 +1,53 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::simulation::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_partition() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let mut network = launch_network(60, seed_from_env(), LinkConditions::default(), honest).await;
+
+    // Wait for network to boot
+    sleep(Duration::from_secs(10)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    // Split the network in two halves and wait for pings to notice
+    partition(&[(0..30).collect(), (30..60).collect()]);
+    sleep(Duration::from_secs(120)).await;
+    refresh_all_buckets(&network, 1).await;
+
+    // No connection should cross the partition
+    for (i, node) in network.nodes.iter().enumerate() {
+        for peer_id in node.connections.peers().await {
+            let j = network.nodes.iter().position(|n| n.peer_id == peer_id).unwrap();
+            assert!(!are_partitioned(i, j), "Node {i} is still connected to node {j}");
+        }
+    }
+
+    // Lookups still succeed within a partition
+    publish_values(&network).await;
+    let key = network.nodes[10].peer_id.to_owned();
+    network.nodes[20].dht_lookup(key).await.unwrap();
+    let key = network.nodes[40].peer_id.to_owned();
+    network.nodes[50].dht_lookup(key).await.unwrap();
+
+    // Restarting nodes after the heal reconnects both halves
+    heal();
+    let churn = Churn {
+        interval: Duration::from_secs(10),
+        departures: 2,
+        arrivals: 2,
+        restart_probability: 1.0,
+    };
+    network.churn(&churn, Duration::from_secs(50)).await;
+    refresh_all_buckets(&network, 2).await;
+
+    publish_values(&network).await;
+    let success_rate = lookup_success_rate(&network, 30).await;
+    assert!(success_rate >= 0.85, "Lookup success rate did not recover ({success_rate})");
+}
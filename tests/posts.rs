@@ -0,0 +1,65 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_posts() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Posts go to the published account
+    assert!(matches!(nodes[3].post(Post::new("Too early")).await, Err(PostError::NoAccount)));
+    let alice = nodes[3].peer_id.clone();
+    nodes[3].publish_account(account("alice")).await.unwrap();
+    let mut refs = Vec::new();
+    for i in 0..40 {
+        refs.push(nodes[3].post(Post::new(format!("Post {i}"))).await.unwrap());
+    }
+
+    // Others reply and quote
+    nodes[5].publish_account(account("bob")).await.unwrap();
+    let reply = Post { reply_to: Some(refs[39].clone()), ..Post::new("Reply") };
+    let quote = Post { quote: Some(refs[0].clone()), media: vec![[7; 32]], ..Post::new("Quote") };
+    nodes[5].post(reply).await.unwrap();
+    nodes[5].post(quote).await.unwrap();
+
+    // Posts are signed by their author
+    let (desc, snapshot) = nodes[20].fetch_account(alice.clone()).await.unwrap();
+    let posts = Vec::from(snapshot.posts);
+    assert_eq!(posts.len(), 40);
+    for (i, post) in posts.into_iter().enumerate() {
+        let (author, post) = post.into_verified().unwrap();
+        assert_eq!(author, alice);
+        assert_eq!(post.text, format!("Post {i}"));
+        assert_eq!(post.reference(author), refs[i]);
+    }
+    let (_, snapshot) = nodes[20].fetch_account(nodes[5].peer_id.clone()).await.unwrap();
+    let posts: Vec<Post> = Vec::from(snapshot.posts).into_iter().map(|post| post.into_verified().unwrap().1).collect();
+    assert_eq!(posts[0].reply_to.as_ref(), Some(&refs[39]));
+    assert_eq!(posts[1].quote.as_ref(), Some(&refs[0]));
+    assert_eq!(posts[1].media, vec![[7; 32]]);
+
+    // Old posts can be pruned without changing the hash of the account
+    let mut pruned = nodes[3].account.lock().await.as_ref().unwrap().1.clone();
+    pruned.posts.retain_last(5);
+    let pruned_desc = nodes[3].publish_account(pruned).await.unwrap();
+    assert_eq!(pruned_desc.hash, desc.hash);
+    let provider = (alice.clone(), None);
+    let snapshot = nodes[20].fetch_snapshot(provider, &pruned_desc, SegmentSelection::All, SegmentSelection::All, SegmentSelection::Last { count: 3 }, SegmentSelection::Nothing).await.unwrap();
+    let posts = Vec::from(snapshot.posts);
+    assert_eq!(posts.len(), 3);
+    assert_eq!(posts.last().unwrap().data_unverified().text, "Post 39");
+
+    // And posting continues on the pruned log
+    nodes[3].post(Post::new("Post 40")).await.unwrap();
+    let (_, snapshot) = nodes[20].fetch_account(alice).await.unwrap();
+    let posts = Vec::from(snapshot.posts);
+    assert_eq!(posts.last().unwrap().data_unverified().text, "Post 40");
+    assert_eq!(posts.len(), 6);
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_publish() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Node 3 publishes its account and others can download it
+    let alice = nodes[3].peer_id.clone();
+    let desc1 = nodes[3].publish_account(account("alice")).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    let (desc, snapshot) = nodes[20].fetch_account(alice.clone()).await.unwrap();
+    assert_eq!(desc, desc1);
+    assert_eq!(AccountData::try_from(snapshot).unwrap(), account("alice"));
+
+    // The closest peers store the value too
+    let mut replicas = 0;
+    for node in nodes.iter().filter(|node| node.peer_id != alice) {
+        if let Some(values) = node.dht.get(&alice).await {
+            assert_eq!(values.len(), 1);
+            assert_eq!(values[0].provider, alice);
+            replicas += 1;
+        }
+    }
+    assert!(replicas > 0);
+
+    // Updates replace the previous version, even within the same second
+    let desc2 = nodes[3].publish_account(account("alice2")).await.unwrap();
+    assert!(desc2.timestamp > desc1.timestamp);
+    sleep(Duration::from_secs(1)).await;
+    let (desc, snapshot) = nodes[20].fetch_account(alice.clone()).await.unwrap();
+    assert_eq!(desc, desc2);
+    assert_eq!(AccountData::try_from(snapshot).unwrap().username, "alice2");
+    assert_eq!(nodes[3].dht.get(&alice).await.unwrap().len(), 1);
+    let provider = (alice.clone(), None);
+    assert!(matches!(nodes[20].fetch_snapshot(provider, &desc1, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All).await, Err(FetchSnapshotError::Unavailable)));
+
+    // Replaying the old version doesn't roll back stores
+    let (peer_id, values) = {
+        let mut holders = Vec::new();
+        for node in nodes.iter().filter(|node| node.peer_id != alice) {
+            if let Some(values) = node.dht.get(&alice).await {
+                holders.push((node.peer_id.clone(), values));
+            }
+        }
+        holders.pop().unwrap()
+    };
+    assert_eq!(values[0].account_snapshot_desc.data_unverified(), &desc2);
+    let old_value = DhtValue {
+        provider: alice.clone(),
+        cached_addr: Some(nodes[3].addr.clone()),
+        account_snapshot_desc: desc1.sign(&nodes[3].rsa_public_key, &nodes[3].rsa_private_key).unwrap(),
+        migration: None,
+        interaction: None,
+    };
+    let holder = nodes.iter().find(|node| node.peer_id == peer_id).unwrap();
+    assert!(!holder.dht.set(alice.clone(), old_value.clone()).await);
+
+    // Peers can't announce values on behalf of others
+    let forger = nodes.iter().find(|node| node.peer_id != alice && node.peer_id != peer_id).unwrap();
+    let forged = old_value.sign(&forger.rsa_public_key, &forger.rsa_private_key).unwrap();
+    let victim = forger.connections.peers().await.into_iter().find(|p| p != &alice).unwrap();
+    forger.connections.send_packet(&victim, Packet::StoreDhtValue(StoreDhtValuePacket { key_id: alice.clone(), value: forged })).await;
+    sleep(Duration::from_secs(1)).await;
+    let victim = nodes.iter().find(|node| node.peer_id == victim).unwrap();
+    if let Some(values) = victim.dht.get(&alice).await {
+        assert!(values.iter().all(|value| value.provider == alice && value.account_snapshot_desc.data_unverified() == &desc2));
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+async fn storers<'a>(nodes: &'a [Arc<Node>], key: &KeyID) -> Vec<&'a Arc<Node>> {
+    let mut storers = Vec::new();
+    for node in nodes.iter().filter(|node| &node.peer_id != key) {
+        if node.dht.get(key).await.is_some() {
+            storers.push(node);
+        }
+    }
+    storers
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_recovery() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Alice has a backup key, and Bob follows her
+    let (alice, backup, new_alice, bob, mallory) = (&nodes[3], &nodes[4], &nodes[5], &nodes[6], &nodes[9]);
+    let key = alice.peer_id.clone();
+    alice.publish_account(AccountBuilder::new("alice").backup(backup).build()).await.unwrap();
+    alice.post(Post::new("Hello")).await.unwrap();
+    bob.publish_account(AccountBuilder::new("bob").backup(bob).following(&[alice]).build()).await.unwrap();
+    assert_eq!(texts(&bob.timeline(None, 10).await.entries), vec!["Hello"]);
+
+    // Mallory steals the primary key of Alice, but cannot replace her backup
+    let stolen = AccountBuilder::new("alice").backup(mallory).build();
+    let desc = AccountSnapshotDescriptor::new(u64::MAX / 2, &stolen.snapshot());
+    let forged = DhtValue {
+        provider: key.clone(),
+        cached_addr: None,
+        account_snapshot_desc: desc.sign(&alice.rsa_public_key, &alice.rsa_private_key).unwrap(),
+        migration: None,
+        interaction: None,
+    };
+    let storers = storers(nodes, &key).await;
+    assert!(!storers.is_empty());
+    let packet = Packet::StoreDhtValue(StoreDhtValuePacket { key_id: key.clone(), value: forged.sign(&alice.rsa_public_key, &alice.rsa_private_key).unwrap() });
+    for storer in &storers {
+        alice.connections.send_packet(&storer.peer_id, packet.clone()).await;
+    }
+    sleep(Duration::from_secs(1)).await;
+    for storer in &storers {
+        let values = storer.dht.get(&key).await.unwrap();
+        assert!(values.iter().all(|value| value.account_snapshot_desc.data_unverified().backup_peer_id == backup.peer_id));
+    }
+
+    // Neither can she migrate the account to herself
+    let forged_migration = Migration::new(key.clone(), mallory.peer_id.clone()).sign(&alice.rsa_public_key, &alice.rsa_private_key).unwrap();
+    let mut mallory_account = AccountBuilder::new("alice").backup(mallory).build();
+    mallory_account.backup_peer_id = key.clone();
+    mallory.migrate_account(forged_migration, mallory_account).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(bob.resolve_account(key.clone()).await, key);
+    assert_eq!(nodes[20].resolve_account(key.clone()).await, key);
+
+    // The backup moves the account to a new key
+    let migration = backup.sign_migration(key.clone(), new_alice.peer_id.clone()).unwrap();
+    assert!(matches!(alice.migrate_account(migration.clone(), AccountBuilder::new("alice").backup(backup).build()).await, Err(MigrationError::WrongTarget)));
+    assert!(matches!(new_alice.migrate_account(migration.clone(), AccountBuilder::new("alice").backup(mallory).build()).await, Err(MigrationError::BackupMismatch)));
+    new_alice.migrate_account(migration, AccountBuilder::new("alice").backup(backup).build()).await.unwrap();
+    new_alice.post(Post::new("Recovered")).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+
+    // Migrations are followed when resolving Alice, once we check again for accounts we found had not migrated
+    assert_eq!(bob.resolve_account(key.clone()).await, key);
+    sleep(MIGRATION_CHECK_INTERVAL).await;
+    assert_eq!(bob.resolve_account(key.clone()).await, new_alice.peer_id);
+    assert_eq!(nodes[20].resolve_account(key.clone()).await, new_alice.peer_id);
+    let (_, snapshot) = nodes[20].fetch_account(key.clone()).await.unwrap();
+    let posts: Vec<Post> = Vec::from(snapshot.posts).into_iter().map(|post| post.into_verified().unwrap().1).collect();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].text, "Recovered");
+    let entries = bob.timeline(None, 10).await.entries;
+    assert_eq!(texts(&entries), vec!["Recovered"]);
+    assert_eq!(entries[0].reference.author, new_alice.peer_id);
+
+    // And the stolen key can no longer update the account
+    alice.post(Post::new("Send me your coins")).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    let scam = alice.account.lock().await.as_ref().unwrap().0.hash;
+    for storer in &storers {
+        let values = storer.dht.get(&key).await.unwrap();
+        assert!(values.iter().all(|value| value.account_snapshot_desc.data_unverified().hash != scam));
+    }
+    assert_eq!(nodes[21].resolve_account(key.clone()).await, new_alice.peer_id);
+    assert_eq!(texts(&bob.timeline(None, 10).await.entries), vec!["Recovered"]);
+
+    // Erin's primary key is stolen before she publishes, and Mallory gets a store to see herself as the backup first
+    let (erin, erin_backup, new_erin) = (&nodes[10], &nodes[11], &nodes[12]);
+    let erin_key = erin.peer_id.clone();
+    let mut closest = erin.connections.peers().await;
+    closest.sort_by_key(|peer_id| peer_id.distance(&erin_key));
+    let fooled = nodes.iter().find(|node| node.peer_id == closest[0]).unwrap();
+    let stolen = AccountBuilder::new("erin").backup(mallory).build();
+    let forged = DhtValue {
+        provider: erin_key.clone(),
+        cached_addr: None,
+        account_snapshot_desc: AccountSnapshotDescriptor::new(0, &stolen.snapshot()).sign(&erin.rsa_public_key, &erin.rsa_private_key).unwrap(),
+        migration: None,
+        interaction: None,
+    };
+    let packet = Packet::StoreDhtValue(StoreDhtValuePacket { key_id: erin_key.clone(), value: forged.sign(&erin.rsa_public_key, &erin.rsa_private_key).unwrap() });
+    erin.connections.send_packet(&fooled.peer_id, packet).await;
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(fooled.dht.backup(&erin_key).await, Some(mallory.peer_id.clone()));
+    erin.publish_account(AccountBuilder::new("erin").backup(erin_backup).build()).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(fooled.dht.backup(&erin_key).await, Some(mallory.peer_id.clone()));
+
+    // Only that store accepts Mallory's migration, and most stores accept the one of the real backup
+    let forged_migration = mallory.sign_migration(erin_key.clone(), mallory.peer_id.clone()).unwrap();
+    mallory.migrate_account(forged_migration, AccountBuilder::new("erin").backup(mallory).build()).await.unwrap();
+    let migration = erin_backup.sign_migration(erin_key.clone(), new_erin.peer_id.clone()).unwrap();
+    new_erin.migrate_account(migration, AccountBuilder::new("erin").backup(erin_backup).build()).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(nodes[22].resolve_account(erin_key.clone()).await, new_erin.peer_id);
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::{commands::*, rpc::*};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[tokio::test(start_paused = true)]
+async fn test_rpc() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_network(5, seed_from_env(), LinkConditions::default(), honest).await;
+    sleep(Duration::from_secs(5)).await;
+
+    let path = std::env::temp_dir().join(format!("tewta-rpc-test-{}.sock", std::process::id()));
+    let endpoint = RpcEndpoint::Unix(path.clone());
+    let commands = network.command_senders[0].clone();
+    let endpoint2 = endpoint.clone();
+    tokio::spawn(async move { serve(&endpoint2, commands).await.unwrap() });
+    while !path.exists() {
+        tokio::task::yield_now().await;
+    }
+
+    // Commands map to methods
+    let id = call(&endpoint, &Command::Id).await.unwrap();
+    assert_eq!(id["type"], "id");
+    assert_eq!(id["value"], network.nodes[0].peer_id.to_string());
+
+    let peers = call(&endpoint, &Command::Peers { verbose: true }).await.unwrap();
+    let peers = peers["value"].as_array().unwrap();
+    assert_eq!(peers.len(), network.nodes[0].connections.len().await);
+    assert!(peers.iter().all(|p| p["addr"].as_str().unwrap().starts_with("local-")));
+
+    // Raw requests
+    let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+    let requests = [
+        r#"{"jsonrpc": "2.0", "method": "conns", "id": "a"}"#,
+        r#"{"jsonrpc": "2.0", "method": "refresh-buckets"}"#, // Notification
+        r#"{"jsonrpc": "2.0", "method": "fetch", "id": 2}"#,
+        r#"{"jsonrpc": "2.0", "method": "ping", "params": {"node_id": "zz"}, "id": 3}"#,
+        r#"{"jsonrpc": "2.0", "method""#,
+    ];
+    for request in requests {
+        w.write_all(format!("{request}\n").as_bytes()).await.unwrap();
+    }
+    let mut responses = Vec::new();
+    for _ in 0..4 {
+        let line = lines.next_line().await.unwrap().unwrap();
+        responses.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+    }
+    assert_eq!(responses[0]["id"], "a");
+    assert_eq!(responses[0]["result"]["value"]["count"], peers.len());
+    assert_eq!(responses[1]["error"]["code"], -32601);
+    assert_eq!(responses[2]["error"]["code"], -32602);
+    assert_eq!(responses[3]["error"]["code"], -32700);
+
+    // Account commands
+    let alice = network.nodes[0].peer_id.clone();
+    let published = call(&endpoint, &Command::Publish { username: String::from("alice") }).await.unwrap();
+    assert_eq!(published["type"], "published");
+    assert_eq!(published["value"]["backup_peer_id"], alice.to_string());
+    let posted = call(&endpoint, &Command::Post { text: String::from("Hello") }).await.unwrap();
+    assert_eq!(posted["type"], "posted");
+    assert_eq!(posted["value"]["author"], alice.to_string());
+    assert!(matches!(network.nodes[1].on_command(Command::Follow { peer_id: alice.clone() }).await, CommandOutput::Error(_)));
+    network.nodes[1].on_command(Command::Publish { username: String::from("bob") }).await;
+    assert!(matches!(network.nodes[1].on_command(Command::Follow { peer_id: alice.clone() }).await, CommandOutput::Published(_)));
+    match network.nodes[1].on_command(Command::FetchAccount { peer_id: alice }).await {
+        CommandOutput::Account(account) => {
+            assert_eq!(account.username, "alice");
+            assert_eq!(account.posts.len(), 1);
+            assert_eq!(account.posts[0].text, "Hello");
+        }
+        output => panic!("Unexpected output: {output}"),
+    }
+
+    let _ = std::fs::remove_file(path);
+
+    // Files that are not sockets are left alone
+    let path = std::env::temp_dir().join(format!("tewta-rpc-test-{}.txt", std::process::id()));
+    std::fs::write(&path, "data").unwrap();
+    let commands = network.command_senders[0].clone();
+    assert!(serve(&RpcEndpoint::Unix(path.clone()), commands).await.is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    let _ = std::fs::remove_file(path);
+}
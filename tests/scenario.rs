@@ -0,0 +1,16 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::simulation::Scenario;
+
+#[tokio::test(start_paused = true)]
+async fn test_scenario() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let scenario = Scenario::parse(include_str!("../scenarios/partition.scenario")).unwrap();
+    let failures = run_scenario(&scenario, seed_from_env()).await;
+    assert!(failures.is_empty(), "Expectations not met: {failures:?}");
+}
@@ -3,36 +3,128 @@
 
 mod common;
 use crate::common::*;
-use tewta::{commands::*, RUNNING_COMMAND_COUNTER};
-use std::io::Write;
+use tewta::{commands::*, node::{NodeConfig, NodeConfigArgs}, simulation::{Action, Scenario}};
+use std::{io::Write, path::PathBuf};
+use structopt::StructOpt;
 
-#[tokio::main]
-async fn main() {
+/// Simulates a network of nodes, interactively or from a scenario file.
+///
+/// Logs are filtered by RUST_LOG and can be selected per node, like `RUST_LOG='error,[node{addr=local-3}]=debug'`.
+/// Set TEWTA_LOG_FORMAT=json for JSON output, and TEWTA_LOG_DIR to write each node's logs to its own file.
+#[derive(StructOpt)]
+struct Args {
+    /// Scenario file to run non-interactively.
+    /// Exits with a non-zero status if any expectation fails.
+    scenario: Option<PathBuf>,
+    /// Overrides the seeds of the scenario
+    #[structopt(long)]
+    seed: Option<u64>,
+    #[structopt(flatten)]
+    config: NodeConfigArgs,
+}
+
+fn main() {
+    let args = Args::from_args();
+    let config = args.config.load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(2);
+    });
+    match args.scenario {
+        Some(path) => {
+            let scenario = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {e}", path.display());
+                std::process::exit(2);
+            });
+            let mut scenario = Scenario::parse(&scenario).unwrap_or_else(|e| {
+                eprintln!("Invalid scenario {}: {e}", path.display());
+                std::process::exit(2);
+            });
+            scenario.base_config = config;
+            std::process::exit(run_scenario_file(scenario, args.seed));
+        }
+        None => tokio::runtime::Runtime::new().unwrap().block_on(interactive(config)),
+    }
+}
+
+/// Returns the exit code
+fn run_scenario_file(scenario: Scenario, seed: Option<u64>) -> i32 {
+    let seeds = match seed {
+        Some(seed) => vec![seed],
+        None if scenario.seeds.is_empty() => vec![seed_from_env()],
+        None => scenario.seeds.clone(),
+    };
+
+    // The simulated network is global, so each seed runs in its own process
+    if seeds.len() > 1 {
+        let mut failed = false;
+        for seed in seeds {
+            // Same arguments, so that configuration flags are passed along
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(std::env::args_os().skip(1))
+                .arg("--seed")
+                .arg(seed.to_string())
+                .status()
+                .expect("failed to run scenario");
+            failed |= !status.success();
+        }
+        return failed as i32;
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().start_paused(true).build().unwrap();
+    let failures = runtime.block_on(run_scenario(&scenario, seeds[0]));
+    match failures.len() {
+        0 => {
+            println!("Scenario passed with seed {}", seeds[0]);
+            0
+        }
+        n => {
+            println!("Scenario failed with seed {}: {n} expectation(s) not met", seeds[0]);
+            1
+        }
+    }
+}
+
+async fn interactive(config: NodeConfig) {
     let mut buf = String::new();
     println!("Node count: ");
     std::io::stdin().read_line(&mut buf).unwrap();
     let node_count = buf.trim().parse::<usize>().unwrap();
-    let command_senders = launch_network(node_count, true).await.0;
+    buf.clear();
+    println!("Seed (leave empty for random): ");
+    std::io::stdin().read_line(&mut buf).unwrap();
+    let seed = match buf.trim() {
+        "" => seed_from_env(),
+        seed => seed.parse::<u64>().unwrap(),
+    };
+    let mut network = launch_configured_network(node_count, seed, LinkConditions::default(), honest, move |_| config.clone()).await;
 
     print!("\x1b[32m>>> \x1b[0m");
     loop {
         let mut raw_command = String::new();
         std::io::stdout().flush().unwrap();
         std::io::stdin().read_line(&mut raw_command).unwrap();
-        match Command::parse(&raw_command) {
-            Ok((destinators, command)) => {
-                for destinator in destinators {
-                    command_senders[destinator].send(command.clone()).await.unwrap();
-                    unsafe { RUNNING_COMMAND_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed); }
-                }
+
+        match Action::parse(raw_command.trim()) {
+            Ok(Action::Command(destinators, command)) => {
+                // The prompt is printed back once all nodes replied
+                let replies = network.send_command(&destinators, command);
+                tokio::spawn(async move {
+                    print_replies(replies).await;
+                    print!("\x1b[32m>>> \x1b[0m");
+                    std::io::stdout().flush().unwrap();
+                });
             }
+            Ok(action) => match network.run_action(&action).await {
+                Ok(()) => print!("\x1b[32m>>> \x1b[0m"),
+                Err(e) => print!("{e}\n\x1b[31m>>> \x1b[0m"),
+            },
             Err(e) => {
                 eprintln!("{}", e);
                 match e {
                     CommandParsingError::Clap(e) if e.kind == structopt::clap::ErrorKind::HelpDisplayed => {
                         print!("\x1b[32m>>> \x1b[0m");
                     }
-                    _ => print!("\x1b[31m>>> \x1b[0m"), 
+                    _ => print!("\x1b[31m>>> \x1b[0m"),
                 };
             },
         }
@@ -0,0 +1,57 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+async fn announce(node: &Node, provider: &Node, desc: AccountSnapshotDescriptor) {
+    node.dht.set(node.peer_id.clone(), DhtValue {
+        provider: provider.peer_id.clone(),
+        cached_addr: Some(provider.addr.clone()),
+        account_snapshot_desc: desc.sign(&node.rsa_public_key, &node.rsa_private_key).unwrap(),
+        migration: None,
+        interaction: None,
+    }).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_snapshots() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Node 3 provides its account
+    let account = AccountBuilder::new("alice").followers(1500).build();
+    let hash = nodes[3].snapshots.insert(account.snapshot()).await;
+    let desc = AccountSnapshotDescriptor::new(1, &account.snapshot());
+    assert_eq!(desc.hash, hash);
+    announce(&nodes[3], &nodes[3], desc.clone()).await;
+
+    // A newer version is announced, but its provider doesn't have it
+    announce(&nodes[3], &nodes[4], AccountSnapshotDescriptor::new(2, &AccountBuilder::new("alice").followers(1501).build().snapshot())).await;
+
+    // Others download the version that is available
+    let (fetched_desc, snapshot) = nodes[20].fetch_account(nodes[3].peer_id.clone()).await.unwrap();
+    assert_eq!(fetched_desc, desc);
+    assert!(snapshot.is_complete());
+    assert_eq!(AccountData::try_from(snapshot).unwrap(), account);
+
+    // Parts of the lists can be selected
+    let provider = (nodes[3].peer_id.clone(), None);
+    let snapshot = nodes[20].fetch_snapshot(provider.clone(), &desc, SegmentSelection::Range { start: 1000, end: 1100 }, SegmentSelection::Nothing, SegmentSelection::Nothing, SegmentSelection::Nothing).await.unwrap();
+    assert!(!snapshot.is_complete());
+    let followers = Vec::from(snapshot.followers);
+    assert_eq!(followers.len(), 100);
+    assert_eq!(followers[0].username, "follower1000");
+    let snapshot = nodes[20].fetch_snapshot(provider.clone(), &desc, SegmentSelection::Segments(vec![1]), SegmentSelection::All, SegmentSelection::All, SegmentSelection::All).await.unwrap();
+    assert_eq!(Vec::from(snapshot.followers).len(), 1500 - 1024);
+
+    // Unknown snapshots and accounts
+    let mut unknown = desc.clone();
+    unknown.hash = [0; 32];
+    assert!(matches!(nodes[20].fetch_snapshot(provider, &unknown, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All, SegmentSelection::All).await, Err(FetchSnapshotError::Unavailable)));
+    assert!(matches!(nodes[20].fetch_account(nodes[5].peer_id.clone()).await, Err(FetchAccountError::NotFound)));
+}
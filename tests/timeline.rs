@@ -0,0 +1,77 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+fn post(text: &str, timestamp: u64) -> Post {
+    Post { timestamp, ..Post::new(text) }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timeline() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Alice follows Bob and Carol, but neither Dave nor Eve. Carol follows Dave.
+    let (alice, bob, carol, dave, eve) = (&nodes[3], &nodes[4], &nodes[5], &nodes[6], &nodes[7]);
+    alice.publish_account(AccountBuilder::new("alice").following(&[bob, carol]).build()).await.unwrap();
+    carol.publish_account(AccountBuilder::new("carol").following(&[dave]).build()).await.unwrap();
+    for (node, username) in [(bob, "bob"), (dave, "dave"), (eve, "eve")] {
+        node.publish_account(account(username)).await.unwrap();
+    }
+    assert!(alice.timeline(None, 10).await.entries.is_empty());
+
+    let b0 = bob.post(post("b0", 1000)).await.unwrap();
+    bob.post(post("b1", 1002)).await.unwrap();
+    bob.post(post("b2", 1004)).await.unwrap();
+    carol.post(post("c0", 1001)).await.unwrap();
+    carol.post(post("c1", 1003)).await.unwrap();
+    let d0 = dave.post(post("d0", 10)).await.unwrap();
+    eve.post(post("e0", 1005)).await.unwrap();
+
+    // Posts Carol interacted with are included, dated by the interaction, and appear once
+    carol.interact(InteractionKind::Like, d0.clone()).await.unwrap();
+    carol.interact(InteractionKind::Reshare, b0.clone()).await.unwrap();
+    let page = alice.timeline(None, 10).await;
+    assert!(page.next.is_none());
+    let entries = page.entries;
+    assert_eq!(entries.len(), 6);
+    assert_eq!(texts(&entries[2..]), vec!["b2", "c1", "b1", "c0"]);
+    let first: BTreeMap<&str, &TimelineReason> = entries[..2].iter().map(|entry| (entry.post.text.as_str(), &entry.reason)).collect();
+    assert_eq!(first.get("d0"), Some(&&TimelineReason::Liked(carol.peer_id.clone())));
+    assert_eq!(first.get("b0"), Some(&&TimelineReason::Reshared(carol.peer_id.clone())));
+    assert_eq!(entries.iter().find(|entry| entry.reference == d0).unwrap().reference.author, dave.peer_id);
+
+    // Pages follow each other
+    let page1 = alice.timeline(None, 4).await;
+    let cursor = page1.next.clone().unwrap();
+    assert_eq!(cursor.to_string().parse::<TimelineCursor>().unwrap(), cursor);
+    let page2 = alice.timeline(Some(cursor), 4).await;
+    assert!(page2.next.is_none());
+    assert_eq!([page1.entries, page2.entries].concat(), entries);
+
+    // Snapshots with an unchanged hash are not downloaded again
+    let mut pruned = bob.account.lock().await.as_ref().unwrap().1.clone();
+    pruned.posts.retain_last(1);
+    bob.publish_account(pruned).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(alice.timeline(None, 10).await.entries, entries);
+
+    // But new versions are
+    bob.post(post("b3", 1006)).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    let entries = alice.timeline(None, 10).await.entries;
+    assert!(texts(&entries).contains(&"b3"));
+    assert!(!texts(&entries).contains(&"b1"));
+
+    // The timeline is available as a command
+    match alice.on_command(Command::Timeline { cursor: None, limit: 2 }).await {
+        CommandOutput::Timeline(page) => assert_eq!(page.entries.len(), 2),
+        output => panic!("Unexpected output: {output}"),
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_trust() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_social_network().await;
+    let nodes = &network.nodes;
+
+    // Alice follows Bob, who follows Carol, who follows Dave. Nobody follows Eve.
+    let (alice, bob, carol, dave, eve) = (&nodes[3], &nodes[4], &nodes[5], &nodes[6], &nodes[7]);
+    alice.publish_account(AccountBuilder::new("alice").following(&[bob]).build()).await.unwrap();
+    bob.publish_account(AccountBuilder::new("bob").following(&[carol]).build()).await.unwrap();
+    carol.publish_account(AccountBuilder::new("carol").following(&[dave]).build()).await.unwrap();
+    dave.publish_account(account("dave")).await.unwrap();
+    eve.publish_account(account("eve")).await.unwrap();
+
+    let trust = alice.trust_graph().await;
+    assert_eq!(trust.depth(&alice.peer_id), Some(0));
+    assert_eq!(trust.depth(&bob.peer_id), Some(1));
+    assert_eq!(trust.depth(&carol.peer_id), Some(2));
+    assert_eq!(trust.depth(&dave.peer_id), None);
+    assert_eq!(trust.depth(&eve.peer_id), None);
+
+    // Interactions from outside the trust radius are left out
+    let post = alice.post(Post::new("Hello")).await.unwrap();
+    bob.interact(InteractionKind::Like, post.clone()).await.unwrap();
+    carol.post(Post { reply_to: Some(post.clone()), ..Post::new("Reply from carol") }).await.unwrap();
+    dave.interact(InteractionKind::Like, post.clone()).await.unwrap();
+    eve.post(Post { reply_to: Some(post.clone()), ..Post::new("Reply from eve") }).await.unwrap();
+    sleep(Duration::from_secs(1)).await;
+    let actors: BTreeSet<PeerID> = alice.interactions(&post).await.into_iter().map(|(actor, _)| actor).collect();
+    assert_eq!(actors, BTreeSet::from([bob.peer_id.clone(), carol.peer_id.clone()]));
+    let replies = alice.replies(&post).await;
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].0, carol.peer_id);
+    assert_eq!(replies[0].1.text, "Reply from carol");
+
+    // Posts from outside the trust radius are not fetched for the timeline
+    let carol_post = carol.post(Post::new("Carol")).await.unwrap();
+    let eve_post = eve.post(Post::new("Eve")).await.unwrap();
+    bob.post(Post::new("Bob")).await.unwrap();
+    bob.interact(InteractionKind::Reshare, carol_post).await.unwrap();
+    bob.interact(InteractionKind::Reshare, eve_post).await.unwrap();
+    let entries = alice.timeline(None, 10).await.entries;
+    assert_eq!(texts(&entries).into_iter().collect::<BTreeSet<_>>(), BTreeSet::from(["Hello", "Bob", "Carol"]));
+    assert!(!alice.timeline_cache.lock().await.contains_key(&eve.peer_id));
+
+    // Muted accounts are hidden but still extend trust
+    alice.mute(bob.peer_id.clone()).await;
+    let trust = alice.trust_graph().await;
+    assert!(trust.trusts(&bob.peer_id) && !trust.shows(&bob.peer_id));
+    assert!(trust.shows(&carol.peer_id));
+    assert!(alice.timeline(None, 10).await.entries.is_empty());
+    let actors: BTreeSet<PeerID> = alice.interactions(&post).await.into_iter().map(|(actor, _)| actor).collect();
+    assert_eq!(actors, BTreeSet::from([carol.peer_id.clone()]));
+    assert!(alice.unmute(&bob.peer_id).await);
+
+    // Blocked accounts are hidden along with the accounts they brought in
+    alice.block(bob.peer_id.clone()).await;
+    let trust = alice.trust_graph().await;
+    assert!(!trust.trusts(&bob.peer_id) && !trust.trusts(&carol.peer_id));
+    assert!(alice.timeline(None, 10).await.entries.is_empty());
+    assert!(alice.interactions(&post).await.is_empty());
+    assert!(alice.unblock(&bob.peer_id).await);
+    assert_eq!(alice.timeline(None, 10).await.entries.len(), 3);
+}
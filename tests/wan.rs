@@ -0,0 +1,59 @@
+// Copyright (c) 2022  Mubelotix <mubelotix@gmail.com>
+// Program licensed under GNU AGPL v3 or later. See the LICENSE file for details.
+
+mod common;
+use crate::common::*;
+use tewta::prelude::*;
+
+#[tokio::test(start_paused = true)]
+async fn test_wan() {
+    #[cfg(not(feature = "test"))]
+    compile_error!("Test feature required");
+
+    let network = launch_network(100, seed_from_env(), LinkConditions::wan(), honest).await;
+    let nodes = &network.nodes;
+
+    // Wait for network to boot
+    sleep(Duration::from_secs(10)).await;
+
+    for _ in 0..2 {
+        // Update buckets
+        for node in nodes {
+            node.connections.refresh_buckets().await;
+        }
+
+        // Wait for buckets to update
+        sleep(Duration::from_secs(10)).await;
+    }
+
+    // Let nodes ping each other a few times
+    sleep(Duration::from_secs(250)).await;
+
+    // Pings should reflect the latency of links
+    let mut measured = 0;
+    for node in nodes {
+        for peer_id in node.connections.peers().await {
+            if let Some(ping_nanos) = node.connections.ping_nanos(&peer_id).await {
+                assert!(ping_nanos >= 40_000_000, "Ping is lower than the minimum round-trip latency");
+                measured += 1;
+            }
+        }
+    }
+    assert!(measured > 0, "No ping was measured");
+
+    // DHT lookups still work
+    let key = nodes[0].peer_id.to_owned();
+    nodes[0].dht.set(key.clone(), DhtValue {
+        provider: nodes[0].peer_id.clone(),
+        cached_addr: None,
+        account_snapshot_desc: AccountSnapshotDescriptor {
+            timestamp: 0,
+            hash: [0; 32],
+            backup_peer_id: nodes[0].peer_id.clone(),
+            size: 0,
+        }.sign(&nodes[0].rsa_public_key, &nodes[0].rsa_private_key).unwrap(),
+        migration: None,
+        interaction: None,
+    }).await;
+    nodes[42].dht_lookup(key).await.unwrap();
+}